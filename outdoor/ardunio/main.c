@@ -5,15 +5,27 @@
 #define BAUD 9600
 
 #define SERIAL_BUFFER_SIZE  (256)
+#define RECEIVE_BUFFER_SIZE (32)
+
+#define FIRMWARE_VERSION "wind 2.1"
 
 typedef struct {
 	int headIdx;
 	int tailIdx;
+	unsigned int overflows;
 	char buffer[SERIAL_BUFFER_SIZE];
 } T_SerialBuffer;
 
 T_SerialBuffer SerialBuffer;
 
+typedef struct {
+	unsigned char length;
+	unsigned char discard;
+	char buffer[RECEIVE_BUFFER_SIZE];
+} T_ReceiveBuffer;
+
+T_ReceiveBuffer Received;
+
 //----------------------------------------------------------------------------------------------------------------------------------
 void init_serial(void)
 {
@@ -30,6 +42,10 @@ void init_serial(void)
 	UCSR0C = (1<<USBS0) | (3<<UCSZ00);
 
 	SerialBuffer.headIdx = SerialBuffer.tailIdx = 0;
+	SerialBuffer.overflows = 0;
+
+	Received.length = 0;
+	Received.discard = 0;
 }
 
 
@@ -57,6 +73,8 @@ void serial_write(char byte)
 	if (next_idx != SerialBuffer.tailIdx) {
 		SerialBuffer.buffer[idx] = byte;
 		SerialBuffer.headIdx = next_idx;
+	} else {
+		SerialBuffer.overflows++;
 	}
 }
 
@@ -69,6 +87,215 @@ void serial_writeln(char * str)
 	}
 }
 
+/*
+Every line is a frame, <type>:<sequence>:<payload>*<checksum>
+The sequence counts 0 to 255 so the pi can spot lost lines, and the checksum
+is the XOR of all the bytes before the '*' as two hex digits.
+  H - hello with the firmware version, always sequence 0
+  W - wind speed in m/s
+  D - wind vane ADC reading
+  R - running count of rain gauge tips
+  E - diagnostics
+  P - measurement window in seconds, in reply to a P from the pi
+*/
+
+static unsigned char FrameSequence = 0;
+
+//----------------------------------------------------------------------------------------------------------------------------------
+static unsigned char frame_write(const char * str, unsigned char checksum)
+{
+	while (*str != '\0') {
+		checksum ^= *str;
+		serial_write(*str++);
+	}
+	return checksum;
+}
+
+//----------------------------------------------------------------------------------------------------------------------------------
+// Write the digits of value so they end just before end, returns where they start
+char * format_uint(unsigned int value, char * end)
+{
+	*end = '\0';
+	do {
+		*--end = (value % 10) + '0';
+		value /= 10;
+	} while (value > 0);
+	return end;
+}
+
+//----------------------------------------------------------------------------------------------------------------------------------
+void send_frame(char type, const char * payload)
+{
+	static const char hex[] = "0123456789ABCDEF";
+	char buffer[6];
+	unsigned char checksum = 0;
+
+	buffer[0] = type;
+	buffer[1] = ':';
+	buffer[2] = '\0';
+	checksum = frame_write(buffer, checksum);
+	checksum = frame_write(format_uint(FrameSequence++, &buffer[5]), checksum);
+	checksum = frame_write(":", checksum);
+	checksum = frame_write(payload, checksum);
+
+	buffer[0] = '*';
+	buffer[1] = hex[checksum >> 4];
+	buffer[2] = hex[checksum & 0x0F];
+	buffer[3] = '\n';
+	buffer[4] = '\0';
+	serial_writeln(buffer);
+}
+
+//----------------------------------------------------------------------------------------------------------------------------------
+// Send a frame with a number as the payload
+void report_tagged(char tag, unsigned int value)
+{
+	char buffer[6];
+	send_frame(tag, format_uint(value, &buffer[5]));
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+// Report bytes lost because the send buffer was full
+void report_overflows(void)
+{
+	static const char prefix[] = "overflow:";
+	char buffer[sizeof(prefix) + 5];
+
+	if (SerialBuffer.overflows > 0) {
+		const unsigned int overflows = SerialBuffer.overflows;
+		SerialBuffer.overflows = 0;
+
+		int idx = 0;
+		while (prefix[idx] != '\0') {
+			buffer[idx] = prefix[idx];
+			idx++;
+		}
+		char * digits = format_uint(overflows, &buffer[sizeof(buffer) - 1]);
+		while (*digits != '\0') {
+			buffer[idx++] = *digits++;
+		}
+		buffer[idx] = '\0';
+		send_frame('E', buffer);
+	}
+}
+
+/*
+Lines from the pi use the same framing. The only one is P to set the measurement
+window in seconds, 1 to 60, which is answered with a P frame giving the new window.
+*/
+
+typedef struct {
+	unsigned long one_second;
+	unsigned long period;
+} T_Window;
+
+T_Window Window;
+
+void init_window(unsigned long one_second, unsigned int secs)
+{
+	Window.one_second = one_second;
+	Window.period = secs * one_second;
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+// Value of a hex digit, or -1
+static int hex_digit(char ch)
+{
+	if (ch >= '0' && ch <= '9') {
+		return ch - '0';
+	} else if (ch >= 'A' && ch <= 'F') {
+		return ch - 'A' + 10;
+	} else if (ch >= 'a' && ch <= 'f') {
+		return ch - 'a' + 10;
+	}
+	return -1;
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+// Value of up to 3 decimal digits making up the whole string, or 0
+static unsigned int parse_uint(const char * str)
+{
+	unsigned int value = 0;
+	int digits = 0;
+
+	while (*str != '\0') {
+		if (*str < '0' || *str > '9' || ++digits > 3) {
+			return 0;
+		}
+		value = value * 10 + (*str++ - '0');
+	}
+	return value;
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+// Check a line from the pi is a good frame and act on it
+static void handle_line(char * line)
+{
+	unsigned char checksum = 0;
+	char * end = line;
+
+	while (*end != '\0' && *end != '*') {
+		checksum ^= *end++;
+	}
+	if (*end != '*' || hex_digit(end[1]) < 0 || hex_digit(end[2]) < 0 || end[3] != '\0' ||
+	    ((hex_digit(end[1]) << 4) | hex_digit(end[2])) != checksum) {
+		send_frame('E', "bad line");
+		return;
+	}
+	*end = '\0';
+
+	// Skip the type and sequence, commands are rare enough not to need the sequence checked
+	char * payload = line;
+	int colons = 0;
+	while (*payload != '\0' && colons < 2) {
+		if (*payload++ == ':') {
+			colons++;
+		}
+	}
+
+	if (line[0] != 'P' || colons < 2) {
+		send_frame('E', "unknown command");
+		return;
+	}
+
+	const unsigned int secs = parse_uint(payload);
+	if (secs < 1 || secs > 60) {
+		send_frame('E', "bad window");
+		return;
+	}
+	Window.period = secs * Window.one_second;
+	report_tagged('P', secs);
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+// If a byte has been received add it to the line, handling the line when it is complete
+void run_receive(void)
+{
+	if ((UCSR0A & (1<<RXC0))) {
+		const char byte = UDR0;
+
+		if (byte == '\n' || byte == '\r') {
+			if (Received.length > 0 && !Received.discard) {
+				Received.buffer[Received.length] = '\0';
+				handle_line(Received.buffer);
+			}
+			Received.length = 0;
+			Received.discard = 0;
+		} else if (Received.length < RECEIVE_BUFFER_SIZE - 1) {
+			Received.buffer[Received.length++] = byte;
+		} else {
+			// Too long to be a command
+			Received.discard = 1;
+		}
+	}
+}
+
+
 /*
 1 contact closure per 1.25 m/s
 100 mph = 44.704 m/s = 35.76 Hz
@@ -124,9 +351,14 @@ void report_speed(unsigned int speed)
 		}
 		tens /= 10UL;
 	}
-	buffer[idx++] = '\n';
-	buffer[idx++] = '\0';
-	serial_writeln(buffer);
+	buffer[idx] = '\0';
+
+	// Leading spaces aren't needed in a frame
+	idx = 0;
+	while (buffer[idx] == ' ') {
+		idx++;
+	}
+	send_frame('W', &buffer[idx]);
 }
 
 //----------------------------------------------------------------------------------------------------------------------------------
@@ -180,6 +412,93 @@ static int has_rotated(void)
 }
 
 
+/*
+Rain gauge, tipping bucket reed switch between PB4 (pin 12) and ground.
+The running count of tips is reported in R frames, wrapping at 16 bits,
+so the pi loses nothing if a line is lost.
+*/
+
+typedef struct {
+	unsigned int count;
+	unsigned int prev_timer;
+	unsigned long time;
+	unsigned long last_edge;
+	unsigned long debounce_period;
+	unsigned char prev_state;
+} T_RainGauge;
+
+T_RainGauge Rain;
+
+void init_rain_gauge(unsigned long debounce_period)
+{
+	// Input with pull up
+	PORTB |= _BV(PORTB4);
+
+	Rain.count = 0;
+	Rain.prev_timer = TCNT1;
+	Rain.time = 0;
+	Rain.last_edge = 0;
+	Rain.debounce_period = debounce_period;
+	Rain.prev_state = PINB & _BV(PORTB4);
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+void report_rain(void)
+{
+	report_tagged('R', Rain.count);
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+// Called every time round the loops, so timer 1 can't wrap between calls.
+// An edge only counts if the switch has been still for the debounce period.
+static void run_rain_gauge(void)
+{
+	const unsigned int now_timer = TCNT1;
+	Rain.time += (unsigned int)(now_timer - Rain.prev_timer);
+	Rain.prev_timer = now_timer;
+
+	const unsigned char state = PINB & _BV(PORTB4);
+	if (state != Rain.prev_state) {
+		const int settled = (Rain.time - Rain.last_edge) > Rain.debounce_period;
+		Rain.last_edge = Rain.time;
+		Rain.prev_state = state;
+
+		// Pull Up, so zero means contact closed
+		if (state == 0 && settled) {
+			Rain.count++;
+			report_rain();
+		}
+	}
+}
+
+
+/*
+Wind vane, resistor network between ADC0 (pin A0) and ground with a 10k pull up to 5V.
+The raw reading is reported in a D frame just before each speed, the pi looks up the direction.
+*/
+
+void init_vane(void)
+{
+	// AVcc reference, ADC0
+	ADMUX = (1<<REFS0);
+
+	// Enable, 16MHz/128 = 125kHz ADC clock
+	ADCSRA = (1<<ADEN) | (7<<ADPS0);
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+unsigned int read_vane(void)
+{
+	ADCSRA |= (1<<ADSC);
+	while (ADCSRA & (1<<ADSC)) {
+	}
+	return ADC;
+}
+
+
 //----------------------------------------------------------------------------------------------------------------------------------
 int main(void)
 {
@@ -202,9 +521,12 @@ int main(void)
 
 	unsigned long count = 0;
 	unsigned long time = 0;
-	const unsigned long measurement_period = 2 * one_second;
+	init_window(one_second, 2);
 
-	serial_writeln("Start\n");
+	init_rain_gauge(one_second/20);
+	init_vane();
+
+	send_frame('H', FIRMWARE_VERSION);
 
 	while(1) {
 
@@ -216,9 +538,12 @@ int main(void)
 				time = 0;
 				break;
 			}
-			
-			/* empty UART transmit buffer */
+
+			run_rain_gauge();
+
+			/* empty UART transmit buffer, and take commands from the pi */
 			run_serial();
+			run_receive();
 		}
 
 		while(1) {
@@ -232,14 +557,20 @@ int main(void)
 				}
 			}
 
-			if (time > measurement_period) {
+			if (time > Window.period) {
 				break;
 			}
 
-			/* empty UART transmit buffer */
+			run_rain_gauge();
+
+			/* empty UART transmit buffer, and take commands from the pi */
 			run_serial();
+			run_receive();
 		}
 		
+		report_tagged('D', read_vane());
 		report_speed((count + time/2)/time);
+		report_rain();
+		report_overflows();
 	}
 }
@@ -0,0 +1,186 @@
+//!
+//! BH1750 ambient light sensor
+//!
+
+use i2cdev::linux::LinuxI2CDevice;
+use i2cdev::core::*;
+use sensor::Sensor;
+use std::time::Duration;
+use weather_err::Result;
+
+/// ADDR pin low, 0x5C with it high
+pub const BH1750_ADDR : u16 = 0x23;
+
+const POWER_ON : u8 = 0x01;
+const ONE_TIME_HIGH_RES : u8 = 0x20;
+
+// The default measurement time saturates at 54612 lx, well short of full sun at around 100000 lx.
+// The shortest allowed doubles the range at the cost of resolution.
+const DEFAULT_MEASUREMENT_TIME : u8 = 69;
+const MEASUREMENT_TIME : u8 = 31;
+
+// Counts per lx at the default measurement time
+const COUNTS_PER_LUX : f32 = 1.2;
+
+// Luminous efficacy of daylight in lm/W, to estimate irradiance from illuminance. It varies with the sun's
+// elevation and cloud, so this is only an estimate.
+const LUX_PER_WATT : f32 = 126.7;
+
+// Longest a high resolution measurement takes
+const MEASUREMENT_MS : u64 = 180;
+
+const CHANNELS : [(&str, &str); 2] = [("illuminance", "lx"), ("solar", "W/m²")];
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Estimated solar irradiance in W/m² from illuminance in lx
+pub fn irradiance(lux : f32) -> f32 {
+    lux / LUX_PER_WATT
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Bh1750 {
+    dev : LinuxI2CDevice,
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Bh1750 {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(dev_name : &str) -> Self {
+        Self::with_address(dev_name, BH1750_ADDR)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn with_address(dev_name : &str, address : u16) -> Self {
+        Self {
+            dev : match LinuxI2CDevice::new(dev_name, address) {
+                Ok(dev) => dev,
+                Err(error) => panic!("Failed to open {} with address {} - {}", dev_name, address, error)
+            }
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn write(&mut self, data : &[u8]) -> Result<()> {
+        Ok(self.dev.write(data).inspect_err(|_| metrics::I2C_ERRORS.inc())?)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn read(&mut self, data : &mut [u8]) -> Result<()> {
+        Ok(self.dev.read(data).inspect_err(|_| metrics::I2C_ERRORS.inc())?)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn process_resp(resp : &[u8]) -> f32 {
+        let counts = ((resp[0] as u16) << 8) | (resp[1] as u16);
+        counts as f32 / COUNTS_PER_LUX * DEFAULT_MEASUREMENT_TIME as f32 / MEASUREMENT_TIME as f32
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Power up and set the measurement time, the sensor forgets it when powered down
+    pub fn init(&mut self) -> Result<()> {
+        self.write(&[POWER_ON])?;
+        self.write(&[0x40 | (MEASUREMENT_TIME >> 5)])?;
+        self.write(&[0x60 | (MEASUREMENT_TIME & 0x1F)])?;
+        Ok(())
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Start a measurement, it takes up to 180ms and the sensor powers down afterwards
+    pub fn one_shot(&mut self) -> Result<()> {
+        self.write(&[ONE_TIME_HIGH_RES])?;
+        Ok(())
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Illuminance in lx
+    pub fn sample(&mut self) -> Result<f32> {
+        let mut resp : [u8; 2] = [0; 2];
+        self.read(&mut resp)?;
+        Ok(Self::process_resp(&resp))
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// One time measurements of illuminance, with the solar irradiance estimated from it. Call init first.
+impl Sensor for Bh1750 {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_name(&self) -> &str {
+        "bh1750"
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_channels(&self) -> &[(&'static str, &'static str)] {
+        &CHANNELS
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn trigger(&mut self) -> Result<()> {
+        self.one_shot()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_duration(&self) -> Duration {
+        Duration::from_millis(MEASUREMENT_MS)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn read(&mut self, _unix_time : i64) -> Result<Vec<sensor::Channel>> {
+        let lux = self.sample()?;
+        Ok(sensor::channels(&CHANNELS, &[Some(lux as f64), Some(irradiance(lux) as f64)]))
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn check_process_resp() {
+        assert_eq!(Bh1750::process_resp(&[0x00, 0x00]), 0.0);
+        // 1000 counts at the shorter measurement time
+        assert_relative_eq!(Bh1750::process_resp(&[0x03, 0xE8]), 1854.8, max_relative = 0.001);
+        // Saturated
+        assert_relative_eq!(Bh1750::process_resp(&[0xFF, 0xFF]), 121556.0, max_relative = 0.001);
+    }
+
+    #[test]
+    fn check_irradiance() {
+        assert_eq!(irradiance(0.0), 0.0);
+        assert_relative_eq!(irradiance(100000.0), 789.3, max_relative = 0.001);
+    }
+
+    #[test]
+    #[ignore = "needs a BH1750 on /dev/i2c-bh1750"]
+    fn read_illuminance() {
+        let mut sensor = Bh1750::new("/dev/i2c-bh1750");
+
+        sensor.init().unwrap();
+        sensor.one_shot().unwrap();
+        thread::sleep(Duration::from_millis(200));
+        let lux = sensor.sample().unwrap();
+
+        println!("{:.0} lx {:.0} W/m²", lux, irradiance(lux));
+    }
+}
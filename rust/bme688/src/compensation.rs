@@ -0,0 +1,786 @@
+//!
+//! Compensation of the raw ADC readings using the sensor's calibration. There are two ways of doing it, the float
+//! factorisation of the datasheet formulas this crate has always used, and Bosch's integer reference code.
+//!
+
+use std::fs;
+use toml::Table;
+use weather_err::{Result, WeatherError};
+use crate::Summary;
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// The raw calibration parameters, as named in the datasheet
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Calibration {
+    pub par_t1 : u16,
+    pub par_t2 : i16,
+    pub par_t3 : i8,
+
+    pub par_p1 : u16,
+    pub par_p2 : i16,
+    pub par_p3 : i8,
+    pub par_p4 : i16,
+    pub par_p5 : i16,
+    pub par_p6 : i8,
+    pub par_p7 : i8,
+    pub par_p8 : i16,
+    pub par_p9 : i16,
+    pub par_p10 : u8,
+
+    pub par_h1 : u16,
+    pub par_h2 : u16,
+    pub par_h3 : i8,
+    pub par_h4 : i8,
+    pub par_h5 : i8,
+    pub par_h6 : u8,
+    pub par_h7 : i8,
+
+    pub par_g1 : i8,
+    pub par_g2 : i16,
+    pub par_g3 : i8,
+    pub res_heat_range : u8,
+    pub res_heat_val : i8
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// A value from a calibration file, checked it fits
+fn get_param<T : TryFrom<i64>>(table : &Table, name : &str) -> Result<T> {
+    let value = table.get(name).and_then(|value| value.as_integer())
+        .ok_or_else(|| WeatherError::from(format!("No {} in calibration", name)))?;
+    T::try_from(value).map_err(|_| WeatherError::from(format!("Calibration {} of {} is out of range", name, value)))
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Calibration {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn params(&self) -> [(&'static str, i64); 25] {
+        [("par_t1", self.par_t1 as i64), ("par_t2", self.par_t2 as i64), ("par_t3", self.par_t3 as i64),
+         ("par_p1", self.par_p1 as i64), ("par_p2", self.par_p2 as i64), ("par_p3", self.par_p3 as i64),
+         ("par_p4", self.par_p4 as i64), ("par_p5", self.par_p5 as i64), ("par_p6", self.par_p6 as i64),
+         ("par_p7", self.par_p7 as i64), ("par_p8", self.par_p8 as i64), ("par_p9", self.par_p9 as i64),
+         ("par_p10", self.par_p10 as i64),
+         ("par_h1", self.par_h1 as i64), ("par_h2", self.par_h2 as i64), ("par_h3", self.par_h3 as i64),
+         ("par_h4", self.par_h4 as i64), ("par_h5", self.par_h5 as i64), ("par_h6", self.par_h6 as i64),
+         ("par_h7", self.par_h7 as i64),
+         ("par_g1", self.par_g1 as i64), ("par_g2", self.par_g2 as i64), ("par_g3", self.par_g3 as i64),
+         ("res_heat_range", self.res_heat_range as i64), ("res_heat_val", self.res_heat_val as i64)]
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn to_toml(&self) -> String {
+        let mut table = Table::new();
+        for (name, value) in self.params() {
+            table.insert(String::from(name), toml::Value::Integer(value));
+        }
+        table.to_string()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn from_toml(text : &str) -> Result<Self> {
+        let table = text.parse::<Table>().map_err(|error| WeatherError::from(format!("Bad calibration {}", error)))?;
+        Ok(Self {
+            par_t1 : get_param(&table, "par_t1")?,
+            par_t2 : get_param(&table, "par_t2")?,
+            par_t3 : get_param(&table, "par_t3")?,
+            par_p1 : get_param(&table, "par_p1")?,
+            par_p2 : get_param(&table, "par_p2")?,
+            par_p3 : get_param(&table, "par_p3")?,
+            par_p4 : get_param(&table, "par_p4")?,
+            par_p5 : get_param(&table, "par_p5")?,
+            par_p6 : get_param(&table, "par_p6")?,
+            par_p7 : get_param(&table, "par_p7")?,
+            par_p8 : get_param(&table, "par_p8")?,
+            par_p9 : get_param(&table, "par_p9")?,
+            par_p10 : get_param(&table, "par_p10")?,
+            par_h1 : get_param(&table, "par_h1")?,
+            par_h2 : get_param(&table, "par_h2")?,
+            par_h3 : get_param(&table, "par_h3")?,
+            par_h4 : get_param(&table, "par_h4")?,
+            par_h5 : get_param(&table, "par_h5")?,
+            par_h6 : get_param(&table, "par_h6")?,
+            par_h7 : get_param(&table, "par_h7")?,
+            par_g1 : get_param(&table, "par_g1")?,
+            par_g2 : get_param(&table, "par_g2")?,
+            par_g3 : get_param(&table, "par_g3")?,
+            res_heat_range : get_param(&table, "res_heat_range")?,
+            res_heat_val : get_param(&table, "res_heat_val")?
+        })
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn save(&self, file_name : &str) -> Result<()> {
+        fs::write(file_name, self.to_toml())?;
+        Ok(())
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn load(file_name : &str) -> Result<Self> {
+        Self::from_toml(&fs::read_to_string(file_name)?)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// The ADC readings from one data field, before compensation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RawReading {
+    /// 20 bits
+    pub temp_adc : u32,
+    /// 20 bits
+    pub press_adc : u32,
+    pub humd_adc : u16
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compensation {
+    /// This crate's own factorisation of the datasheet's floating point formulas
+    Float,
+    /// Bosch's fixed point reference code
+    Integer
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Compensation {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "float" => Some(Compensation::Float),
+            "integer" => Some(Compensation::Integer),
+            _ => None
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn two_to_pow(exp : i8) -> f64 {
+    2.0_f64.powi(exp as i32)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Coefficients of the float path, worked out once from the calibration
+#[derive(Default)]
+struct FloatCoefficients {
+    // Temperature params
+    par_ta : f64,
+    par_tb : f64,
+    par_tc : f64,
+
+    // Pressure params
+    par_pvar1a : f64,
+    par_pvar1b : f64,
+    par_pvar1c : f64,
+
+    // last know var1 result. var1 is calculated from temperature
+    par_pvar1 : f64,
+
+    par_pvar2a : f64,
+    par_pvar2b : f64,
+    par_pvar2c : f64,
+
+    // last know var2 result. var2 is calculated from temperature
+    par_pvar2 : i64,
+
+    par_pa : f64,
+    par_pb : f64,
+    par_pc : f64,
+    par_pd : f64,
+
+    // Humdity params
+    par_h1 : i32,
+    par_h2 : f64,
+    par_h3 : f64,
+    par_h4 : f64,
+    par_h5 : f64,
+    par_h6 : f64,
+    par_h7 : f64,
+
+    par_hvar3 : i32,
+    par_hvar4 : f64,
+    par_hvar5 : f64,
+
+    // Last measure temperature
+    temperature : f64,
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl FloatCoefficients {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn new(calibration : &Calibration) -> Self {
+        let mut coefficients = Self::default();
+        coefficients.cache_temperature_params(calibration);
+        coefficients.cache_pressure_params1(calibration);
+        coefficients.cache_pressure_params2(calibration);
+        coefficients.cache_pressure_params3(calibration);
+        coefficients.cache_humditiy_params(calibration);
+        coefficients
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    ///
+    /// From the Datasheet...
+    /// var1 = ((temp_adc / 16384) - (par_t1 / 1024)) * par_t2;
+    /// var2 = (((temp_adc / 131072) - (par_t1 / 8192)) * ((temp_adc / 131072) - (par_t1 / 8192))) * (par_t3 * 16);
+    /// t_fine = var1 + var2;
+    /// temp = t_fine / 5120.0
+    ///
+    fn cache_temperature_params(&mut self, calibration : &Calibration) {
+        let par_t1 = calibration.par_t1;
+        let par_t2 = calibration.par_t2;
+        let par_t3 = calibration.par_t3;
+
+        // 16 = 2^4
+        // 131072 = 2^17
+        // 16384 = 2^14
+        // 8192 = 2^13
+        // 1024 = 2^10
+
+        // t_fine = A * (temp_adc)^2 + B * temp_adc + C, find A,B and C
+        // Collecting temp_adc^2, temp_adc and the constant terms from equations above, 
+        // Note: prime versions are 2^30 bigger i.e. A' = A * 2^30
+        // A = (par_t3 * 16) / (131072 * 131072)
+        //   = (par_t3 * 2^4) / (2^17 * 2^17)
+        //   = par_t3 / 2^30
+        // A' = par_t3
+        // B = (par_t2/16384) - 2 * (par_t3 * 16) * (par_t1 / (8192 * 131072))
+        //   = (par_t2 / 2^14) - (2^1 * par_t3 * 2^4 * par_t1 / (2^13 * 2^17))
+        //   = (par_t2 / 2^14) - ((par_t1 * par_t3) / 2^25)
+        // B' = (par_t2 * 2^16) - ((par_t1 * par_t3) * 2^5)
+        // C = - (par_t1 * par_t2) / 1024 + (par_t1 * par_t1 * par_t3 * 16) / (8192 * 8192)
+        //   = - (par_t1 * par_t2) / 2^10 + (par_t1 * par_t1 * par_t3 * 2^4) / (2^13 * 2^13)
+        //   = - (par_t1 * par_t2) / 2^10 + (par_t1^2 * par_t3) / 2^22
+        // C' = - (par_t1 * par_t2) * 2^20 + (par_t1^2 * par_t3) * 2^8
+        let par_t13 = (par_t1 as i32) * (par_t3 as i32);
+        let par_t12 = (par_t1 as i32) * (par_t2 as i32);
+
+        let a = par_t3;
+        let b = (((par_t2 as i32) << 11) - par_t13) << 5;
+        let c = (-((par_t12 as i64) << 12) + (par_t1 as i64) * (par_t13 as i64)) << 8;
+
+        // 2^-30 / 5120 => to convert t_fine to temperature
+        let denom = two_to_pow(-40) * 0.2;
+
+        self.par_ta = (a as f64) * denom;
+        self.par_tb = (b as f64) * denom;
+        self.par_tc = (c as f64) * denom;
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    ///
+    /// From the Datasheet...
+    /// temp = t_fine * 5120
+    /// var1 = (t_fine / 2) - 64000;
+    /// var1a = var1 * var1 * (par_p6 / 131072);
+    /// var1b = var1a + (var1 * par_p5 * 2);
+    /// var2 = (var1b / 4) + (par_p4 * 65536);
+    ///
+    fn cache_pressure_params2(&mut self, calibration : &Calibration) {
+        let par_p4 = calibration.par_p4;
+        let par_p5 = calibration.par_p5;
+        let par_p6 = calibration.par_p6;
+
+        // var2 = (var1b / 2^2) + (par_p4 * 2^16);
+        // var2 = ((var1a + (var1 * par_p5 * 2)) / 2^2) + (par_p4 * 2^16);
+        // var2 = (var1a / 2^2) + (var1 * par_p5 /2) + (par_p4 * 2^16);
+        // var2 = (((var1^2 * par_p6 /2^17 / 2^2) + (var1 * par_p5)/2 + (par_p4 * 2^16);
+        // var2 = (var1^2  * par_p6 / 2^19) + (var1 * par_p5)/2 + (par_p4 * 2^16);
+
+        //         16 = 2^4
+        //        256 = 2^8
+        //       5120 = 5 * 2^10
+        //      16384 = 2^14
+        //      32768 = 2^15
+        //      64000 = 2^9 * 125
+        //      65536 = 2^16
+        //      32000 = 2^8 * 125
+        //     131072 = 2^17
+        //     524288 = 2^19
+        // 2147483648 = 2^31;
+
+        // var2 = A * t_fine^2 + B * t_fine + C
+        // A = 5120^2 * 2^-2 * par_p6 / 2^19
+        //   = 5^2 * 2^20 * 2^-2 * par_p6 / 2^19
+        //   = 5^2 * par_p6 / 2^1
+        // A' = 5^2 * par_p6
+        // B = 5120 * (-64000 * par_p6 / 2^19 + par_p5 / 2 / 2)
+        //   = 5 * 2^10 * (-125 * 2^9 * par_p6 / 2^19 + par_p5 / 2^2)
+        //   = 5 * (-125 * par_p6 + par_p5 * 2^8)
+        // B' = 5 * 2 * (-5^3 * par_p6 + par_p5 *2^8)
+        // C = 64000^2 * par_p6 / 2^19 - 32000 * par_p5 + (par_p4 * 2^16)
+        //   = (125^2 * 2^18 * par_p6 / 2^19) - (2^8 * 125 * par_p5) + (par_p4 * 2^16)
+        //   = (125^2 * par_p6 / 2) - (125 * 2^8 * par_p5) + (par_p4 * 2^16)
+        // C' = (125^2 * par_p6) - (125 * 2^9 *par_p5) + (par_p4 * 2^17)
+
+        let a = 25 * (par_p6 as i16);
+        let b = 10 * ((-125 * (par_p6 as i32)) + ((par_p5 as i32) << 8));
+        let c = (125 * 125 * (par_p6 as i64)) - ((125 * (par_p5 as i64)) << 9) + ((par_p4 as i64) << 17);
+
+        let denom = two_to_pow(-13);
+
+        self.par_pvar2a = (a as f64) * denom;
+        self.par_pvar2b = (b as f64) * denom;
+        self.par_pvar2c = (c as f64) * denom;
+
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    ///
+    /// From the Datasheet...
+    /// t_fine = temp * 5120
+    /// var1 = (t_fine / 2) - 64000;
+    /// var1a = (((par_p3 * var1 * var1) / 16384) + (par_p2 * var1)) / 524288;
+    /// var1b = (1 + (var1a / 32768)) * par_p1;
+    ///
+    fn cache_pressure_params1(&mut self, calibration : &Calibration) {
+        let par_p1 = calibration.par_p1;
+        let par_p2 = calibration.par_p2;
+        let par_p3 = calibration.par_p3;
+
+        // var1b = par_p1 * (1 + var1a / 2^15);
+        // var1b = par_p1 * (1 + ((((par_p3 * var1 * var1) /2^14) + (par_p2 * var1)) / 2^19) /2^15))
+        // var1b = par_p1 * (1 + (par_p3 * var1^2) / 2^48 + ((par_p2 * var1) / 2^34))
+
+
+        // var1 = A * t_fine^2 + B * t_fine + C
+        // A = 5120^2 * par_p1 * par_p3 / 2^2 * 2^48
+        //   = 25 *2^20 * par_p1 * par_p3 / 2^2 * 2^48
+        //   = 25 * par_p1 * par_p3 / 2^30
+        // A' = 25 * par_p1 * par_p3
+        // B = 5120 * par_p1 *(-64000 * par_p3 / 2^48 +  par_p2 / 2^1 / 2^34)
+        //   = 5120 * par_p1 *(-125 * 2^9 * par_p3 / 2^48 +  par_p2 / 2^35)
+        //   = 5 * 12^10 *par_p1 *(-125 * par_p3 / 2^39 + par_p2 / 2^35)
+        //   = 5 * par_p1 * (-125 * par_p3 / 2^29 + par_p2 / 2^25)
+        // B' = 5 * par_p1 *(-125 * par_p3 * 2 + par_p2 * 2^5)
+        // C = par_p1 * (1 + 64000^2 * par_p3 / 2^48 - (64000 * par_p2 / 2^34))
+        //   = par_p1 * (1 + 125^2 * 2^18 * par_p3 / 2^48) - (125 * 2^9 * par_p2 / 2^34)
+        //   = par_p1 * (1 + 125^2 * par_p3 / 2^30) - (125 * par_p2 / 2^25)
+        // C' = par_p1 * (2^30 + (125^2 * par_p3) - (125 * par_p2 * 2^5))
+
+        let a = 25 * (par_p1 as i32) * (par_p3 as i32);
+        let b = (5 * ((par_p1 as i64) * ((-125 * (par_p3 as i64)) + ((par_p2 as i64) << 4)))) << 1;
+        let c = (par_p1 as i64) * ((1 << 30) + (125 * 125 * (par_p3 as i64))  - ((125 * (par_p2 as i64)) << 5));
+
+        // 6250 = 2 * 3125
+        let denom = two_to_pow(-31) * 0.00032;
+
+        self.par_pvar1a = (a as f64) * denom;
+        self.par_pvar1b = (b as f64) * denom;
+        self.par_pvar1c = (c as f64) * denom;
+
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    ///
+    /// From the Datasheet...
+    /// var1 = (par_p9 * press_comp * press_comp) / 2147483648;
+    /// var2 = press_comp * (par_p8 / 32768);
+    /// var3 = (press_comp / 256) * (press_comp / 256) * (press_comp / 256) * (par_p10 / 131072);
+    /// press_comp = press_comp + (var1_p + var2_p + var3_p + (par_p7 * 128)) / 16;
+    ///
+    fn cache_pressure_params3(&mut self, calibration : &Calibration) {
+
+        let par_p7 = calibration.par_p7;
+        let par_p8 = calibration.par_p8;
+        let par_p9 = calibration.par_p9;
+        let par_p10 = calibration.par_p10;
+
+
+        // pressure = A * press_comp^3 + B * press_comp^2 + C * press_comp + D
+        // A = par_p10/(256 * 256 * 256 * 131072 * 16)
+        //   = par_p10 /(2^8 * 2^8 * 2^8 * 2^17 * 2^4)
+        //   = par_p10 / (2^45)
+        // A' = par_p10
+        // B  = par_p9 / (2^31 * 2^4)
+        //   = par_p9 / (2^35)
+        // B' = par_p9 * 3^10
+        // C  = 1 + par_p8 / (2^15 *2^4)
+        //   = 1 + par_p8 / (2^19)
+        // C' = 2^45 + par_p8 * 2^26
+        // D = 128 * par_p7 / (16)
+        //   = par_p7 * 2^3
+        // D' = par_p7 * 2^48
+
+        let a = par_p10;
+        let b = (par_p9 as i32) << 10; 
+        let c = (1_i64 << 45) + ((par_p8 as i64) << 26);
+        let d = (par_p7 as i64) << 48;
+
+        let denom = two_to_pow(-47) * 0.04;
+
+        self.par_pa = (a as f64) * denom;
+        self.par_pb = (b as f64) * denom;
+        self.par_pc = (c as f64) * denom;
+        self.par_pd = (d as f64) * denom;
+
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn cache_humditiy_params(&mut self, calibration : &Calibration) {
+
+        let par_h1 = calibration.par_h1;
+        let par_h2 = calibration.par_h2;
+        let par_h3 = calibration.par_h3;
+        let par_h4 = calibration.par_h4;
+        let par_h5 = calibration.par_h5;
+        let par_h6 = calibration.par_h6;
+        let par_h7 = calibration.par_h7;
+
+        // From the Datasheet...
+        // var1 = humd_adc - ((par_h1 * 16) + ((par_h3 / 2) * temp));
+        // var2 = var1 * ((par_h2 / 262144) * (1 + ((par_h4 / 16384) * temp) + ((par_h5 / 1048576) * temp * temp)));
+        // humd_comp = var2 + ((par_h6 / 16384) + ((par_h7 / 2097152) * temp)) * var2 * var2);
+
+        //   16384 = 2^14
+        //  262144 = 2^18
+        // 1048576 = 2^20
+        // 2097152 = 2^21
+
+        self.par_h1 = (par_h1 as i32) << 4;
+        self.par_h2 = (par_h2 as f64) * two_to_pow(-18);
+        self.par_h3 = (par_h3 as f64) * two_to_pow(-1);
+        self.par_h4 = ((par_h4 as f64) * self.par_h2) * two_to_pow(-14);
+        self.par_h5 = ((par_h5 as f64) * self.par_h2) * two_to_pow(-20);
+        self.par_h6 = (par_h6 as f64) * two_to_pow(-14);
+        self.par_h7 = (par_h7 as f64) * two_to_pow(-21);
+
+        // Becomes...
+        // var1 = humd_adc - (par_h1 + par_h3 * temp);
+        // var2 = var1 * (par_h2 + par_h4 * temp + par_h5 * temp * temp);
+        // humd_comp = var2 + (par_h6 + (par_h7 * temp)) * var2 * var2);
+    }
+
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn temperature(&mut self, adc : u32) -> f64 {
+
+        let adc = adc as f64;
+
+        let temp = self.par_ta * adc * adc + self.par_tb * adc + self.par_tc;
+
+        if self.temperature != temp {
+            self.temperature = temp;
+            self.cache_press_temp_vars();
+            self.cache_humd_temp_vars();
+        }
+
+        temp
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn cache_press_temp_vars(&mut self) {
+
+        let temp = self.temperature;
+        let var2 = self.par_pvar2a  * temp * temp + self.par_pvar2b  * temp + self.par_pvar2c;
+
+        self.par_pvar2 = var2 as i64;
+
+        let var1 = self.par_pvar1a  * temp * temp + self.par_pvar1b  * temp + self.par_pvar1c;
+
+        self.par_pvar1 = var1;
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn cache_humd_temp_vars(&mut self) {
+        let temp = self.temperature;
+
+        // var1 = humd_adc - (par_h1 + par_h3 * temp);
+        // var2 = var1 * (par_h2 + par_h4 * temp + par_h5 * temp * temp);
+        // humd_comp = var2 + (par_h6 + (par_h7 * temp)) * var2 * var2);
+
+        let var3 = self.par_h1 + ((self.par_h3 * temp) as i32);
+        let var4 = self.par_h2 + self.par_h4 * temp + self.par_h5 * temp * temp;
+        let var5 = self.par_h6 + self.par_h7 * temp;
+
+        // Becomes
+        // var1 = humd_adc - var3;
+        // var2 = var1 * var4;
+        // humd_comp = var2 + var5 * var2 * var2);
+        self.par_hvar3 = var3;
+        self.par_hvar4 = var4;
+        self.par_hvar5 = var5;
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// In millibars, needs the temperature first
+    fn pressure(&self, adc : u32) -> f64 {
+
+        let comp = (((1048576 - adc as i32) as i64 - self.par_pvar2) as f64) / self.par_pvar1;
+
+        comp * comp * comp * self.par_pa + comp * comp * self.par_pb + comp * self.par_pc + self.par_pd
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Needs the temperature first
+    fn humidity(&self, adc : u16) -> f64 {
+
+        // Becomes
+        // var1 = humd_adc - var3;
+        // var2 = var1 * var4;
+        // humd_comp = var2 + var5 * var2 * var2);
+        let var1 = (adc as i32 - self.par_hvar3) as f64;
+        let var2 = var1 * self.par_hvar4;
+
+        var2 + self.par_hvar5 * var2 * var2
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Bosch's integer temperature, in hundredths of a degree, and t_fine for the pressure and humidity
+fn integer_temperature(calibration : &Calibration, adc : u32) -> (i32, i32) {
+    let var1 = ((adc as i32) >> 3) - ((calibration.par_t1 as i32) << 1);
+    let var2 = (var1 * calibration.par_t2 as i32) >> 11;
+    let var3 = ((var1 >> 1) * (var1 >> 1)) >> 12;
+    let var3 = (var3 * ((calibration.par_t3 as i32) << 4)) >> 14;
+    let t_fine = var2 + var3;
+    (((t_fine * 5) + 128) >> 8, t_fine)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Bosch's integer pressure in Pa
+fn integer_pressure(calibration : &Calibration, t_fine : i32, adc : u32) -> i32 {
+    let var1 = (t_fine >> 1) - 64000;
+    let var2 = ((((var1 >> 2) * (var1 >> 2)) >> 11) * calibration.par_p6 as i32) >> 2;
+    let var2 = var2 + ((var1 * calibration.par_p5 as i32) << 1);
+    let var2 = (var2 >> 2) + ((calibration.par_p4 as i32) << 16);
+    let var1 = (((((var1 >> 2) * (var1 >> 2)) >> 13) * ((calibration.par_p3 as i32) << 5)) >> 3) +
+               ((calibration.par_p2 as i32 * var1) >> 1);
+    let var1 = var1 >> 18;
+    let var1 = ((32768 + var1) * calibration.par_p1 as i32) >> 15;
+    if var1 == 0 {
+        return 0;
+    }
+    let pressure = 1048576 - adc as i32;
+    let pressure = (pressure - (var2 >> 12)).wrapping_mul(3125);
+    // Avoid overflowing when shifting
+    let pressure = if pressure >= 0x40000000 {
+        (pressure / var1) << 1
+    } else {
+        (pressure << 1) / var1
+    };
+    let var1 = (calibration.par_p9 as i32 * (((pressure >> 3) * (pressure >> 3)) >> 13)) >> 12;
+    let var2 = ((pressure >> 2) * calibration.par_p8 as i32) >> 13;
+    // Bosch multiplies this in 32 bits, which overflows inside the sensor's 300 to 1100 hPa range once par_p10 is over 27,
+    // e.g. reading 1071.77 rather than 1092.2 hPa with a par_p10 of 30. 64 bits gives what the float code does.
+    let var3 = ((((pressure >> 8) as i64).pow(3) * calibration.par_p10 as i64) >> 17) as i32;
+    pressure + ((var1 + var2 + var3 + ((calibration.par_p7 as i32) << 7)) >> 4)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Bosch's integer humidity in thousandths of a percent
+fn integer_humidity(calibration : &Calibration, t_fine : i32, adc : u16) -> i32 {
+    let temp_scaled = ((t_fine * 5) + 128) >> 8;
+    let var1 = (adc as i32 - (calibration.par_h1 as i32 * 16)) -
+               (((temp_scaled * calibration.par_h3 as i32) / 100) >> 1);
+    let var2 = (calibration.par_h2 as i32 *
+                (((temp_scaled * calibration.par_h4 as i32) / 100) +
+                 (((temp_scaled * ((temp_scaled * calibration.par_h5 as i32) / 100)) >> 6) / 100) +
+                 (1 << 14))) >> 10;
+    let var3 = var1 * var2;
+    let var4 = (calibration.par_h6 as i32) << 7;
+    let var4 = (var4 + ((temp_scaled * calibration.par_h7 as i32) / 100)) >> 4;
+    let var5 = ((var3 >> 14) * (var3 >> 14)) >> 10;
+    let var6 = (var4 * var5) >> 1;
+    let humidity = (((var3 + var6) >> 10) * 1000) >> 12;
+    humidity.clamp(0, 100000)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Turns raw readings into a Summary by either path. With a saved calibration and logged raw readings, this
+/// replays what a sensor in the field saw.
+pub struct Compensator {
+    calibration : Calibration,
+    compensation : Compensation,
+    float : FloatCoefficients
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Compensator {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(calibration : Calibration, compensation : Compensation) -> Self {
+        Self {
+            float : FloatCoefficients::new(&calibration),
+            calibration,
+            compensation
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_compensation(&self) -> Compensation {
+        self.compensation
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn set_compensation(&mut self, compensation : Compensation) {
+        self.compensation = compensation;
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Temperature, humidity and pressure in millibars
+    pub fn compensate(&mut self, raw : &RawReading) -> Summary {
+        let RawReading { temp_adc, press_adc, humd_adc } = *raw;
+        match self.compensation {
+            Compensation::Float => {
+                let temp = self.float.temperature(temp_adc);
+                let humd = self.float.humidity(humd_adc).clamp(0.0, 100.0);
+                Summary::new(temp as f32, humd as f32, self.float.pressure(press_adc) as f32)
+            },
+            Compensation::Integer => {
+                let (temp, t_fine) = integer_temperature(&self.calibration, temp_adc);
+                let humd = integer_humidity(&self.calibration, t_fine, humd_adc);
+                let press = integer_pressure(&self.calibration, t_fine, press_adc);
+                Summary::new(temp as f32 / 100.0, humd as f32 / 1000.0, press as f32 / 100.0)
+            }
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    // A typical calibration block
+    fn calibration() -> Calibration {
+        Calibration {
+            par_t1 : 26000, par_t2 : 26500, par_t3 : 3,
+            par_p1 : 36000, par_p2 : -10300, par_p3 : 88, par_p4 : 6500, par_p5 : -90, par_p6 : 30, par_p7 : 40,
+            par_p8 : -2900, par_p9 : -2000, par_p10 : 30,
+            par_h1 : 790, par_h2 : 1000, par_h3 : 0, par_h4 : 45, par_h5 : 20, par_h6 : 120, par_h7 : -100,
+            ..Calibration::default()
+        }
+    }
+
+    // Temperature, pressure and humidity ADC readings, from cold and dry to warm and damp
+    const ADCS : [(u32, u32, u16); 4] = [(430000, 420000, 16000), (480000, 380000, 20000), (500000, 350000, 24000),
+                                          (540000, 330000, 28000)];
+
+    // What calc_temperature, calc_pressure and calc_humidity in Bosch's bme68x.c return for ADCS with calibration(),
+    // built with gcc with BME68X_USE_FPU and without, when they're in hundredths of a degree, Pa and thousandths of a percent
+    const BOSCH_FLOAT : [(f64, f64, f64); 4] = [(4.423, 880.700, 14.176), (20.220, 972.908, 35.523), (26.540, 1035.456, 60.503),
+                                                (39.181, 1092.235, 90.826)];
+    const BOSCH_INTEGER : [(i32, i32, i32); 4] = [(442, 88071, 14174), (2022, 97287, 35511), (2654, 103545, 60489),
+                                                  (3918, 107177, 90812)];
+
+    fn raw(temp_adc : u32, press_adc : u32, humd_adc : u16) -> RawReading {
+        RawReading { temp_adc, press_adc, humd_adc }
+    }
+
+    #[test]
+    fn check_reference() {
+        // Bosch does the float path in 32 bits
+        let mut compensator = Compensator::new(calibration(), Compensation::Float);
+        for ((temp_adc, press_adc, hum_adc), (temp, press, hum)) in ADCS.into_iter().zip(BOSCH_FLOAT) {
+            let summary = compensator.compensate(&raw(temp_adc, press_adc, hum_adc));
+            assert_abs_diff_eq!(summary.get_temperature() as f64, temp, epsilon = 0.001);
+            assert_abs_diff_eq!(summary.get_pressure() as f64, press, epsilon = 0.005);
+            assert_abs_diff_eq!(summary.get_humidity() as f64, hum, epsilon = 0.001);
+        }
+
+        let calibration = calibration();
+        for ((temp_adc, press_adc, hum_adc), (temp, press, hum)) in ADCS.into_iter().zip(BOSCH_INTEGER) {
+            let (calc_temp, t_fine) = integer_temperature(&calibration, temp_adc);
+            assert_eq!(calc_temp, temp);
+            assert_eq!(integer_humidity(&calibration, t_fine, hum_adc), hum);
+            // Bosch's pressure for the last reading has overflowed, this follows the float path instead
+            let expected = if press_adc == ADCS[3].1 { 109225 } else { press };
+            assert_eq!(integer_pressure(&calibration, t_fine, press_adc), expected);
+        }
+    }
+
+    #[test]
+    fn check_paths() {
+        // The integer path loses a little to rounding
+        for (compensation, temp_error, press_error, hum_error) in [(Compensation::Float, 0.001, 0.005, 0.001),
+                                                                   (Compensation::Integer, 0.01, 0.1, 0.05)] {
+            let mut compensator = Compensator::new(calibration(), compensation);
+            for ((temp_adc, press_adc, hum_adc), (temp, press, hum)) in ADCS.into_iter().zip(BOSCH_FLOAT) {
+                let summary = compensator.compensate(&raw(temp_adc, press_adc, hum_adc));
+                assert_abs_diff_eq!(summary.get_temperature() as f64, temp, epsilon = temp_error);
+                assert_abs_diff_eq!(summary.get_pressure() as f64, press, epsilon = press_error);
+                assert_abs_diff_eq!(summary.get_humidity() as f64, hum, epsilon = hum_error);
+            }
+        }
+    }
+
+    #[test]
+    fn check_humidity_limits() {
+        for compensation in [Compensation::Float, Compensation::Integer] {
+            let mut compensator = Compensator::new(calibration(), compensation);
+            assert_eq!(compensator.compensate(&raw(480000, 380000, 0)).get_humidity(), 0.0);
+            assert_eq!(compensator.compensate(&raw(480000, 380000, 40000)).get_humidity(), 100.0);
+        }
+    }
+
+    #[test]
+    fn check_save_and_load() {
+        let calibration = Calibration { par_g1 : -5, par_g2 : -12000, res_heat_range : 1, res_heat_val : 40, ..calibration() };
+        assert_eq!(Calibration::from_toml(&calibration.to_toml()).unwrap(), calibration);
+
+        let file_name = std::env::temp_dir().join(format!("bme688_calibration_{}.toml", std::process::id()));
+        let file_name = file_name.to_str().unwrap();
+        calibration.save(file_name).unwrap();
+        assert_eq!(Calibration::load(file_name).unwrap(), calibration);
+        fs::remove_file(file_name).unwrap();
+
+        let text = calibration.to_toml().replace("par_t3 = 3", "par_t3 = 300");
+        assert_eq!(format!("{:?}", Calibration::from_toml(&text).unwrap_err()), "Calibration par_t3 of 300 is out of range");
+        let text = calibration.to_toml().replace("par_h7 = -100\n", "");
+        assert_eq!(format!("{:?}", Calibration::from_toml(&text).unwrap_err()), "No par_h7 in calibration");
+    }
+
+    #[test]
+    fn check_replay() {
+        // A sensor's dumped calibration and a logged raw reading
+        let calibration = Calibration::from_toml("par_t1 = 26000\npar_t2 = 26500\npar_t3 = 3\n\
+            par_p1 = 36000\npar_p2 = -10300\npar_p3 = 88\npar_p4 = 6500\npar_p5 = -90\npar_p6 = 30\npar_p7 = 40\n\
+            par_p8 = -2900\npar_p9 = -2000\npar_p10 = 30\n\
+            par_h1 = 790\npar_h2 = 1000\npar_h3 = 0\npar_h4 = 45\npar_h5 = 20\npar_h6 = 120\npar_h7 = -100\n\
+            par_g1 = 0\npar_g2 = 0\npar_g3 = 0\nres_heat_range = 0\nres_heat_val = 0\n").unwrap();
+        let mut compensator = Compensator::new(calibration, Compensation::Integer);
+        let summary = compensator.compensate(&raw(480000, 380000, 20000));
+        assert_abs_diff_eq!(summary.get_temperature(), 20.22, epsilon = 0.01);
+        assert_abs_diff_eq!(summary.get_pressure(), 972.87, epsilon = 0.01);
+        assert_abs_diff_eq!(summary.get_humidity(), 35.511, epsilon = 0.001);
+    }
+}
@@ -1,10 +1,144 @@
 use i2cdev::linux::LinuxI2CDevice;
 use i2cdev::core::*;
+use sensor::{Channel, Sensor};
 use std::fmt;
-use weather_err::Result;
+use std::thread;
+use std::time::Duration;
+use weather_err::{Result, WeatherError};
+
+pub mod compensation;
+
+pub use compensation::{Calibration, Compensation, Compensator, RawReading};
+
+/// SDO pin low, 0x77 with it high
+pub const BME688_ADDR : u16 = 0x76;
+
+const CHIP_ID_REG : u8 = 0xD0;
+const VARIANT_ID_REG : u8 = 0xF0;
+const RESET_REG : u8 = 0xE0;
+const CONFIG_REG : u8 = 0x75;
+const CTRL_GAS_0_REG : u8 = 0x70;
+const CTRL_GAS_1_REG : u8 = 0x71;
+const CTRL_HUM_REG : u8 = 0x72;
+const CTRL_MEAS_REG : u8 = 0x74;
+const RES_HEAT_REG : u8 = 0x5A;
+const GAS_WAIT_REG : u8 = 0x64;
+const GAS_WAIT_SHARED_REG : u8 = 0x6E;
+
+// Start of each of the three data fields, they are 0x11 apart
+const FIELD_REG : u8 = 0x1D;
+const FIELD_SIZE : u8 = 0x11;
+const FIELD_COUNT : u8 = 3;
+// Within a field, after the status and measurement index
+const PRESS_OFFSET : usize = 0x02;
+const TEMP_OFFSET : usize = 0x05;
+const HUMD_OFFSET : usize = 0x08;
+// The gas ADC is where the BME680 had 2 more bytes of humidity
+const GAS_OFFSET : usize = 0x0F;
+
+const SLEEP_MODE : u8 = 0x00;
+const FORCED_MODE : u8 = 0x01;
+const PARALLEL_MODE : u8 = 0x02;
+const RUN_GAS : u8 = 0x20;
+
+pub const MAX_HEATER_STEPS : usize = 10;
+
+// The BME680 and BME688 share a chip ID, the variant ID tells them apart
+const CHANNELS : [(&str, &str); 3] = [("temperature", "°C"), ("humidity", "%"), ("pressure", "hPa")];
+
+const CHIP_ID : u8 = 0x61;
+const SOFT_RESET : u8 = 0xB6;
 
-const BME688_ADDR : u16 = 0x76;
 
+//----------------------------------------------------------------------------------------------------------------------------------
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+    Bme680,
+    Bme688
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Variant {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn from_ids(chip_id : u8, variant_id : u8) -> Result<Self> {
+        match (chip_id, variant_id) {
+            (CHIP_ID, 0x00) => Ok(Variant::Bme680),
+            (CHIP_ID, 0x01) => Ok(Variant::Bme688),
+            (CHIP_ID, variant_id) => Err(WeatherError::from(format!("Unknown BME68x variant {:#04x}", variant_id))),
+            // Other Bosch sensors that answer at the same address
+            (0x58, _) => Err(WeatherError::from("Found a BMP280 rather than a BME688")),
+            (0x60, _) => Err(WeatherError::from("Found a BME280 rather than a BME688")),
+            (chip_id, _) =>
+                Err(WeatherError::from(format!("Unexpected chip ID {:#04x}, expected {:#04x} for a BME688", chip_id, CHIP_ID)))
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Variant::Bme680 => write!(f, "BME680"),
+            Variant::Bme688 => write!(f, "BME688")
+        }
+    }
+}
+
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Heater target in C, and how long to hold it as a multiple of the profile's shared duration
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeaterStep {
+    pub temperature : u16,
+    pub multiplier : u8
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Up to 10 heater steps the BME688 cycles through in parallel mode, measuring gas at the end of each
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeaterProfile {
+    steps : Vec<HeaterStep>,
+    shared_ms : u16
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl HeaterProfile {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(steps : &[HeaterStep], shared_ms : u16) -> Result<Self> {
+        if steps.is_empty() || steps.len() > MAX_HEATER_STEPS {
+            return Err(WeatherError::from(format!("Heater profile has {} steps, it needs 1 to {}",
+                                                  steps.len(), MAX_HEATER_STEPS)));
+        }
+        if let Some(step) = steps.iter().find(|step| step.temperature > 400 || step.multiplier == 0) {
+            return Err(WeatherError::from(format!("Heater step {}C x{} should be at most 400C and at least x1",
+                                                  step.temperature, step.multiplier)));
+        }
+        if shared_ms == 0 || shared_ms >= 1923 {
+            return Err(WeatherError::from(format!("Heater duration {}ms should be 1 to 1922", shared_ms)));
+        }
+        Ok(Self { steps : steps.to_vec(), shared_ms })
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_steps(&self) -> &[HeaterStep] {
+        &self.steps
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Turn on gas measurement with nb_conv, the low nibble, set to the number of steps to cycle through
+    fn ctrl_gas_1(&self) -> u8 {
+        RUN_GAS | self.steps.len() as u8
+    }
+}
 
 
 //----------------------------------------------------------------------------------------------------------------------------------
@@ -46,6 +180,100 @@ impl fmt::Display for Summary {
 }
 
 
+//----------------------------------------------------------------------------------------------------------------------------------
+/// A data field from parallel mode
+pub struct Field {
+    /// Heater step the gas was measured at
+    pub gas_index : u8,
+    /// Counts up with each measurement, to put fields in order
+    pub meas_index : u8,
+    pub summary : Summary,
+    /// What the summary was compensated from
+    pub raw : RawReading,
+    /// Gas resistance in ohms, None unless the heater was stable and the reading valid
+    pub gas : Option<f32>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// New data flag and heater step from a field's status byte
+fn parse_field_status(status : u8) -> (bool, u8) {
+    (status & 0x80 != 0, status & 0x0F)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// The ADC readings from a field's registers
+fn parse_raw(field : &[u8]) -> RawReading {
+    // Big-endian, with the 20 bit readings in the top of their third byte
+    let adc_20 = |offset : usize|
+        ((field[offset] as u32) << 12) | ((field[offset + 1] as u32) << 4) | ((field[offset + 2] as u32) >> 4);
+    RawReading {
+        temp_adc : adc_20(TEMP_OFFSET),
+        press_adc : adc_20(PRESS_OFFSET),
+        humd_adc : ((field[HUMD_OFFSET] as u16) << 8) | (field[HUMD_OFFSET + 1] as u16)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// The fields that come after the last one returned, oldest first. The measurement index wraps from 255 to 0, so
+/// fields are put in order by how far they are past the last one rather than by the index itself.
+fn new_fields(mut fields : Vec<Field>, last_meas_index : Option<u8>) -> Vec<Field> {
+    // With nothing returned yet, start before the field the others are all just after
+    let last_meas_index = last_meas_index.or_else(|| fields.iter()
+        .map(|field| field.meas_index)
+        .find(|&index| fields.iter().all(|other| other.meas_index.wrapping_sub(index) < 0x80))
+        .map(|index| index.wrapping_sub(1)));
+    let after = |field : &Field| last_meas_index.map_or(0, |last| field.meas_index.wrapping_sub(last));
+    // Fields stay flagged as new until overwritten, those at or before the last one were returned already
+    fields.retain(|field| (1..0x80).contains(&after(field)));
+    fields.sort_by_key(after);
+    fields
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Heater resistance register value for a target temperature, from the datasheet
+fn calc_res_heat(params : &Calibration, target : u16, ambient : f32) -> u8 {
+    let var1 = params.par_g1 as f64 / 16.0 + 49.0;
+    let var2 = (params.par_g2 as f64 / 32768.0) * 0.0005 + 0.00235;
+    let var3 = params.par_g3 as f64 / 1024.0;
+    let var4 = var1 * (1.0 + var2 * target.min(400) as f64);
+    let var5 = var4 + var3 * ambient as f64;
+    let res_heat = 3.4 * (var5 * (4.0 / (4.0 + params.res_heat_range as f64)) *
+                          (1.0 / (1.0 + params.res_heat_val as f64 * 0.002)) - 25.0);
+    res_heat.clamp(0.0, 255.0) as u8
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Shared heater duration register value, in steps of 0.477ms with a multiplication factor of 1, 4, 16 or 64
+fn calc_gas_wait_shared(duration_ms : u16) -> u8 {
+    let mut duration = duration_ms as u32 * 1000 / 477;
+    let mut factor = 0;
+    while duration > 0x3F && factor < 3 {
+        duration >>= 2;
+        factor += 1;
+    }
+    (duration.min(0x3F) as u8) | (factor << 6)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Gas resistance in ohms from the BME688's ADC and range, None if it isn't valid or the heater wasn't stable
+fn calc_gas_resistance(msb : u8, lsb : u8) -> Option<f32> {
+    if lsb & 0x30 != 0x30 {
+        return None;
+    }
+    let adc = ((msb as u32) << 2) | ((lsb as u32) >> 6);
+    let range = lsb & 0x0F;
+    let var1 = (262144_u32 >> range) as f64;
+    let var2 = 4096.0 + (adc as f64 - 512.0) * 3.0;
+    Some((1_000_000.0 * var1 / var2) as f32)
+}
+
+
 
 //----------------------------------------------------------------------------------------------------------------------------------
 pub struct Bme688 {
@@ -53,47 +281,11 @@ pub struct Bme688 {
     hum_oversampling : u8,
     temp_oversampling : u8,
     pres_oversampling : u8,
-
-    // Temperature params
-    par_ta : f64,
-    par_tb : f64,
-    par_tc : f64,
-
-    // Pressure params
-    par_pvar1a : f64,
-    par_pvar1b : f64,
-    par_pvar1c : f64,
-
-    // last know var1 result. var1 is calculated from temperature
-    par_pvar1 : f64,
-
-    par_pvar2a : f64,
-    par_pvar2b : f64,
-    par_pvar2c : f64,
-
-    // last know var2 result. var2 is calculated from temperature
-    par_pvar2 : i64,
-
-    par_pa : f64,
-    par_pb : f64,
-    par_pc : f64,
-    par_pd : f64,
-
-    // Humdity params
-    par_h1 : i32,
-    par_h2 : f64,
-    par_h3 : f64,
-    par_h4 : f64,
-    par_h5 : f64,
-    par_h6 : f64,
-    par_h7 : f64,
-
-    par_hvar3 : i32,
-    par_hvar4 : f64,
-    par_hvar5 : f64,
-
-    // Last measure temperature
-    temperature : f64,
+    filter : u8,
+    variant : Option<Variant>,
+    // Measurement index of the newest field returned in parallel mode
+    last_meas_index : Option<u8>,
+    compensator : Compensator
 }
 
 
@@ -117,17 +309,45 @@ fn calc_oversampling(reqd : u8) -> u8 {
 }
 
 //----------------------------------------------------------------------------------------------------------------------------------
-fn two_to_pow(exp : i8) -> f64 {
-    return 2.0_f64.powi(exp as i32)
+/// How long a forced mode measurement takes in µs, as Bosch's driver works it out from the oversampling register values
+fn calc_duration_us(hum_oversampling : u8, temp_oversampling : u8, pres_oversampling : u8) -> u32 {
+    let cycles = |oversampling : u8| match oversampling {
+        0 => 0,
+        _ => 1 << (oversampling.min(5) - 1)
+    };
+    let cycles = cycles(hum_oversampling) + cycles(temp_oversampling) + cycles(pres_oversampling);
+    // Switching between temperature, pressure and humidity, the gas measurement and waking up
+    cycles * 1963 + 477 * 4 + 477 * 5 + 1000
+}
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn calc_filter(coefficient : u8) -> u8 {
+    match coefficient {
+        0 => 0,
+        1 => 1,
+        3 => 2,
+        7 => 3,
+        15 => 4,
+        31 => 5,
+        63 => 6,
+        127 => 7,
+        _ => panic!("Invalid IIR filter coefficient {} for BME688, should be 0, 1, 3, 7, 15, 31, 63 or 127", coefficient)
+    }
 }
 
 //----------------------------------------------------------------------------------------------------------------------------------
 impl Bme688 {
 
     pub fn new(dev_name : &str) -> Self {
-        let dev = match LinuxI2CDevice::new(dev_name, BME688_ADDR) {
+        Self::with_address(dev_name, BME688_ADDR)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn with_address(dev_name : &str, address : u16) -> Self {
+        let dev = match LinuxI2CDevice::new(dev_name, address) {
             Ok(dev) => dev,
-            Err(error) => panic!("Failed to open {} with address {} - {}", dev_name, BME688_ADDR, error)
+            Err(error) => panic!("Failed to open {} with address {} - {}", dev_name, address, error)
         };
 
         Self {
@@ -135,24 +355,21 @@ impl Bme688 {
             hum_oversampling : 0,
             temp_oversampling : 0,
             pres_oversampling : 0,
-            par_ta : 0.0, par_tb : 0.0, par_tc : 0.0,
-            par_pvar1a : 0.0, par_pvar1b : 0.0, par_pvar1c : 0.0, par_pvar1: 0.0,
-            par_pvar2a : 0.0, par_pvar2b : 0.0, par_pvar2c: 0.0, par_pvar2: 0,
-            par_pa : 0.0, par_pb : 0.0, par_pc : 0.0, par_pd : 0.0,
-            par_h1 : 0, par_h2 : 0.0, par_h3 : 0.0, par_h4: 0.0, par_h5: 0.0, par_h6: 0.0, par_h7: 0.0,
-            par_hvar3 : 0, par_hvar4 : 0.0, par_hvar5 : 0.0,
-            temperature : 0.0,
+            filter : 0,
+            variant : None,
+            last_meas_index : None,
+            compensator : Compensator::new(Calibration::default(), Compensation::Float)
         }
     }
 
     //------------------------------------------------------------------------------------------------------------------------------
     fn read_u8(&mut self, addr : u8) -> Result<u8> {
-        Ok(self.dev.smbus_read_byte_data(addr)?)
+        Ok(self.dev.smbus_read_byte_data(addr).inspect_err(|_| metrics::I2C_ERRORS.inc())?)
     }
 
     //------------------------------------------------------------------------------------------------------------------------------
     fn write_u8(&mut self, addr :u8, value : u8) -> Result<()> {
-        Ok(self.dev.smbus_write_byte_data(addr, value)?)
+        Ok(self.dev.smbus_write_byte_data(addr, value).inspect_err(|_| metrics::I2C_ERRORS.inc())?)
     }
 
     //------------------------------------------------------------------------------------------------------------------------------
@@ -162,14 +379,18 @@ impl Bme688 {
     }
 
     //------------------------------------------------------------------------------------------------------------------------------
-    fn read_u16_be(&mut self, addr : u8) -> Result<u16> {
-        // big-endian
-        Ok(((self.read_u8(addr)? as u16) << 8) | (self.read_u8(addr + 1)? as u16))
+    /// Consecutive registers in one transfer, the sensor doesn't update them part way through
+    fn read_block(&mut self, addr : u8, length : u8) -> Result<Vec<u8>> {
+        let block = self.dev.smbus_read_i2c_block_data(addr, length).inspect_err(|_| metrics::I2C_ERRORS.inc())?;
+        if block.len() != length as usize {
+            return Err(WeatherError::from(format!("Read {} bytes from {:#x}, expected {}", block.len(), addr, length)));
+        }
+        Ok(block)
     }
 
     //------------------------------------------------------------------------------------------------------------------------------
     fn read_i8(&mut self, addr : u8) -> Result<i8> {
-        Ok(self.dev.smbus_read_byte_data(addr)? as i8)
+        Ok(self.read_u8(addr)? as i8)
     }
 
     //------------------------------------------------------------------------------------------------------------------------------
@@ -179,261 +400,97 @@ impl Bme688 {
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    ///
-    /// From the Datasheet...
-    /// var1 = ((temp_adc / 16384) - (par_t1 / 1024)) * par_t2;
-    /// var2 = (((temp_adc / 131072) - (par_t1 / 8192)) * ((temp_adc / 131072) - (par_t1 / 8192))) * (par_t3 * 16);
-    /// t_fine = var1 + var2;
-    /// temp = t_fine / 5120.0
-    ///
-    fn cache_temperature_params(&mut self) -> Result<()> {
-        let par_t1 = self.read_u16_le(0xE9)?;
-        let par_t2 = self.read_i16_le(0x8A)?;
-        let par_t3 = self.read_i8(0x8C)?;
-
-        // 16 = 2^4
-        // 131072 = 2^17
-        // 16384 = 2^14
-        // 8192 = 2^13
-        // 1024 = 2^10
-
-        // t_fine = A * (temp_adc)^2 + B * temp_adc + C, find A,B and C
-        // Collecting temp_adc^2, temp_adc and the constant terms from equations above, 
-        // Note: prime versions are 2^30 bigger i.e. A' = A * 2^30
-        // A = (par_t3 * 16) / (131072 * 131072)
-        //   = (par_t3 * 2^4) / (2^17 * 2^17)
-        //   = par_t3 / 2^30
-        // A' = par_t3
-        // B = (par_t2/16384) - 2 * (par_t3 * 16) * (par_t1 / (8192 * 131072))
-        //   = (par_t2 / 2^14) - (2^1 * par_t3 * 2^4 * par_t1 / (2^13 * 2^17))
-        //   = (par_t2 / 2^14) - ((par_t1 * par_t3) / 2^25)
-        // B' = (par_t2 * 2^16) - ((par_t1 * par_t3) * 2^5)
-        // C = - (par_t1 * par_t2) / 1024 + (par_t1 * par_t1 * par_t3 * 16) / (8192 * 8192)
-        //   = - (par_t1 * par_t2) / 2^10 + (par_t1 * par_t1 * par_t3 * 2^4) / (2^13 * 2^13)
-        //   = - (par_t1 * par_t2) / 2^10 + (par_t1^2 * par_t3) / 2^22
-        // C' = - (par_t1 * par_t2) * 2^20 + (par_t1^2 * par_t3) * 2^8
-        let par_t13 = (par_t1 as i32) * (par_t3 as i32);
-        let par_t12 = (par_t1 as i32) * (par_t2 as i32);
-
-        let a = par_t3;
-        let b = (((par_t2 as i32) << 11) - par_t13) << 5;
-        let c = (-((par_t12 as i64) << 12) + (par_t1 as i64) * (par_t13 as i64)) << 8;
-
-        // 2^-30 / 5120 => to convert t_fine to temperature
-        let denom = two_to_pow(-40) * 0.2;
-
-        self.par_ta = (a as f64) * denom;
-        self.par_tb = (b as f64) * denom;
-        self.par_tc = (c as f64) * denom;
+    /// Put every register back to its power on value, e.g. after a crash part way through configuring it
+    pub fn soft_reset(&mut self) -> Result<()> {
+        self.write_u8(RESET_REG, SOFT_RESET)?;
+        // Start up takes 2ms
+        thread::sleep(Duration::from_millis(10));
         Ok(())
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    ///
-    /// From the Datasheet...
-    /// temp = t_fine * 5120
-    /// var1 = (t_fine / 2) - 64000;
-    /// var1a = var1 * var1 * (par_p6 / 131072);
-    /// var1b = var1a + (var1 * par_p5 * 2);
-    /// var2 = (var1b / 4) + (par_p4 * 65536);
-    ///
-    fn cache_pressure_params2(&mut self) -> Result <()> {
-        let par_p4 = self.read_i16_le(0x94)?;
-        let par_p5 = self.read_i16_le(0x96)?;
-        let par_p6 = self.read_i8(0x99)?;
-
-        // var2 = (var1b / 2^2) + (par_p4 * 2^16);
-        // var2 = ((var1a + (var1 * par_p5 * 2)) / 2^2) + (par_p4 * 2^16);
-        // var2 = (var1a / 2^2) + (var1 * par_p5 /2) + (par_p4 * 2^16);
-        // var2 = (((var1^2 * par_p6 /2^17 / 2^2) + (var1 * par_p5)/2 + (par_p4 * 2^16);
-        // var2 = (var1^2  * par_p6 / 2^19) + (var1 * par_p5)/2 + (par_p4 * 2^16);
-
-        //         16 = 2^4
-        //        256 = 2^8
-        //       5120 = 5 * 2^10
-        //      16384 = 2^14
-        //      32768 = 2^15
-        //      64000 = 2^9 * 125
-        //      65536 = 2^16
-        //      32000 = 2^8 * 125
-        //     131072 = 2^17
-        //     524288 = 2^19
-        // 2147483648 = 2^31;
-
-        // var2 = A * t_fine^2 + B * t_fine + C
-        // A = 5120^2 * 2^-2 * par_p6 / 2^19
-        //   = 5^2 * 2^20 * 2^-2 * par_p6 / 2^19
-        //   = 5^2 * par_p6 / 2^1
-        // A' = 5^2 * par_p6
-        // B = 5120 * (-64000 * par_p6 / 2^19 + par_p5 / 2 / 2)
-        //   = 5 * 2^10 * (-125 * 2^9 * par_p6 / 2^19 + par_p5 / 2^2)
-        //   = 5 * (-125 * par_p6 + par_p5 * 2^8)
-        // B' = 5 * 2 * (-5^3 * par_p6 + par_p5 *2^8)
-        // C = 64000^2 * par_p6 / 2^19 - 32000 * par_p5 + (par_p4 * 2^16)
-        //   = (125^2 * 2^18 * par_p6 / 2^19) - (2^8 * 125 * par_p5) + (par_p4 * 2^16)
-        //   = (125^2 * par_p6 / 2) - (125 * 2^8 * par_p5) + (par_p4 * 2^16)
-        // C' = (125^2 * par_p6) - (125 * 2^9 *par_p5) + (par_p4 * 2^17)
-
-        let a = 25 * (par_p6 as i16);
-        let b = 10 * ((-125 * (par_p6 as i32)) + ((par_p5 as i32) << 8));
-        let c = (125 * 125 * (par_p6 as i64)) - ((125 * (par_p5 as i64)) << 9) + ((par_p4 as i64) << 17);
-
-        let denom = two_to_pow(-13);
-
-        self.par_pvar2a = (a as f64) * denom;
-        self.par_pvar2b = (b as f64) * denom;
-        self.par_pvar2c = (c as f64) * denom;
-
-//        println!("Pres var2 = {:?} * temp^2 + {:?} * temp + {:?}", self.par_pvar2a, self.par_pvar2b, self.par_pvar2c);
-        Ok(())
+    /// Make sure it's a BME680 or BME688 on the bus, and which
+    pub fn check_chip(&mut self) -> Result<Variant> {
+        let chip_id = self.read_u8(CHIP_ID_REG)?;
+        let variant_id = self.read_u8(VARIANT_ID_REG)?;
+        let variant = Variant::from_ids(chip_id, variant_id)?;
+        self.variant = Some(variant);
+        Ok(variant)
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    ///
-    /// From the Datasheet...
-    /// t_fine = temp * 5120
-    /// var1 = (t_fine / 2) - 64000;
-    /// var1a = (((par_p3 * var1 * var1) / 16384) + (par_p2 * var1)) / 524288;
-    /// var1b = (1 + (var1a / 32768)) * par_p1;
-    ///
-    fn cache_pressure_params1(&mut self) -> Result<()> {
-        let par_p1 = self.read_u16_le(0x8E)?;
-        let par_p2 = self.read_i16_le(0x90)?;
-        let par_p3 = self.read_i8(0x92)?;
-
-        // var1b = par_p1 * (1 + var1a / 2^15);
-        // var1b = par_p1 * (1 + ((((par_p3 * var1 * var1) /2^14) + (par_p2 * var1)) / 2^19) /2^15))
-        // var1b = par_p1 * (1 + (par_p3 * var1^2) / 2^48 + ((par_p2 * var1) / 2^34))
-
-
-        // var1 = A * t_fine^2 + B * t_fine + C
-        // A = 5120^2 * par_p1 * par_p3 / 2^2 * 2^48
-        //   = 25 *2^20 * par_p1 * par_p3 / 2^2 * 2^48
-        //   = 25 * par_p1 * par_p3 / 2^30
-        // A' = 25 * par_p1 * par_p3
-        // B = 5120 * par_p1 *(-64000 * par_p3 / 2^48 +  par_p2 / 2^1 / 2^34)
-        //   = 5120 * par_p1 *(-125 * 2^9 * par_p3 / 2^48 +  par_p2 / 2^35)
-        //   = 5 * 12^10 *par_p1 *(-125 * par_p3 / 2^39 + par_p2 / 2^35)
-        //   = 5 * par_p1 * (-125 * par_p3 / 2^29 + par_p2 / 2^25)
-        // B' = 5 * par_p1 *(-125 * par_p3 * 2 + par_p2 * 2^5)
-        // C = par_p1 * (1 + 64000^2 * par_p3 / 2^48 - (64000 * par_p2 / 2^34))
-        //   = par_p1 * (1 + 125^2 * 2^18 * par_p3 / 2^48) - (125 * 2^9 * par_p2 / 2^34)
-        //   = par_p1 * (1 + 125^2 * par_p3 / 2^30) - (125 * par_p2 / 2^25)
-        // C' = par_p1 * (2^30 + (125^2 * par_p3) - (125 * par_p2 * 2^5))
-
-        let a = 25 * (par_p1 as i32) * (par_p3 as i32);
-        let b = 5 * ((par_p1 as i64) * ((-125 * (par_p3 as i64)) + ((par_p2 as i64) << 4))) << 1;
-        let c = (par_p1 as i64) * ((1 << 30) + (125 * 125 * (par_p3 as i64))  - ((125 * (par_p2 as i64)) << 5));
-
-        // 6250 = 2 * 3125
-        let denom = two_to_pow(-31) * 0.00032;
-
-        self.par_pvar1a = (a as f64) * denom;
-        self.par_pvar1b = (b as f64) * denom;
-        self.par_pvar1c = (c as f64) * denom;
-
-//        println!("Pres var1 = {:?} * temp^2 + {:?} * temp + {:?}", self.par_pvar1a, self.par_pvar1b, self.par_pvar1c);
-        Ok(())
+    /// Soft reset, check the chip and read its calibration
+    pub fn init(&mut self) -> Result<Variant> {
+        self.soft_reset()?;
+        let variant = self.check_chip()?;
+        self.cache_params()?;
+        Ok(variant)
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    ///
-    /// From the Datasheet...
-    /// var1 = (par_p9 * press_comp * press_comp) / 2147483648;
-    /// var2 = press_comp * (par_p8 / 32768);
-    /// var3 = (press_comp / 256) * (press_comp / 256) * (press_comp / 256) * (par_p10 / 131072);
-    /// press_comp = press_comp + (var1_p + var2_p + var3_p + (par_p7 * 128)) / 16;
-    ///
-    fn cache_pressure_params3(&mut self) -> Result<()> {
-
-        let par_p7 = self.read_i8(0x98)?;
-        let par_p8 = self.read_i16_le(0x9C)?;
-        let par_p9 = self.read_i16_le(0x9E)?;
-        let par_p10 = self.read_i8(0xA0)?;
-
-
-        // pressure = A * press_comp^3 + B * press_comp^2 + C * press_comp + D
-        // A = par_p10/(256 * 256 * 256 * 131072 * 16)
-        //   = par_p10 /(2^8 * 2^8 * 2^8 * 2^17 * 2^4)
-        //   = par_p10 / (2^45)
-        // A' = par_p10
-        // B  = par_p9 / (2^31 * 2^4)
-        //   = par_p9 / (2^35)
-        // B' = par_p9 * 3^10
-        // C  = 1 + par_p8 / (2^15 *2^4)
-        //   = 1 + par_p8 / (2^19)
-        // C' = 2^45 + par_p8 * 2^26
-        // D = 128 * par_p7 / (16)
-        //   = par_p7 * 2^3
-        // D' = par_p7 * 2^48
-
-        let a = par_p10;
-        let b = (par_p9 as i32) << 10; 
-        let c = (1_i64 << 45) + ((par_p8 as i64) << 26);
-        let d = (par_p7 as i64) << 48;
-
-        let denom = two_to_pow(-47) * 0.04;
+    pub fn read_calibration(&mut self) -> Result<Calibration> {
+        let tmp = self.read_u8(0xE2)? as u16;
+        Ok(Calibration {
+            par_t1 : self.read_u16_le(0xE9)?,
+            par_t2 : self.read_i16_le(0x8A)?,
+            par_t3 : self.read_i8(0x8C)?,
+
+            par_p1 : self.read_u16_le(0x8E)?,
+            par_p2 : self.read_i16_le(0x90)?,
+            par_p3 : self.read_i8(0x92)?,
+            par_p4 : self.read_i16_le(0x94)?,
+            par_p5 : self.read_i16_le(0x96)?,
+            par_p6 : self.read_i8(0x99)?,
+            par_p7 : self.read_i8(0x98)?,
+            par_p8 : self.read_i16_le(0x9C)?,
+            par_p9 : self.read_i16_le(0x9E)?,
+            par_p10 : self.read_u8(0xA0)?,
+
+            par_h1 : ((self.read_u8(0xE3)? as u16) << 4) | (tmp & 0x0F),
+            par_h2 : ((self.read_u8(0xE1)? as u16) << 4) | (tmp >> 4),
+            par_h3 : self.read_i8(0xE4)?,
+            par_h4 : self.read_i8(0xE5)?,
+            par_h5 : self.read_i8(0xE6)?,
+            par_h6 : self.read_u8(0xE7)?,
+            par_h7 : self.read_i8(0xE8)?,
+
+            par_g1 : self.read_i8(0xED)?,
+            par_g2 : self.read_i16_le(0xEB)?,
+            par_g3 : self.read_i8(0xEE)?,
+            res_heat_range : (self.read_u8(0x02)? >> 4) & 0x03,
+            res_heat_val : self.read_i8(0x00)?
+        })
+    }
 
-        self.par_pa = (a as f64) * denom;
-        self.par_pb = (b as f64) * denom;
-        self.par_pc = (c as f64) * denom;
-        self.par_pd = (d as f64) * denom;
 
-//        println!("Pressure = {:?} * comp^3 + {:?} * comp^2 + {:?} * comp + {:?}", self.par_pa, self.par_pb, self.par_pc, self.par_pd);
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn cache_params(&mut self) -> Result<()>{
+        let calibration = self.read_calibration()?;
+        self.set_calibration(calibration);
         Ok(())
-
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    fn cache_humditiy_params(&mut self) -> Result<()>{
+    pub fn get_calibration(&self) -> &Calibration {
+        self.compensator.get_calibration()
+    }
 
-        let tmp = self.read_u8(0xE2)? as u16;
-        let par_h1 = ((self.read_u8(0xE3)? as u16) << 4) | (tmp & 0x0F);
-        let par_h2 = ((self.read_u8(0xE1)? as u16) << 4) | (tmp >> 4);
-        let par_h3 = self.read_i8(0xE4)?;
-        let par_h4 = self.read_i8(0xE5)?;
-        let par_h5 = self.read_i8(0xE6)?;
-        let par_h6 = self.read_i8(0xE7)?;
-        let par_h7 = self.read_i8(0xE8)?;
-
-        // From the Datasheet...
-        // var1 = humd_adc - ((par_h1 * 16) + ((par_h3 / 2) * temp));
-        // var2 = var1 * ((par_h2 / 262144) * (1 + ((par_h4 / 16384) * temp) + ((par_h5 / 1048576) * temp * temp)));
-        // humd_comp = var2 + ((par_h6 / 16384) + ((par_h7 / 2097152) * temp)) * var2 * var2);
-
-        //   16384 = 2^14
-        //  262144 = 2^18
-        // 1048576 = 2^20
-        // 2097152 = 2^21
-
-        self.par_h1 = (par_h1 as i32) << 4;
-        self.par_h2 = (par_h2 as f64) * two_to_pow(-18);
-        self.par_h3 = (par_h3 as f64) * two_to_pow(-1);
-        self.par_h4 = ((par_h4 as f64) * self.par_h2) * two_to_pow(-14);
-        self.par_h5 = ((par_h5 as f64) * self.par_h2) * two_to_pow(-20);
-        self.par_h6 = (par_h6 as f64) * two_to_pow(-14);
-        self.par_h7 = (par_h7 as f64) * two_to_pow(-21);
-
-        // Becomes...
-        // var1 = humd_adc - (par_h1 + par_h3 * temp);
-        // var2 = var1 * (par_h2 + par_h4 * temp + par_h5 * temp * temp);
-        // humd_comp = var2 + (par_h6 + (par_h7 * temp)) * var2 * var2);
-        Ok(())
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Use a saved calibration rather than the sensor's own
+    pub fn set_calibration(&mut self, calibration : Calibration) {
+        self.compensator = Compensator::new(calibration, self.compensator.get_compensation());
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    pub fn cache_params(&mut self) -> Result<()>{
-        self.cache_temperature_params()?;
-        self.cache_pressure_params1()?;
-        self.cache_pressure_params2()?;
-        self.cache_pressure_params3()?;
-        self.cache_humditiy_params()?;
-        Ok(())
+    /// Which way to turn the ADC readings into values, float unless set
+    pub fn set_compensation(&mut self, compensation : Compensation) {
+        self.compensator.set_compensation(compensation);
     }
 
 
@@ -455,165 +512,190 @@ impl Bme688 {
     }
 
 
-
     //------------------------------------------------------------------------------------------------------------------------------
-    fn read_temp_adc(&mut self, field :u8) -> Result<i32> {
-        let base : u8 = 0x22 + 0x11 * field;
-        // Big-endian!
-        let msb = self.read_u8(base + 0x00)? as u32;
-        let lsb = self.read_u8(base + 0x01)? as u32;
-        let xlsb = self.read_u8(base + 0x02)? as u32;
-
-        let adc = ((msb << 12) | (lsb << 4) | (xlsb >> 4)) as i32;
-//        println!("temp_adc {:?}", adc);
-        Ok(adc)
+    /// IIR filter on temperature and pressure, 0 (off), 1, 3, 7, 15, 31, 63 or 127. Smooths out slams of doors and gusts.
+    pub fn set_filter(&mut self, coefficient : u8) {
+        self.filter = calc_filter(coefficient);
     }
 
 
-    //------------------------------------------------------------------------------------------------------------------------------
-    fn read_press_adc(&mut self, field :u8) -> Result<i32> {
-        let base : u8 = 0x1F + 0x11 * field;
-        // Big-endian!
-        let msb = self.read_u8(base + 0x00)? as u32;
-        let lsb = self.read_u8(base + 0x01)? as u32;
-        let xlsb = self.read_u8(base + 0x02)? as u32;
 
-        let adc = ((msb << 12) | (lsb << 4) | (xlsb >> 4)) as i32;
-//        println!("press_adc {:?}", adc);
-        Ok(adc)
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// All of a data field's registers at once, so they come from the same measurement
+    fn read_field_block(&mut self, field : u8) -> Result<Vec<u8>> {
+        self.read_block(FIELD_REG + FIELD_SIZE * field, FIELD_SIZE)
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    fn read_humd_adc(&mut self, field :u8) -> Result<u16> {
-        let base : u8 = 0x25 + 0x11 * field;
-        // Big-endian!
-        let adc = self.read_u16_be(base)?;
-//        println!("humd_adc {:?}", adc);
-        Ok(adc)
+    /// The ADC readings from a data field, e.g. to log them alongside the calibration
+    pub fn read_raw(&mut self, field : u8) -> Result<RawReading> {
+        Ok(parse_raw(&self.read_field_block(field)?))
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    fn read_temp(&mut self, field :u8) -> Result<f32> {
+    /// Temperature, humidity and pressure from a raw reading, e.g. from read_raw
+    pub fn compensate(&mut self, raw : &RawReading) -> Summary {
+        self.compensator.compensate(raw)
+    }
 
-        let adc = self.read_temp_adc(field)? as f64;
 
-        let temp = self.par_ta * adc * adc + self.par_tb * adc + self.par_tc;
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn read_summary(&mut self, field : u8) -> Result<Summary> {
+        let raw = self.read_raw(field)?;
+        Ok(self.compensate(&raw))
+    }
 
-        if self.temperature != temp {
-            self.temperature = temp;
-            self.cache_press_temp_vars();
-            self.cache_humd_temp_vars();
-        }
 
-        // println!("Temperature is {:.2} C", temp);
-        Ok(temp as f32)
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn sample(&mut self) -> Result<Summary> {
+        self.read_summary(0)
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    fn cache_press_temp_vars(&mut self) {
-
-        let temp = self.temperature;
-        let var2 = self.par_pvar2a  * temp * temp + self.par_pvar2b  * temp + self.par_pvar2c;
+    fn configure(&mut self) -> Result<u8> {
+        // Write Humdity oversampling
 
-        self.par_pvar2 = var2 as i64;
-//        println!("var2 = {:?}", self.par_pvar2);
+        self.write_u8(CTRL_HUM_REG, self.hum_oversampling)?;
 
-        let var1 = self.par_pvar1a  * temp * temp + self.par_pvar1b  * temp + self.par_pvar1c;
+        // Write Pressure & temperature oversampling
+        let tmp = (self.temp_oversampling << 5) | (self.pres_oversampling << 2);
+        self.write_u8(CTRL_MEAS_REG, tmp)?;
 
-        self.par_pvar1 = var1;
-//        println!("var1 = {:?}", self.par_pvar1);
+        self.write_u8(CONFIG_REG, self.filter << 2)?;
+        Ok(tmp)
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    fn cache_humd_temp_vars(&mut self) {
-        let temp = self.temperature;
+    pub fn one_shot(&mut self) -> Result<()> {
+        let tmp = self.configure()?;
+        self.write_u8(CTRL_MEAS_REG, tmp | FORCED_MODE)?;
+        Ok(())
+    }
 
-        // var1 = humd_adc - (par_h1 + par_h3 * temp);
-        // var2 = var1 * (par_h2 + par_h4 * temp + par_h5 * temp * temp);
-        // humd_comp = var2 + (par_h6 + (par_h7 * temp)) * var2 * var2);
 
-        let var3 = self.par_h1 + ((self.par_h3 * temp) as i32);
-        let var4 = self.par_h2 + self.par_h4 * temp + self.par_h5 * temp * temp;
-        let var5 = self.par_h6 + self.par_h7 * temp;
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Measure continuously, cycling through the heater profile. ambient_temp is used to work out the heater settings.
+    /// Only the BME688 has parallel mode, so check_chip or init must have found one.
+    pub fn start_parallel(&mut self, profile : &HeaterProfile, ambient_temp : f32) -> Result<()> {
+        match self.variant {
+            Some(Variant::Bme688) => (),
+            Some(variant) => return Err(WeatherError::from(format!("Parallel mode needs a BME688, found a {}", variant))),
+            None => return Err(WeatherError::from("Check the chip before starting parallel mode"))
+        }
+        let tmp = self.configure()?;
+
+        // Unused steps have no duration and are skipped
+        for step in 0..MAX_HEATER_STEPS {
+            let (res_heat, multiplier) = match profile.steps.get(step) {
+                Some(step) => (calc_res_heat(self.compensator.get_calibration(), step.temperature, ambient_temp), step.multiplier),
+                None => (0, 0)
+            };
+            self.write_u8(RES_HEAT_REG + step as u8, res_heat)?;
+            self.write_u8(GAS_WAIT_REG + step as u8, multiplier)?;
+        }
+        self.write_u8(GAS_WAIT_SHARED_REG, calc_gas_wait_shared(profile.shared_ms))?;
+        self.write_u8(CTRL_GAS_0_REG, 0)?;
+        self.write_u8(CTRL_GAS_1_REG, profile.ctrl_gas_1())?;
 
-        // Becomes
-        // var1 = humd_adc - var3;
-        // var2 = var1 * var4;
-        // humd_comp = var2 + var5 * var2 * var2);
-        self.par_hvar3 = var3;
-        self.par_hvar4 = var4;
-        self.par_hvar5 = var5;
+        self.last_meas_index = None;
+        self.write_u8(CTRL_MEAS_REG, tmp | PARALLEL_MODE)?;
+        Ok(())
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    fn read_press(&mut self, field: u8) -> Result<f32> {
+    /// Back to sleep, e.g. to leave parallel mode
+    pub fn stop(&mut self) -> Result<()> {
+        let mode = self.read_u8(CTRL_MEAS_REG)?;
+        self.write_u8(CTRL_MEAS_REG, (mode & !0x03) | SLEEP_MODE)?;
+        Ok(())
+    }
 
-        let adc = self.read_press_adc(field)?;
 
-        let comp = (((1048576 - adc) as i64 - self.par_pvar2) as f64) / self.par_pvar1;
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// One of the three data fields, None if it has no new data
+    pub fn read_field(&mut self, field : u8) -> Result<Option<Field>> {
+        // Parallel mode keeps writing the fields, a burst read can't mix two measurements
+        let block = self.read_field_block(field)?;
+        let (new_data, gas_index) = parse_field_status(block[0]);
+        if !new_data {
+            return Ok(None);
+        }
+        let raw = parse_raw(&block);
+        let summary = self.compensate(&raw);
+        let gas = calc_gas_resistance(block[GAS_OFFSET], block[GAS_OFFSET + 1]);
+        Ok(Some(Field { gas_index, meas_index : block[1], summary, raw, gas }))
+    }
 
-        let pressure = comp * comp * comp * self.par_pa + comp * comp * self.par_pb + comp * self.par_pc + self.par_pd;
 
-        let pressure = pressure + 28_f64;
-        // println!("Pressure {:.0} millibars", pressure);
-        Ok(pressure as f32)
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Fields with data not returned before, oldest first. Poll at least as often as the profile takes three steps.
+    pub fn read_fields(&mut self) -> Result<Vec<Field>> {
+        let mut fields = Vec::new();
+        for field in 0..FIELD_COUNT {
+            if let Some(field) = self.read_field(field)? {
+                fields.push(field);
+            }
+        }
+        let fields = new_fields(fields, self.last_meas_index);
+        if let Some(newest) = fields.last() {
+            self.last_meas_index = Some(newest.meas_index);
+        }
+        Ok(fields)
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    fn read_humd(&mut self, field: u8) -> Result<f32> {
+    pub fn is_ready(&mut self) -> Result<bool> {
+        let mode = self.read_u8(0x74)? & 0x03;
+        Ok(mode == 0)
+    }
+}
 
-        let adc = self.read_humd_adc(field)? as i32;
 
-        // Becomes
-        // var1 = humd_adc - var3;
-        // var2 = var1 * var4;
-        // humd_comp = var2 + var5 * var2 * var2);
-        let var1 = (adc - self.par_hvar3) as f64;
-        let var2 = var1 * self.par_hvar4;
-        let humdity = var2 + self.par_hvar5 * var2 * var2;
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Forced mode measurements of temperature, humidity and pressure
+impl Sensor for Bme688 {
 
-        // println!("Humdity {:.2}%", humdity);
-        Ok(humdity as f32)
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_name(&self) -> &str {
+        "bme688"
     }
 
+
     //------------------------------------------------------------------------------------------------------------------------------
-    pub fn sample(&mut self) -> Result<Summary> {
-        let temp = self.read_temp(0)?;
-        let press = self.read_press(0)?;
-        let humd = self.read_humd(0)?;
-        Ok(Summary::new(temp, humd, press))
+    fn get_channels(&self) -> &[(&'static str, &'static str)] {
+        &CHANNELS
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    pub fn one_shot(&mut self) -> Result<()> {
-        // Write Humdity oversampling
+    fn trigger(&mut self) -> Result<()> {
+        self.one_shot()
+    }
 
-        self.write_u8(0x72, self.hum_oversampling)?;
 
-        // Write Pressure & temperature oversampling
-        let tmp = (self.temp_oversampling << 5) | (self.pres_oversampling << 2);
-        self.write_u8(0x74, tmp)?;
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_duration(&self) -> Duration {
+        Duration::from_micros(calc_duration_us(self.hum_oversampling, self.temp_oversampling, self.pres_oversampling).into())
+    }
 
-        self.write_u8(0x74, tmp | 1)?;
-        Ok(())
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn is_ready(&mut self) -> Result<bool> {
+        Bme688::is_ready(self)
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    pub fn is_ready(&mut self) -> Result<bool> {
-        let mode = self.read_u8(0x74)? & 0x03;
-        Ok(if mode == 0 {
-            true
-        } else {
-            false
-        })
+    fn read(&mut self, _unix_time : i64) -> Result<Vec<Channel>> {
+        let summary = self.sample()?;
+        Ok(sensor::channels(&CHANNELS, &[Some(summary.get_temperature() as f64), Some(summary.get_humidity() as f64),
+                                         Some(summary.get_pressure() as f64)]))
     }
 }
 
@@ -621,14 +703,101 @@ impl Bme688 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::thread;
-    use std::time::Duration;
 
     #[test]
+    fn check_variant() {
+        assert_eq!(Variant::from_ids(0x61, 0x00).unwrap(), Variant::Bme680);
+        assert_eq!(Variant::from_ids(0x61, 0x01).unwrap(), Variant::Bme688);
+        assert_eq!(format!("{}", Variant::Bme688), "BME688");
+        assert_eq!(format!("{:?}", Variant::from_ids(0x61, 0x02).unwrap_err()), "Unknown BME68x variant 0x02");
+        assert_eq!(format!("{:?}", Variant::from_ids(0x60, 0x00).unwrap_err()), "Found a BME280 rather than a BME688");
+        assert_eq!(format!("{:?}", Variant::from_ids(0xFF, 0xFF).unwrap_err()),
+                   "Unexpected chip ID 0xff, expected 0x61 for a BME688");
+    }
+
+    #[test]
+    fn check_duration() {
+        // 2x humidity, 8x temperature and 4x pressure
+        assert_eq!(calc_duration_us(calc_oversampling(2), calc_oversampling(8), calc_oversampling(4)), 14 * 1963 + 5293);
+        assert_eq!(calc_duration_us(0, 0, 0), 5293);
+        assert_eq!(calc_duration_us(5, 5, 5), 48 * 1963 + 5293);
+    }
+
+    #[test]
+    fn check_heater() {
+        let params = Calibration::default();
+        assert_eq!(calc_res_heat(&params, 300, 25.0), 199);
+        assert_eq!(calc_gas_wait_shared(140), 0x92);
+        assert_eq!(calc_gas_wait_shared(1), 2);
+
+        assert!(HeaterProfile::new(&[], 140).is_err());
+        assert!(HeaterProfile::new(&[HeaterStep { temperature : 450, multiplier : 1 }], 140).is_err());
+        assert!(HeaterProfile::new(&[HeaterStep { temperature : 320, multiplier : 5 }], 2000).is_err());
+        let steps = [HeaterStep { temperature : 320, multiplier : 5 }; 10];
+        let profile = HeaterProfile::new(&steps, 140).unwrap();
+        assert_eq!(profile.get_steps().len(), 10);
+        assert_eq!(profile.ctrl_gas_1(), 0x2A);
+        assert_eq!(HeaterProfile::new(&steps[..3], 140).unwrap().ctrl_gas_1(), 0x23);
+        assert!(HeaterProfile::new(&[HeaterStep { temperature : 320, multiplier : 5 }; 11], 140).is_err());
+    }
+
+    #[test]
+    fn check_gas() {
+        assert_eq!(parse_field_status(0xA3), (true, 3));
+        assert_eq!(parse_field_status(0x20), (false, 0));
+
+        // ADC of 512 at range 0, valid and stable
+        assert_eq!(calc_gas_resistance(0x80, 0x30), Some(64_000_000.0));
+        assert_eq!(calc_gas_resistance(0x80, 0x35), Some(2_000_000.0));
+        // Not stable, not valid
+        assert!(calc_gas_resistance(0x80, 0x20).is_none());
+        assert!(calc_gas_resistance(0x80, 0x10).is_none());
+    }
+
+    #[test]
+    fn check_parse_raw() {
+        let mut field = [0u8; FIELD_SIZE as usize];
+        field[PRESS_OFFSET..PRESS_OFFSET + 3].copy_from_slice(&[0x4A, 0x3B, 0x70]);
+        field[TEMP_OFFSET..TEMP_OFFSET + 3].copy_from_slice(&[0x7D, 0x1A, 0x90]);
+        field[HUMD_OFFSET..HUMD_OFFSET + 2].copy_from_slice(&[0x5B, 0x3C]);
+        assert_eq!(parse_raw(&field), RawReading { temp_adc : 0x7D1A9, press_adc : 0x4A3B7, humd_adc : 0x5B3C });
+    }
+
+    #[test]
+    fn check_new_fields() {
+        let fields = |indices : &[u8]| indices.iter().map(|&meas_index| Field {
+            gas_index : 0, meas_index, summary : Summary::new(20.0, 50.0, 1000.0),
+            raw : RawReading { temp_adc : 0, press_adc : 0, humd_adc : 0 }, gas : None
+        }).collect::<Vec<_>>();
+        let indices = |fields : Vec<Field>| fields.iter().map(|field| field.meas_index).collect::<Vec<_>>();
+
+        assert_eq!(indices(new_fields(fields(&[12, 10, 11]), None)), [10, 11, 12]);
+        assert_eq!(indices(new_fields(fields(&[12, 13, 11]), Some(12))), [13]);
+        // Across the wrap a new field 0 comes after 255
+        assert_eq!(indices(new_fields(fields(&[0, 254, 255]), Some(254))), [255, 0]);
+        assert_eq!(indices(new_fields(fields(&[1, 0, 255]), None)), [255, 0, 1]);
+        assert!(new_fields(fields(&[]), None).is_empty());
+    }
+
+    #[test]
+    fn check_filter() {
+        assert_eq!(calc_filter(0), 0);
+        assert_eq!(calc_filter(3), 2);
+        assert_eq!(calc_filter(127), 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid IIR filter coefficient 4")]
+    fn check_bad_filter() {
+        calc_filter(4);
+    }
+
+    #[test]
+    #[ignore = "needs a BME688 on /dev/i2c-bme688"]
     fn read_temperature() {
         let mut sensor = Bme688::new("/dev/i2c-bme688");
 
-        sensor.cache_params().unwrap();
+        println!("{}", sensor.init().unwrap());
 
         sensor.set_humdity_oversampling(16);
         sensor.set_pressure_oversampling(16);
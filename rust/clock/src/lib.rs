@@ -1,4 +1,4 @@
-use chrono::{Utc, Timelike};
+use chrono::{DateTime, Utc, Timelike};
 
 //----------------------------------------------------------------------------------------------------------------------------------
 pub struct Clock {
@@ -20,21 +20,26 @@ impl Clock {
 
     //------------------------------------------------------------------------------------------------------------------------------
     pub fn get_nearest_tick(&self) -> i64 {
-        let now = Utc::now();
-        let secs = (now.second() + 60 * now.minute()) as u32;
-        let mut secs_adj = secs % self.period_in_secs;
-        if secs_adj > (self.period_in_secs/2) {
-            secs_adj -= self.period_in_secs;
+        self.nearest_tick(Utc::now())
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Signed, rounding up to the next tick takes a period off the remainder
+    fn nearest_tick(&self, now : DateTime<Utc>) -> i64 {
+        let secs = now.second() + 60 * now.minute();
+        let mut secs_adj = (secs % self.period_in_secs) as i64;
+        if secs_adj > (self.period_in_secs/2) as i64 {
+            secs_adj -= self.period_in_secs as i64;
         }
-        now.timestamp() - (secs_adj as i64)
+        now.timestamp() - secs_adj
     }
 
     //------------------------------------------------------------------------------------------------------------------------------
     pub fn secs_to_next_tick(&self) -> u32 {
         let now = chrono::Utc::now();
 
-        let secs = (now.second() + 60 * now.minute()) as u32;
-        let delay = (self.period_in_secs - secs % self.period_in_secs) as u32;
+        let secs = now.second() + 60 * now.minute();
+        let delay = self.period_in_secs - secs % self.period_in_secs;
         println!("Duration {}", delay);
         delay
     }
@@ -44,9 +49,23 @@ impl Clock {
 //----------------------------------------------------------------------------------------------------------------------------------
 #[cfg(test)]
 mod tests {
-    use chrono::{DateTime, Utc, Datelike};
+    use chrono::{Datelike, TimeZone};
     use super::*;
 
+    #[test]
+    fn check_nearest_tick_rounds_up() {
+        let ticker = Clock::new(60*15);
+
+        // Past half a period the remainder goes negative
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 10, 52, 31).unwrap();
+        let tick = Utc.with_ymd_and_hms(2024, 3, 1, 11, 0, 0).unwrap();
+        assert_eq!(ticker.nearest_tick(now), tick.timestamp());
+
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 10, 37, 30).unwrap();
+        let tick = Utc.with_ymd_and_hms(2024, 3, 1, 10, 30, 0).unwrap();
+        assert_eq!(ticker.nearest_tick(now), tick.timestamp());
+    }
+
     #[test]
     fn check_nearest_tick() {
         let ticker = Clock::new(60*15);
@@ -54,21 +73,27 @@ mod tests {
         let now = Utc::now();
         let unix_time = ticker.get_nearest_tick();
 
+        // Exactly half way rounds down, after 52:30 it's the next hour
+        let secs = 60 * now.minute() + now.second();
+        let hour = if secs > 3150 { now + chrono::Duration::minutes(10) } else { now };
+
         let now_test = DateTime::from_timestamp(unix_time, 0).expect("invalid timestamp");
-        assert_eq!(now.year_ce(), now_test.year_ce());
-        assert_eq!(now.month(), now_test.month());
-        assert_eq!(now.day(), now_test.day());
-        assert_eq!(now.hour(), now_test.hour());
+        assert_eq!(hour.year_ce(), now_test.year_ce());
+        assert_eq!(hour.month(), now_test.month());
+        assert_eq!(hour.day(), now_test.day());
+        assert_eq!(hour.hour(), now_test.hour());
         assert_eq!(0, now_test.second());
 
-        if now.minute() < 8 {
+        if secs <= 450 {
             assert_eq!(now_test.minute(), 0)
-        } else if now.minute() < 23 {
+        } else if secs <= 1350 {
             assert_eq!(now_test.minute(), 15)
-        } else if now.minute() < 38 {
+        } else if secs <= 2250 {
             assert_eq!(now_test.minute(), 30)
-        } else {
+        } else if secs <= 3150 {
             assert_eq!(now_test.minute(), 45)
+        } else {
+            assert_eq!(now_test.minute(), 0)
         }
     }
 
@@ -1,12 +1,8 @@
-use std::sync::Arc;
 use std::time::Duration;
 use std::thread;
 
 use crate::sensor::Sensor;
 
-use clock;
-use config;
-
 mod sensor;
 
 
@@ -18,22 +14,55 @@ fn wait_tick(ticker : &clock::Clock) -> Result<(), ()> {
 }
 
 
+//----------------------------------------------------------------------------------------------------------------------------------
+fn create_pusher(config : &config::Config) -> Option<influx::Pusher> {
+    match influx::Pusher::from_config(config)? {
+        Ok(pusher) => Some(pusher),
+        Err(error) => panic!("Bad influx config {:?}", error)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Failing to push is not fatal, the cursor means the rows will go next time
+fn push_to_influx(config : &config::Config, pusher : &Option<influx::Pusher>) {
+    if let Some(pusher) = pusher {
+        for name in config.get_nodes() {
+            let (db_file, db_table) = config.get_database(name);
+            match pusher.push(name, db_file, db_table) {
+                Ok(pushed) => println!("Pushed {} {} rows to influx", pushed, name),
+                Err(error) => println!("Failed to push {} to influx {:?}", name, error)
+            }
+        }
+    }
+}
+
+
 //----------------------------------------------------------------------------------------------------------------------------------
 fn main() {
     let config = config::Config::new();
 
-    let indoor_sensor = Sensor::new(&config, "indoor").unwrap();
-    let outdoor_sensor = Sensor::new(&config, "outdoor").unwrap();
+    if let Some(port) = config.get_metrics_port("collector") {
+        metrics::Exporter::new(port).start();
+    }
 
-    indoor_sensor.collect().unwrap();
-    outdoor_sensor.collect().unwrap();
+    let sensors : Vec<_> = config.get_nodes().into_iter().map(|name| Sensor::new(&config, name).unwrap()).collect();
+
+    let pusher = create_pusher(&config);
+
+    for sensor in &sensors {
+        sensor.collect().unwrap();
+    }
+    push_to_influx(&config, &pusher);
 
     let ticker = clock::Clock::new(config.get_sample_period() * 60);
 
     loop {
         wait_tick(&ticker).unwrap();
         println!("Tick");
-        indoor_sensor.collect().unwrap();
-        outdoor_sensor.collect().unwrap();
+        for sensor in &sensors {
+            sensor.collect().unwrap();
+        }
+        push_to_influx(&config, &pusher);
     }
 }
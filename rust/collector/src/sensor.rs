@@ -8,12 +8,11 @@ use std::net::TcpStream;
 use std::io::{BufReader, BufRead, Write};
 use weather_err::{Result, WeatherError};
 
-use crate::config;
-
 type Connection = Mutex<sqlite::Connection>;
 
 //----------------------------------------------------------------------------------------------------------------------------------
 pub struct Sensor {
+    name : String,
     address : SocketAddr,
     columns : Vec<String>,
     db_connection : Connection,
@@ -26,14 +25,15 @@ impl Sensor {
 
     //------------------------------------------------------------------------------------------------------------------------------
     pub fn new(config : &config::Config, name : &str) -> Result<Self> {
-        let address = match Self::get_address(&config, &name) {
+        let address = match Self::get_address(config, name) {
             Some(address) => address,
             None => return Err(WeatherError::from("No IP address"))
         };
         let columns = Self::get_column_names(&address).unwrap();
-        let (db_connection, db_table) = Self::create_db_connection(&config, &columns, &name)?;
+        let (db_connection, db_table) = Self::create_db_connection(config, &columns, name)?;
         let last_collected_time = Self::get_last_time(&db_connection, &db_table)?;
         Ok(Self {
+            name : String::from(name),
             address,
             columns,
             db_connection,
@@ -44,10 +44,7 @@ impl Sensor {
 
     //------------------------------------------------------------------------------------------------------------------------------
     fn get_address(config : &config::Config, name : &str) -> Option<SocketAddr> {
-        let host = match config.get_host(&name) {
-            Some(host) => host,
-            None => return None
-        };
+        let host = config.get_host(name)?;
         let port = config.get_port();
 
         let mut addrs_iter = match format!("{}:{}", host, port).to_socket_addrs() {
@@ -74,7 +71,7 @@ impl Sensor {
         let mut stream_in = BufReader::new(&socket);
         let mut stream_out = &socket;
 
-        stream_out.write_all(b"columns\n");
+        stream_out.write_all(b"columns\n")?;
 
         let mut columns = Vec::<String>::new();
 
@@ -85,18 +82,18 @@ impl Sensor {
                 break;
             }
             line = String::from(line.trim());
-            if line == "" {
+            if line.is_empty() {
                 break;
             }
             columns.push(line);
         }
         println!("{:?}", columns);
-        return Ok(columns);
+        Ok(columns)
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    fn create_db_connection(config : &config::Config, columns : &Vec<String>, name : &str)-> Result<(Connection, String)> {
+    fn create_db_connection(config : &config::Config, columns : &[String], name : &str)-> Result<(Connection, String)> {
 
         let (db_file, db_table) = config.get_database(name);
         println!("Opening database {}", db_file);
@@ -105,15 +102,10 @@ impl Sensor {
 
         println!("Creating/using db table {}", db_table);
 
-        let mut query = String::from(format!("CREATE TABLE IF NOT EXISTS {} (unix_time INT NOT NULL", db_table));
-        for col in columns {
-            query.push_str(format!(", {} REAL", col).as_str());
-        }
-        query.push_str(", PRIMARY KEY(unix_time));");
-
+        let columns : Vec<&str> = columns.iter().map(|col| col.as_str()).collect();
         {
             let conn = db_connection.lock().expect("Unexpected failure to lock mutex");
-            (*conn).execute(query)?;
+            database::create_table(&conn, db_table, &columns)?;
         }
 
         Ok((db_connection, String::from(db_table)))
@@ -131,10 +123,7 @@ impl Sensor {
         // Should only be one row!
         let row = statement.into_iter().next().unwrap()?;
         // println!("{:?}", row);
-        let last_collected_time = match row.try_read::<i64, _>("MAX(unix_time)") {
-            Ok(value) => value,
-            Err(..) => 0
-        };
+        let last_collected_time = row.try_read::<i64, _>("MAX(unix_time)").unwrap_or_default();
         //println!("last_collected_time = {}", last_collected_time);
         Ok(last_collected_time)
     }
@@ -143,17 +132,21 @@ impl Sensor {
     //------------------------------------------------------------------------------------------------------------------------------
     fn insert(&self, unix_time : i64, values : &HashMap::<String, f32>) {
 
-        if values.len() == 0 {
+        if values.is_empty() {
             return;
         }
-        let mut query = format!("INSERT INTO {} VALUES ({}", self.db_table, unix_time);
+        // Columns the node didn't send are NULL
+        let mut row = Vec::new();
         for col in &self.columns {
-            query.push_str(format!(", {}", values[col]).as_str());
+            let value = values.get(col).map(|value| *value as f64);
+            if let Some(value) = value {
+                metrics::COLLECTED.set_labelled(&[&self.name, col], value);
+            }
+            row.push((col.as_str(), value));
         }
-        query.push_str(");");
         {
             let conn = self.db_connection.lock().expect("Unexpected failure to lock mutex");
-            (*conn).execute(query).unwrap();
+            database::insert(&conn, &self.db_table, unix_time, &row).unwrap();
         }
         {
             let mut time = self.last_collected_time.lock().expect("Unexpected failure to lock mutex");
@@ -174,7 +167,7 @@ impl Sensor {
             (*time) + 1
         };
 
-        stream_out.write_all(format!("{}\n", next_time).as_bytes());
+        stream_out.write_all(format!("{}\n", next_time).as_bytes())?;
 
         let mut values = HashMap::new();
         let mut line = String::new();
@@ -186,7 +179,7 @@ impl Sensor {
                 break;
             }
             line = String::from(line.trim());
-            if line == "" {
+            if line.is_empty() {
                 break;
             }
             let mut tokens = line.split("=");
@@ -7,10 +7,72 @@ pub struct Config {
 }
 
 
+//----------------------------------------------------------------------------------------------------------------------------------
+/// One of the sensors a station node has, from its sensors list. Keys other than type, dev and address are options
+/// for that type of sensor.
+pub struct SensorConfig {
+    pub kind : String,
+    pub dev : String,
+    pub address : Option<u16>,
+    pub options : Table
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl SensorConfig {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn from_value(value : &toml::Value) -> Self {
+        let mut options = value.as_table().expect("sensors entries must be tables").clone();
+        let kind = match options.remove("type").as_ref().and_then(|kind| kind.as_str()) {
+            Some(kind) => kind.to_string(),
+            None => panic!("sensors entries need a type")
+        };
+        let dev = match options.remove("dev").as_ref().and_then(|dev| dev.as_str()) {
+            Some(dev) => dev.to_string(),
+            None => panic!("No dev for {} sensor", kind)
+        };
+        let address = options.remove("address").map(|address| match address.as_integer() {
+            Some(address) if (0..0x80).contains(&address) => address as u16,
+            _ => panic!("address of {} sensor must be a 7 bit I2C address", kind)
+        });
+        Self { kind, dev, address, options }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_str(&self, key : &str) -> Option<&str> {
+        self.options.get(key).map(|value| match value.as_str() {
+            Some(value) => value,
+            None => panic!("{} of {} sensor must be a string", key, self.kind)
+        })
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_integer(&self, key : &str) -> Option<i64> {
+        self.options.get(key).map(|value| match value.as_integer() {
+            Some(value) => value,
+            None => panic!("{} of {} sensor must be a whole number", key, self.kind)
+        })
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_f32(&self, key : &str) -> Option<f32> {
+        self.options.get(key).map(|value| match value.as_float().or(value.as_integer().map(|value| value as f64)) {
+            Some(value) => value as f32,
+            None => panic!("{} of {} sensor must be a number", key, self.kind)
+        })
+    }
+}
+
+
 //----------------------------------------------------------------------------------------------------------------------------------
 impl Config {
 
     //------------------------------------------------------------------------------------------------------------------------------
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         let path = Path::new("weather.toml");
         let config_str = match std::fs::read_to_string(path) {
@@ -63,6 +125,158 @@ impl Config {
 
 
 
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_optional(&self, name : &str, key : &str) -> Option<&toml::Value> {
+        self.config.get(name)?.get(key)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// The metrics exporter is optional, so no port means don't start it
+    pub fn get_metrics_port(&self, name : &str) -> Option<u16> {
+        self.get_optional(name, "metrics_port")?.as_integer().map(|port| port as u16)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// No mqtt section or host means don't publish
+    pub fn get_mqtt_broker(&self) -> Option<(&str, u16)> {
+        let host = self.get_optional("mqtt", "host")?.as_str()?;
+        let port = match self.get_optional("mqtt", "port") {
+            Some(port) => port.as_integer().expect("mqtt port must be an integer") as u16,
+            None => 1883
+        };
+        Some((host, port))
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_mqtt_topic_prefix(&self) -> &str {
+        self.get_optional("mqtt", "topic_prefix").and_then(|prefix| prefix.as_str()).unwrap_or("weather")
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_mqtt_discovery_prefix(&self) -> &str {
+        self.get_optional("mqtt", "discovery_prefix").and_then(|prefix| prefix.as_str()).unwrap_or("homeassistant")
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_mqtt_credentials(&self) -> Option<(&str, &str)> {
+        let username = self.get_optional("mqtt", "username")?.as_str()?;
+        let password = self.get_optional("mqtt", "password").and_then(|password| password.as_str()).unwrap_or("");
+        Some((username, password))
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Number of messages held while the broker is unreachable
+    pub fn get_mqtt_queue_size(&self) -> usize {
+        self.get_optional("mqtt", "queue_size").and_then(|size| size.as_integer()).unwrap_or(1000) as usize
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Identifies this weather station when data leaves it
+    pub fn get_station_name(&self) -> &str {
+        self.get_optional("common", "station").and_then(|name| name.as_str()).unwrap_or("weather_station")
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// The InfluxDB write endpoint, None means don't push
+    pub fn get_influx_url(&self) -> Option<&str> {
+        self.get_optional("influx", "url")?.as_str()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_influx_token(&self) -> Option<&str> {
+        self.get_optional("influx", "token")?.as_str()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// File holding the unix time of the last row pushed for each node
+    pub fn get_influx_cursor_file(&self) -> &str {
+        self.get_optional("influx", "cursor_file").and_then(|file| file.as_str()).unwrap_or("influx_cursor.toml")
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_influx_batch_size(&self) -> usize {
+        self.get_optional("influx", "batch_size").and_then(|size| size.as_integer()).unwrap_or(5000) as usize
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_influx_retries(&self) -> u32 {
+        self.get_optional("influx", "retries").and_then(|retries| retries.as_integer()).unwrap_or(3) as u32
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Numbers such as altitude = 120 are fine written without a decimal point
+    fn get_optional_f32(&self, name : &str, key : &str) -> Option<f32> {
+        let value = self.get_optional(name, key)?;
+        match value.as_float().or(value.as_integer().map(|value| value as f64)) {
+            Some(value) => Some(value as f32),
+            None => panic!("{} in {} must be a number", key, name)
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Station altitude in metres above mean sea level, needed to reduce pressure to sea level
+    pub fn get_altitude(&self) -> f32 {
+        self.get_optional_f32("common", "altitude").unwrap_or(0.0)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Station latitude in degrees, negative in the southern hemisphere
+    pub fn get_latitude(&self) -> Option<f32> {
+        self.get_optional_f32("common", "latitude")
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Rain per tip of the rain gauge bucket
+    pub fn get_rain_mm_per_tip(&self) -> f32 {
+        self.get_optional_f32("outdoor", "rain_mm_per_tip").unwrap_or(0.2794)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Local hour at which the daily rainfall total starts again, 9am by default as for climate records
+    pub fn get_rain_reset_hour(&self) -> u32 {
+        match self.get_optional("outdoor", "rain_reset_hour").and_then(|hour| hour.as_integer()) {
+            Some(hour) if (0..24).contains(&hour) => hour as u32,
+            Some(hour) => panic!("rain_reset_hour {} must be 0 to 23", hour),
+            None => 9
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Wind vane ADC reading to direction lookup, pairs of [adc, degrees]. None means use the standard vane's table.
+    pub fn get_vane_table(&self) -> Option<Vec<(u16, f32)>> {
+        let table = self.get_optional("outdoor", "vane_table")?.as_array().expect("vane_table must be an array");
+        Some(table.iter().map(|entry| {
+            match entry.as_array().map(|pair| pair.as_slice()) {
+                Some([adc, direction]) => {
+                    let adc = adc.as_integer().expect("vane_table ADC reading must be an integer") as u16;
+                    let direction = direction.as_float().or(direction.as_integer().map(|value| value as f64))
+                        .expect("vane_table direction must be a number");
+                    (adc, direction as f32)
+                },
+                _ => panic!("vane_table entries must be [adc, degrees]")
+            }
+        }).collect())
+    }
+
+
     //------------------------------------------------------------------------------------------------------------------------------
     pub fn get_sample_period(&self) -> u32 {
         match self.config["common"]["sample_period_in_mins"].as_integer() {
@@ -81,6 +295,59 @@ impl Config {
     }
 
 
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// IIR filter coefficient for the indoor sensor's temperature and pressure, 0 for none
+    pub fn get_iir_filter(&self) -> u8 {
+        match self.get_optional("indoor", "iir_filter").map(|coefficient| coefficient.as_integer()) {
+            None => 0,
+            Some(Some(coefficient)) if [0, 1, 3, 7, 15, 31, 63, 127].contains(&coefficient) => coefficient as u8,
+            _ => panic!("iir_filter must be 0, 1, 3, 7, 15, 31, 63 or 127")
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// float or integer, how the indoor sensor's readings are compensated
+    pub fn get_compensation(&self) -> &str {
+        self.get_optional("indoor", "compensation").and_then(|compensation| compensation.as_str()).unwrap_or("float")
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// File to save the indoor sensor's calibration to at start up, for replaying its readings offline. Absolute, as the
+    /// working directory depends on how the node was started.
+    pub fn get_calibration_file(&self) -> Option<&str> {
+        let file = self.get_optional("indoor", "calibration_file")?.as_str().expect("calibration_file must be a string");
+        assert!(Path::new(file).is_absolute(), "calibration_file {} must be an absolute path", file);
+        Some(file)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Heater profile for the indoor sensor's gas measurements, pairs of [degrees C, multiple of gas_step_ms].
+    /// None means forced mode without gas.
+    pub fn get_gas_profile(&self) -> Option<Vec<(u16, u8)>> {
+        let profile = self.get_optional("indoor", "gas_profile")?.as_array().expect("gas_profile must be an array");
+        Some(profile.iter().map(|entry| {
+            match entry.as_array().map(|pair| pair.as_slice()) {
+                Some([temperature, multiplier]) => {
+                    let temperature = temperature.as_integer().expect("gas_profile temperature must be an integer") as u16;
+                    let multiplier = multiplier.as_integer().expect("gas_profile multiplier must be an integer") as u8;
+                    (temperature, multiplier)
+                },
+                _ => panic!("gas_profile entries must be [degrees, multiplier]")
+            }
+        }).collect())
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Heater duration that gas_profile's multipliers apply to
+    pub fn get_gas_step_ms(&self) -> u16 {
+        self.get_optional("indoor", "gas_step_ms").and_then(|ms| ms.as_integer()).unwrap_or(140) as u16
+    }
+
+
     //------------------------------------------------------------------------------------------------------------------------------
     pub fn get_wind_dev_name(&self) -> &str {
         match self.config["outdoor"]["wind_dev"].as_str() {
@@ -88,5 +355,123 @@ impl Config {
             None => panic!("No wind dev specified for outdoor in config file")
         }
     }
-}
 
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Measurements per second for the outdoor temperature sensor to average over each tick, None for a single
+    /// measurement at the tick
+    pub fn get_temp_rate(&self) -> Option<f32> {
+        self.get_optional_f32("outdoor", "temp_rate")
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// low, medium or high, higher is less noisy but slower
+    pub fn get_temp_repeatability(&self) -> &str {
+        self.get_optional("outdoor", "temp_repeatability").and_then(|repeatability| repeatability.as_str()).unwrap_or("high")
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// I2C address of the outdoor temperature sensor, 0x44 or 0x45 depending on its ADDR pin
+    pub fn get_temp_addr(&self) -> u16 {
+        match self.get_optional("outdoor", "temp_addr").map(|addr| addr.as_integer()) {
+            None => 0x44,
+            Some(Some(addr)) if addr == 0x44 || addr == 0x45 => addr as u16,
+            _ => panic!("temp_addr must be 0x44 or 0x45")
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Temperature at which the outdoor sensor raises its ALERT pin, None to leave the sensor's defaults
+    pub fn get_frost_alert_temp(&self) -> Option<f32> {
+        self.get_optional_f32("outdoor", "frost_alert_temp")
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Minutes between bursts of the outdoor temperature sensor's heater, None to leave it off
+    pub fn get_heater_interval_mins(&self) -> Option<u32> {
+        self.get_optional("outdoor", "heater_interval_mins").map(|mins| match mins.as_integer() {
+            Some(mins) if mins > 0 => mins as u32,
+            _ => panic!("heater_interval_mins must be a positive whole number")
+        })
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Length of each heater burst
+    pub fn get_heater_secs(&self) -> u32 {
+        self.get_optional("outdoor", "heater_secs").and_then(|secs| secs.as_integer()).unwrap_or(30) as u32
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Time for the sensor to cool after a heater burst, readings until then are flagged
+    pub fn get_heater_recovery_secs(&self) -> u32 {
+        self.get_optional("outdoor", "heater_recovery_secs").and_then(|secs| secs.as_integer()).unwrap_or(120) as u32
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// I2C device of the outdoor light sensor, None if there isn't one
+    pub fn get_solar_dev_name(&self) -> Option<&str> {
+        self.get_optional("outdoor", "solar_dev").map(|dev| dev.as_str().expect("solar_dev must be a string"))
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Baud rate of the wind device, to match the Arduino firmware
+    pub fn get_wind_baud(&self) -> u32 {
+        self.get_optional("outdoor", "wind_baud").and_then(|baud| baud.as_integer()).unwrap_or(9600) as u32
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Seconds the Arduino measures each wind speed over, None to leave it at the firmware's default
+    pub fn get_wind_window_secs(&self) -> Option<u8> {
+        match self.get_optional("outdoor", "wind_window_secs").and_then(|secs| secs.as_integer()) {
+            Some(secs) if (1..=60).contains(&secs) => Some(secs as u8),
+            Some(secs) => panic!("wind_window_secs {} must be 1 to 60", secs),
+            None => None
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Data bits, parity and stop bits of the wind device, e.g. 8N2
+    pub fn get_wind_framing(&self) -> &str {
+        self.get_optional("outdoor", "wind_framing").and_then(|framing| framing.as_str()).unwrap_or("8N2")
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// The sensors a station node reads, in the order their columns go in its table
+    pub fn get_sensors(&self, name : &str) -> Vec<SensorConfig> {
+        match self.get_optional(name, "sensors").map(|sensors| sensors.as_array()) {
+            Some(Some(sensors)) => sensors.iter().map(SensorConfig::from_value).collect(),
+            Some(None) => panic!("sensors for {} must be an array", name),
+            None => panic!("No sensors specified for {} in config file", name)
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Nodes the collector gathers readings from
+    pub fn get_nodes(&self) -> Vec<&str> {
+        match self.get_optional("collector", "nodes") {
+            Some(nodes) => nodes.as_array().and_then(|nodes| nodes.iter().map(|node| node.as_str()).collect())
+                .expect("nodes must be an array of section names"),
+            None => vec!["indoor", "outdoor"]
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// A table of settings by channel in a node's section, e.g. its calibration or qc table
+    pub fn get_channel_settings(&self, name : &str, key : &str) -> Option<&Table> {
+        self.get_optional(name, key).map(|channels| channels.as_table()
+            .unwrap_or_else(|| panic!("{} for {} must be a table of channels", key, name)))
+    }
+}
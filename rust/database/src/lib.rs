@@ -0,0 +1,182 @@
+//!
+//! Generic access to the tables written by the nodes and the collector
+//!
+
+use weather_err::{Result, WeatherError};
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// One row of a table, values in the same order as the columns asked for
+pub struct Row {
+    pub unix_time : i64,
+    pub values : Vec<Option<f64>>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Unit of the values stored in a column, None if unknown or unitless
+pub fn get_unit(column : &str) -> Option<&'static str> {
+    match column {
+        "temperature" => Some("°C"),
+        "humidity" => Some("%"),
+        "pressure" | "msl_pressure" => Some("hPa"),
+        "max_speed" | "ave_speed" | "min_speed" | "gust_speed" | "sustained_speed_2min" | "sustained_speed_10min" => Some("m/s"),
+        "beaufort" => Some("Bft"),
+        "precipitation" | "rain_daily" => Some("mm"),
+        "rain_rate" => Some("mm/h"),
+        "wind_direction" | "gust_direction" => Some("°"),
+        "solar" | "solar_max" => Some("W/m²"),
+        "illuminance" | "illuminance_max" => Some("lx"),
+        "dew_point" | "frost_point" | "heat_index" | "humidex" | "wind_chill" | "apparent_temperature" => Some("°C"),
+        "absolute_humidity" => Some("g/m³"),
+        "gas_resistance" => Some("Ω"),
+        // Uncorrected readings of calibrated channels
+        _ => get_unit(column.strip_suffix("_raw")?)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub fn open(db_file : &str) -> Result<sqlite::Connection> {
+    Ok(sqlite::open(db_file)?)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// All the columns of a table apart from unix_time
+pub fn get_columns(conn : &sqlite::Connection, table : &str) -> Result<Vec<String>> {
+    let query = format!("pragma table_info ('{}');", table);
+    let mut columns = Vec::new();
+    for row in conn.prepare(query)?.into_iter() {
+        let name = String::from(row?.read::<&str, _>("name"));
+        if name != "unix_time" {
+            columns.push(name);
+        }
+    }
+    if columns.is_empty() {
+        return Err(WeatherError::from("No such table or table has no columns"));
+    }
+    Ok(columns)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Create the table if needed, adding any of the REAL columns that an existing table is missing, so older databases
+/// pick up new columns. Rows from before the column was added read as NULL.
+pub fn create_table(conn : &sqlite::Connection, table : &str, columns : &[&str]) -> Result<()> {
+    let mut query = format!("CREATE TABLE IF NOT EXISTS {} (unix_time INT NOT NULL", table);
+    for column in columns {
+        query.push_str(&format!(", {} REAL", column));
+    }
+    query.push_str(", PRIMARY KEY(unix_time));");
+    conn.execute(query)?;
+
+    let known = get_columns(conn, table)?;
+    for column in columns.iter().filter(|column| !known.iter().any(|known| known == *column)) {
+        println!("Adding column {} to {}", column, table);
+        conn.execute(format!("ALTER TABLE {} ADD COLUMN {} REAL;", table, column))?;
+    }
+    Ok(())
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Insert a row by column name, None and non-finite values are stored as NULL
+pub fn insert(conn : &sqlite::Connection, table : &str, unix_time : i64, values : &[(&str, Option<f64>)]) -> Result<()> {
+    let mut names = String::from("unix_time");
+    let mut row = unix_time.to_string();
+    for (column, value) in values {
+        names.push_str(&format!(", {}", column));
+        match value {
+            Some(value) if value.is_finite() => row.push_str(&format!(", {}", value)),
+            _ => row.push_str(", NULL")
+        }
+    }
+    conn.execute(format!("INSERT INTO {} ({}) VALUES ({});", table, names, row))?;
+    Ok(())
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Rows with from < unix_time <= to, oldest first
+pub fn get_rows(conn : &sqlite::Connection, table : &str, columns : &[String],
+            from : i64, to : Option<i64>, limit : Option<usize>) -> Result<Vec<Row>> {
+
+    let known = get_columns(conn, table)?;
+    if let Some(column) = columns.iter().find(|column| !known.contains(column)) {
+        return Err(WeatherError::from(format!("Unknown column {} in {}", column, table)));
+    }
+
+    let mut query = format!("SELECT unix_time, {} FROM {} WHERE unix_time > {}", columns.join(", "), table, from);
+    if let Some(to) = to {
+        query.push_str(&format!(" AND unix_time <= {}", to));
+    }
+    query.push_str(" ORDER BY unix_time");
+    if let Some(limit) = limit {
+        query.push_str(&format!(" LIMIT {}", limit));
+    }
+    query.push(';');
+
+    let mut rows = Vec::new();
+    for row in conn.prepare(query)?.into_iter() {
+        let row = row?;
+        rows.push(Row {
+            unix_time : row.read::<i64, _>("unix_time"),
+            values : columns.iter().map(|column| row.read::<Option<f64>, _>(column.as_str())).collect()
+        });
+    }
+    Ok(rows)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_get_rows() {
+        let conn = open(":memory:").unwrap();
+        conn.execute("CREATE TABLE Test (unix_time INT NOT NULL, temperature REAL, humidity REAL, PRIMARY KEY(unix_time));
+            INSERT INTO Test VALUES (300, 21.5, NULL);
+            INSERT INTO Test VALUES (100, 20.5, 50.0);
+            INSERT INTO Test VALUES (200, 21.0, 55.0);").unwrap();
+
+        assert_eq!(get_columns(&conn, "Test").unwrap(), ["temperature", "humidity"]);
+
+        let columns = get_columns(&conn, "Test").unwrap();
+        let rows = get_rows(&conn, "Test", &columns, 100, None, None).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].unix_time, 200);
+        assert_eq!(rows[0].values, [Some(21.0), Some(55.0)]);
+        assert_eq!(rows[1].values, [Some(21.5), None]);
+
+        let rows = get_rows(&conn, "Test", &[String::from("humidity")], 0, Some(200), Some(1)).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].unix_time, 100);
+        assert_eq!(rows[0].values, [Some(50.0)]);
+
+        assert!(get_rows(&conn, "Test", &[String::from("pressure")], 0, None, None).is_err());
+        assert!(get_columns(&conn, "Missing").is_err());
+    }
+
+    #[test]
+    fn check_create_and_insert() {
+        let conn = open(":memory:").unwrap();
+        create_table(&conn, "Test", &["temperature"]).unwrap();
+        insert(&conn, "Test", 100, &[("temperature", Some(20.5))]).unwrap();
+
+        // Reopening with more columns adds them, older rows read as NULL
+        create_table(&conn, "Test", &["temperature", "dew_point"]).unwrap();
+        assert_eq!(get_columns(&conn, "Test").unwrap(), ["temperature", "dew_point"]);
+        insert(&conn, "Test", 200, &[("dew_point", Some(9.5)), ("temperature", None)]).unwrap();
+        insert(&conn, "Test", 300, &[("temperature", Some(f64::NAN)), ("dew_point", Some(1.0))]).unwrap();
+
+        let columns = get_columns(&conn, "Test").unwrap();
+        let rows = get_rows(&conn, "Test", &columns, 0, None, None).unwrap();
+        assert_eq!(rows[0].values, [Some(20.5), None]);
+        assert_eq!(rows[1].values, [None, Some(9.5)]);
+        assert_eq!(rows[2].values, [None, Some(1.0)]);
+        assert!(insert(&conn, "Test", 300, &[("temperature", Some(1.0))]).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+//!
+//! Export historical data from the collector or node databases
+//!
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::process;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use weather_err::{Result, WeatherError};
+
+use crate::writer::{Csv, JsonLines, Writer};
+
+mod writer;
+#[cfg(feature = "parquet")]
+mod parquet_writer;
+
+const USAGE : &str = "Usage: weather-export <node> [options]
+       weather-export --db <file> --table <table> [options]
+Options:
+  --from <time>         Start time, inclusive
+  --to <time>           End time, exclusive
+  --columns <a,b,..>    Columns to export, default all
+  --tz <zone>           Timezone, e.g. Europe/London, default UTC
+  --format <format>     csv, jsonl or parquet, default csv
+  --output <file>       Default stdout
+Times are unix times or YYYY-MM-DD[ HH:MM[:SS]] in the timezone";
+
+const BATCH_SIZE : usize = 10000;
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+struct Options {
+    node : Option<String>,
+    db_file : Option<String>,
+    db_table : Option<String>,
+    from : Option<String>,
+    to : Option<String>,
+    columns : Option<Vec<String>>,
+    tz : Tz,
+    format : String,
+    output : Option<String>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Options {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn parse(args : &[String]) -> Result<Self> {
+        let mut options = Options {
+            node : None, db_file : None, db_table : None, from : None, to : None, columns : None,
+            tz : Tz::UTC, format : String::from("csv"), output : None
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                if options.node.is_some() {
+                    return Err(WeatherError::from(USAGE));
+                }
+                options.node = Some(arg.clone());
+                continue;
+            }
+            let value = args.next().ok_or(USAGE)?.clone();
+            match arg.as_str() {
+                "--db" => options.db_file = Some(value),
+                "--table" => options.db_table = Some(value),
+                "--from" => options.from = Some(value),
+                "--to" => options.to = Some(value),
+                "--columns" => options.columns = Some(value.split(',').map(|column| String::from(column.trim())).collect()),
+                "--tz" => options.tz = value.parse::<Tz>().map_err(|_| WeatherError::from(format!("Unknown timezone {}", value)))?,
+                "--format" => options.format = value,
+                "--output" => options.output = Some(value),
+                _ => return Err(WeatherError::from(USAGE))
+            }
+        }
+        if options.node.is_none() && (options.db_file.is_none() || options.db_table.is_none()) {
+            return Err(WeatherError::from(USAGE));
+        }
+        Ok(options)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Unix time, or a local date/time in the given timezone
+fn parse_time(value : &str, tz : &Tz) -> Result<i64> {
+    if let Ok(unix_time) = value.parse::<i64>() {
+        return Ok(unix_time);
+    }
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date_time.timestamp());
+    }
+
+    let mut naive = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0));
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"] {
+        if naive.is_some() {
+            break;
+        }
+        naive = NaiveDateTime::parse_from_str(value, format).ok();
+    }
+    let naive = naive.ok_or(WeatherError::from(format!("Can't parse time {}", value)))?;
+
+    // Ambiguous times, when the clocks go back, take the earlier
+    match tz.from_local_datetime(&naive).earliest() {
+        Some(date_time) => Ok(date_time.timestamp()),
+        None => Err(WeatherError::from(format!("{} does not exist in {}", value, tz)))
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn format_time(unix_time : i64, tz : &Tz) -> String {
+    match DateTime::from_timestamp(unix_time, 0) {
+        Some(date_time) => date_time.with_timezone(tz).to_rfc3339(),
+        None => String::new()
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn create_writer(options : &Options) -> Result<Box<dyn Writer>> {
+    let file = match &options.output {
+        Some(output) => Some(File::create(output)?),
+        None => None
+    };
+    Ok(match (options.format.as_str(), file) {
+        ("csv", Some(file)) => Box::new(Csv::new(BufWriter::new(file))),
+        ("csv", None) => Box::new(Csv::new(io::stdout().lock())),
+        ("jsonl", Some(file)) => Box::new(JsonLines::new(BufWriter::new(file))),
+        ("jsonl", None) => Box::new(JsonLines::new(io::stdout().lock())),
+        #[cfg(feature = "parquet")]
+        ("parquet", Some(file)) => Box::new(parquet_writer::Parquet::new(file)),
+        #[cfg(feature = "parquet")]
+        ("parquet", None) => return Err(WeatherError::from("Parquet needs --output")),
+        #[cfg(not(feature = "parquet"))]
+        ("parquet", _) => return Err(WeatherError::from("Built without the parquet feature")),
+        (format, _) => return Err(WeatherError::from(format!("Unknown format {}", format)))
+    })
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn run(args : &[String]) -> Result<()> {
+    let options = Options::parse(args)?;
+
+    let config;
+    let (db_file, db_table) = match (&options.db_file, &options.db_table) {
+        (Some(db_file), Some(db_table)) => (db_file.as_str(), db_table.as_str()),
+        _ => {
+            config = config::Config::new();
+            config.get_database(options.node.as_deref().unwrap())
+        }
+    };
+
+    let conn = database::open(db_file)?;
+    let columns = match &options.columns {
+        Some(columns) => columns.clone(),
+        None => database::get_columns(&conn, db_table)?
+    };
+
+    // Stored range is from < unix_time <= to, asked for is from <= unix_time < to
+    let mut cursor = match &options.from {
+        Some(from) => parse_time(from, &options.tz)? - 1,
+        None => i64::MIN
+    };
+    let to = match &options.to {
+        Some(to) => Some(parse_time(to, &options.tz)? - 1),
+        None => None
+    };
+
+    let mut writer = create_writer(&options)?;
+    writer.header(&columns)?;
+    loop {
+        let rows = database::get_rows(&conn, db_table, &columns, cursor, to, Some(BATCH_SIZE))?;
+        let last = match rows.last() {
+            Some(row) => row.unix_time,
+            None => break
+        };
+        for row in &rows {
+            writer.row(&format_time(row.unix_time, &options.tz), row)?;
+        }
+        cursor = last;
+    }
+    writer.finish()
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn main() {
+    let args : Vec<String> = env::args().skip(1).collect();
+    if let Err(error) = run(&args) {
+        eprintln!("{:?}", error);
+        process::exit(1);
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_parse_time() {
+        let london : Tz = "Europe/London".parse().unwrap();
+        assert_eq!(parse_time("1700000000", &london).unwrap(), 1700000000);
+        assert_eq!(parse_time("2024-01-15", &Tz::UTC).unwrap(), 1705276800);
+        assert_eq!(parse_time("2024-07-15", &london).unwrap(), 1721001600 - 3600);
+        assert_eq!(parse_time("2024-07-15 12:30", &london).unwrap(), 1721046600 - 3600);
+        assert_eq!(parse_time("2024-07-15T12:30:15", &Tz::UTC).unwrap(), 1721046615);
+        assert_eq!(parse_time("2024-07-15T12:30:00+02:00", &Tz::UTC).unwrap(), 1721046600 - 7200);
+        assert!(parse_time("2024-03-31 01:30", &london).is_err());
+        assert!(parse_time("yesterday", &london).is_err());
+    }
+
+    #[test]
+    fn check_format_time() {
+        let london : Tz = "Europe/London".parse().unwrap();
+        assert_eq!(format_time(1721046600, &london), "2024-07-15T13:30:00+01:00");
+        assert_eq!(format_time(1705276800, &Tz::UTC), "2024-01-15T00:00:00+00:00");
+    }
+
+    #[test]
+    fn check_parse_options() {
+        let args : Vec<String> = ["outdoor", "--columns", "temperature, humidity", "--tz", "Europe/London", "--format", "jsonl"]
+            .iter().map(|arg| String::from(*arg)).collect();
+        let options = Options::parse(&args).unwrap();
+        assert_eq!(options.node.as_deref(), Some("outdoor"));
+        assert_eq!(options.columns.unwrap(), ["temperature", "humidity"]);
+        assert_eq!(options.format, "jsonl");
+
+        assert!(Options::parse(&[String::from("--tz")]).is_err());
+        assert!(Options::parse(&[String::from("--db"), String::from("x.db")]).is_err());
+    }
+}
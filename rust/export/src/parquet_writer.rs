@@ -0,0 +1,136 @@
+//!
+//! Parquet output, rows are buffered and written as a single row group on finish
+//!
+
+use std::fs::File;
+use std::sync::Arc;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use database::Row;
+use weather_err::{Result, WeatherError};
+
+use crate::writer::Writer;
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn parquet_error(error : parquet::errors::ParquetError) -> WeatherError {
+    WeatherError::from(format!("Parquet Error {}", error))
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Parquet {
+    file : File,
+    columns : Vec<String>,
+    unix_times : Vec<i64>,
+    times : Vec<ByteArray>,
+    values : Vec<Vec<Option<f64>>>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Parquet {
+    pub fn new(file : File) -> Self {
+        Self {
+            file,
+            columns : Vec::new(),
+            unix_times : Vec::new(),
+            times : Vec::new(),
+            values : Vec::new()
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Writer for Parquet {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn header(&mut self, columns : &[String]) -> Result<()> {
+        self.columns = columns.to_vec();
+        self.values = vec![Vec::new(); columns.len()];
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn row(&mut self, time : &str, row : &Row) -> Result<()> {
+        self.unix_times.push(row.unix_time);
+        self.times.push(ByteArray::from(time));
+        for (values, value) in self.values.iter_mut().zip(&row.values) {
+            values.push(*value);
+        }
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn finish(self : Box<Self>) -> Result<()> {
+        let mut message = String::from("message weather { REQUIRED INT64 unix_time; REQUIRED BYTE_ARRAY time (UTF8);");
+        for column in &self.columns {
+            message.push_str(&format!(" OPTIONAL DOUBLE {};", column));
+        }
+        message.push_str(" }");
+        let schema = Arc::new(parse_message_type(&message).map_err(parquet_error)?);
+
+        // Units go in the file metadata as there is nowhere for them in the schema
+        let units = self.columns.iter()
+            .filter_map(|column| database::get_unit(column).map(|unit| KeyValue::new(format!("unit.{}", column), String::from(unit))))
+            .collect();
+        let props = Arc::new(WriterProperties::builder().set_key_value_metadata(Some(units)).build());
+
+        let mut writer = SerializedFileWriter::new(self.file, schema, props).map_err(parquet_error)?;
+        let mut row_group = writer.next_row_group().map_err(parquet_error)?;
+
+        let mut column_idx = 0;
+        while let Some(mut column) = row_group.next_column().map_err(parquet_error)? {
+            match column_idx {
+                0 => column.typed::<Int64Type>().write_batch(&self.unix_times, None, None),
+                1 => column.typed::<ByteArrayType>().write_batch(&self.times, None, None),
+                idx => {
+                    let values = &self.values[idx - 2];
+                    let present : Vec<f64> = values.iter().filter_map(|value| *value).collect();
+                    let def_levels : Vec<i16> = values.iter().map(|value| value.is_some() as i16).collect();
+                    column.typed::<DoubleType>().write_batch(&present, Some(&def_levels), None)
+                }
+            }.map_err(parquet_error)?;
+            column.close().map_err(parquet_error)?;
+            column_idx += 1;
+        }
+        row_group.close().map_err(parquet_error)?;
+        writer.close().map_err(parquet_error)?;
+        Ok(())
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    #[test]
+    fn check_round_trip() {
+        let path = std::env::temp_dir().join(format!("export_test_{}.parquet", std::process::id()));
+
+        let mut writer = Box::new(Parquet::new(File::create(&path).unwrap()));
+        writer.header(&[String::from("temperature"), String::from("humidity")]).unwrap();
+        writer.row("t100", &Row { unix_time : 100, values : vec![Some(21.5), None] }).unwrap();
+        writer.row("t200", &Row { unix_time : 200, values : vec![Some(22.0), Some(50.0)] }).unwrap();
+        writer.finish().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 2);
+        let units = metadata.key_value_metadata().unwrap();
+        assert_eq!(units[0].key, "unit.temperature");
+        assert_eq!(units[0].value.as_deref(), Some("°C"));
+
+        let rows : Vec<String> = reader.get_row_iter(None).unwrap().map(|row| row.unwrap().to_string()).collect();
+        assert_eq!(rows, ["{unix_time: 100, time: \"t100\", temperature: 21.5, humidity: null}",
+                          "{unix_time: 200, time: \"t200\", temperature: 22.0, humidity: 50.0}"]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,170 @@
+//!
+//! Output formats for exported rows
+//!
+
+use std::io::Write;
+use database::Row;
+use weather_err::Result;
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub trait Writer {
+    fn header(&mut self, columns : &[String]) -> Result<()>;
+
+    /// time is the row's unix_time formatted in the requested timezone
+    fn row(&mut self, time : &str, row : &Row) -> Result<()>;
+
+    fn finish(self : Box<Self>) -> Result<()>;
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn format_value(value : Option<f64>, null : &str) -> String {
+    match value {
+        Some(value) if value.is_finite() => format!("{}", value),
+        _ => String::from(null)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn csv_field(value : &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        String::from(value)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// CSV with a header line giving each column's unit, e.g. "temperature (°C)"
+pub struct Csv<W : Write> {
+    out : W
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl<W : Write> Csv<W> {
+    pub fn new(out : W) -> Self {
+        Self {
+            out
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl<W : Write> Writer for Csv<W> {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn header(&mut self, columns : &[String]) -> Result<()> {
+        let mut fields = vec![String::from("unix_time"), String::from("time")];
+        for column in columns {
+            fields.push(csv_field(&match database::get_unit(column) {
+                Some(unit) => format!("{} ({})", column, unit),
+                None => column.clone()
+            }));
+        }
+        writeln!(self.out, "{}", fields.join(","))?;
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn row(&mut self, time : &str, row : &Row) -> Result<()> {
+        let mut fields = vec![row.unix_time.to_string(), csv_field(time)];
+        fields.extend(row.values.iter().map(|value| format_value(*value, "")));
+        writeln!(self.out, "{}", fields.join(","))?;
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn finish(mut self : Box<Self>) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// One JSON object per line, nulls for missing values
+pub struct JsonLines<W : Write> {
+    out : W,
+    columns : Vec<String>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl<W : Write> JsonLines<W> {
+    pub fn new(out : W) -> Self {
+        Self {
+            out,
+            columns : Vec::new()
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl<W : Write> Writer for JsonLines<W> {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn header(&mut self, columns : &[String]) -> Result<()> {
+        self.columns = columns.iter().map(|column| json::string(column)).collect();
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn row(&mut self, time : &str, row : &Row) -> Result<()> {
+        let mut line = format!("{{\"unix_time\":{},\"time\":{}", row.unix_time, json::string(time));
+        for (column, value) in self.columns.iter().zip(&row.values) {
+            line.push_str(&format!(",{}:{}", column, format_value(*value, "null")));
+        }
+        line.push('}');
+        writeln!(self.out, "{}", line)?;
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn finish(mut self : Box<Self>) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<Row> {
+        vec![Row { unix_time : 100, values : vec![Some(21.5), None] },
+             Row { unix_time : 200, values : vec![Some(22.0), Some(1.25)] }]
+    }
+
+    fn export(writer : &mut dyn Writer) {
+        writer.header(&[String::from("temperature"), String::from("rain, estimated")]).unwrap();
+        for row in rows() {
+            writer.row(&format!("t{}", row.unix_time), &row).unwrap();
+        }
+    }
+
+    #[test]
+    fn check_csv() {
+        let mut out = Vec::new();
+        export(&mut Csv::new(&mut out));
+        assert_eq!(String::from_utf8(out).unwrap(), "unix_time,time,temperature (°C),\"rain, estimated\"\n\
+                                                     100,t100,21.5,\n\
+                                                     200,t200,22,1.25\n");
+    }
+
+    #[test]
+    fn check_json_lines() {
+        let mut out = Vec::new();
+        export(&mut JsonLines::new(&mut out));
+        assert_eq!(String::from_utf8(out).unwrap(),
+            "{\"unix_time\":100,\"time\":\"t100\",\"temperature\":21.5,\"rain, estimated\":null}\n\
+             {\"unix_time\":200,\"time\":\"t200\",\"temperature\":22,\"rain, estimated\":1.25}\n");
+    }
+}
@@ -0,0 +1,121 @@
+//!
+//! Answers the listener's forecast command from the pressure history in the indoor table
+//!
+
+use std::sync::{Arc, Mutex};
+use chrono::{Datelike, Local};
+use weather_err::{Result, WeatherError};
+
+use meteo::forecast::{Forecast, Tendency};
+
+// Enough history either side of three hours ago for Tendency to find a reading
+const HISTORY_SECS : i64 = 4 * 60 * 60;
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Forecaster {
+    db_connection : Arc<Mutex<sqlite::Connection>>,
+    db_table : String,
+    altitude : f32,
+    latitude : Option<f32>,
+    outdoor : Option<(String, u16)>,
+    max_age : i64
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Forecaster {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(config : &config::Config, db_connection : Arc<Mutex<sqlite::Connection>>, db_table : &str) -> Self {
+        Self {
+            db_connection,
+            db_table : String::from(db_table),
+            altitude : config.get_altitude(),
+            latitude : config.get_latitude(),
+            outdoor : config.get_host("outdoor").map(|host| (String::from(host), config.get_port())),
+            max_age : 2 * 60 * config.get_sample_period() as i64
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Wind direction from the outdoor node, if it has a wind vane
+    fn get_wind_direction(&self, unix_time : i64) -> Option<f32> {
+        let (host, port) = self.outdoor.as_ref()?;
+        match listener::client::fetch_latest(host, *port, "wind_direction", unix_time - self.max_age) {
+            Ok(latest) => latest.map(|(_, direction)| direction as f32),
+            Err(error) => {
+                println!("Failed to get wind direction {:?}", error);
+                None
+            }
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Response lines for the listener
+    pub fn forecast(&self) -> Result<String> {
+        let now = Local::now();
+        let columns = [String::from("pressure"), String::from("msl_pressure")];
+        let rows = {
+            let conn = self.db_connection.lock().expect("Unexpected failure to lock mutex");
+            database::get_rows(&conn, &self.db_table, &columns, now.timestamp() - HISTORY_SECS, None, None)?
+        };
+
+        let history : Vec<(i64, f32)> = rows.iter()
+            .filter_map(|row| row.values[0].map(|pressure| (row.unix_time, pressure as f32)))
+            .collect();
+        let tendency = Tendency::from_history(&history).ok_or(WeatherError::from("Not enough pressure history"))?;
+
+        // The newest row with a pressure, a failed reading leaves it NULL
+        let (unix_time, pressure) = *history.last().ok_or(WeatherError::from("No pressure readings"))?;
+        // Rows from before sea level pressure was stored don't have it
+        let msl_pressure = match rows.iter().find(|row| row.unix_time == unix_time).and_then(|row| row.values[1]) {
+            Some(msl_pressure) => msl_pressure as f32,
+            None => meteo::msl_pressure(pressure, self.altitude, None, self.latitude)
+        };
+
+        let southern = self.latitude.is_some_and(|latitude| latitude < 0.0);
+        let wind_direction = self.get_wind_direction(unix_time);
+        let forecast = Forecast::new(msl_pressure, &tendency, now.month(), wind_direction, southern);
+
+        let mut response = format!("unix_time = {}\n", unix_time);
+        response += &format!("\tpressure_change = {:.1}\n", tendency.get_change());
+        response += &format!("\tcharacteristic = {}\n", tendency.get_characteristic());
+        response += &format!("\ttendency = {}\n", tendency.get_description());
+        response += &format!("\tletter = {}\n", forecast.get_letter());
+        response += &format!("\tforecast = {}\n", forecast.get_text());
+        Ok(response)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_forecast() {
+        let conn = sqlite::open(":memory:").unwrap();
+        conn.execute("CREATE TABLE Indoor (unix_time INT NOT NULL, pressure REAL, msl_pressure REAL, PRIMARY KEY(unix_time))")
+            .unwrap();
+        let now = Local::now().timestamp();
+        for (age, pressure) in [(3 * 60 * 60, "1010.0"), (90 * 60, "1008.0"), (15 * 60, "1006.0"), (0, "NULL")] {
+            conn.execute(format!("INSERT INTO Indoor VALUES ({}, {}, {})", now - age, pressure, pressure)).unwrap();
+        }
+        let forecaster = Forecaster {
+            db_connection : Arc::new(Mutex::new(conn)),
+            db_table : String::from("Indoor"),
+            altitude : 0.0,
+            latitude : None,
+            outdoor : None,
+            max_age : 30 * 60
+        };
+
+        // The newest row failed its checks, so the one before is used
+        let response = forecaster.forecast().unwrap();
+        assert!(response.starts_with(&format!("unix_time = {}\n\tpressure_change = -4.0\n", now - 15 * 60)));
+    }
+}
@@ -1,16 +1,36 @@
+use std::fs;
+use std::path::Path;
 use std::time::Duration;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use sqlite;
-use config;
 
-use bme688;
-use clock;
 use listener::Listener;
+use sensor::Channel;
 use weather_err::Result;
 
+use crate::forecast::Forecaster;
+use crate::parallel::Parallel;
+
+mod forecast;
+mod parallel;
+
 type Connection = Arc<Mutex<sqlite::Connection>>;
 
+const COLUMNS : [&str; 5] = ["temperature", "humidity", "pressure", "msl_pressure", "gas_resistance"];
+
+// Channels that can be calibrated
+const CALIBRATED : [(&str, &str); 3] = [("temperature", "°C"), ("humidity", "%"), ("pressure", "hPa")];
+
+// Sent to the MQTT broker, when the values pass their checks
+#[cfg(feature = "mqtt")]
+const PUBLISHED : [&str; 4] = ["temperature", "humidity", "pressure", "msl_pressure"];
+
+// The raw reading each tick's values were compensated from, to replay with the saved calibration
+const ADC_COLUMNS : [&str; 3] = ["temp_adc", "press_adc", "humd_adc"];
+
+// Wind chill and apparent temperature need a wind speed, frost point is not interesting indoors
+const DERIVED : [&str; 4] = ["dew_point", "absolute_humidity", "heat_index", "humidex"];
+
 //----------------------------------------------------------------------------------------------------------------------------------
 fn wait_tick(ticker : &clock::Clock) -> Result<()> {
      let delay_seconds = ticker.secs_to_next_tick();
@@ -20,44 +40,181 @@ fn wait_tick(ticker : &clock::Clock) -> Result<()> {
 
 
 //----------------------------------------------------------------------------------------------------------------------------------
-fn launch_listener(config : &config::Config, db_connection : Connection)
+fn launch_listener(config : &config::Config, db_connection : Connection, db_table : &str)
 {
-    let mut listener = Listener::new(config.get_port(), db_connection);
+    let mut listener = Listener::new(config.get_port(), db_connection.clone());
+
+    let forecaster = Forecaster::new(config, db_connection, db_table);
+    listener.add_command("forecast", Arc::new(move || forecaster.forecast()));
 
     listener.start();
 }
 
 
 //----------------------------------------------------------------------------------------------------------------------------------
-fn create_sensor(config : &config::Config) -> Result<bme688::Bme688> {
+fn launch_metrics(config : &config::Config)
+{
+    if let Some(port) = config.get_metrics_port("indoor") {
+        metrics::Exporter::new(port).start();
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// From the values that passed their checks, and the checks that failed
+fn update_metrics(unix_time : i64, values : &[(&str, Option<f64>)], gas : &[Option<f32>],
+                  failures : &[(String, sensor::qc::Failure)]) {
+    metrics::LAST_TICK.set(unix_time as f64);
+    for (column, value) in values {
+        match (*column, *value) {
+            ("temperature", Some(temperature)) => metrics::TEMPERATURE.set(temperature),
+            ("humidity", Some(humidity)) => metrics::HUMIDITY.set(humidity),
+            ("pressure", Some(pressure)) => metrics::PRESSURE.set(pressure),
+            ("msl_pressure", Some(msl_pressure)) => metrics::MSL_PRESSURE.set(msl_pressure),
+            _ => ()
+        }
+    }
+    for (name, failure) in failures {
+        metrics::QC_FAILURES.inc_labelled(&[name, &failure.to_string()]);
+    }
+    for (step, resistance) in gas.iter().enumerate() {
+        metrics::GAS_RESISTANCE.set_labelled(&[&step.to_string()], resistance.map_or(f64::NAN, |resistance| resistance as f64));
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(feature = "mqtt")]
+fn launch_publisher(config : &config::Config) -> Option<mqtt::Publisher> {
+    let settings = mqtt::Settings::from_config(config)?;
+    let publisher = mqtt::Publisher::new(settings, "indoor", &[
+        mqtt::Channel { name : "temperature", unit : "°C", device_class : "temperature" },
+        mqtt::Channel { name : "humidity", unit : "%", device_class : "humidity" },
+        mqtt::Channel { name : "pressure", unit : "hPa", device_class : "atmospheric_pressure" },
+        mqtt::Channel { name : "msl_pressure", unit : "hPa", device_class : "atmospheric_pressure" }
+    ]);
+    publisher.start();
+    Some(publisher)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// A forced measurement at each tick, or the means from parallel mode with gas readings
+enum Sensor {
+    Forced(Box<bme688::Bme688>),
+    Parallel(Parallel)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn create_sensor(config : &config::Config) -> Result<Sensor> {
 
-    let mut sensor = bme688::Bme688::new(config.get_dev_name("indoor"));
+    let dev_name = config.get_dev_name("indoor");
+    let mut sensor = bme688::Bme688::new(dev_name);
 
-    sensor.cache_params()?;
+    let variant = sensor.init()?;
+    println!("Found a {} on {}", variant, dev_name);
+
+    if let Some(file_name) = config.get_calibration_file() {
+        if let Err(error) = save_calibration(sensor.get_calibration(), file_name) {
+            println!("Failed to save calibration to {} {:?}", file_name, error);
+        }
+    }
 
     sensor.set_humdity_oversampling(16);
     sensor.set_pressure_oversampling(16);
     sensor.set_temperature_oversampling(16);
-
-    Ok(sensor)
+    sensor.set_filter(config.get_iir_filter());
+
+    let compensation = config.get_compensation();
+    sensor.set_compensation(bme688::Compensation::from_name(compensation)
+        .unwrap_or_else(|| panic!("compensation {} should be float or integer", compensation)));
+
+    let steps = match config.get_gas_profile() {
+        Some(steps) => steps,
+        None => return Ok(Sensor::Forced(Box::new(sensor)))
+    };
+    let steps : Vec<_> = steps.into_iter()
+        .map(|(temperature, multiplier)| bme688::HeaterStep { temperature, multiplier })
+        .collect();
+    let profile = bme688::HeaterProfile::new(&steps, config.get_gas_step_ms())?;
+    println!("Measuring gas at {} heater steps", steps.len());
+
+    // The heater settings depend on the ambient temperature
+    let ambient_temp = read_forced(&mut sensor)?.0.get_temperature();
+    let parallel = Parallel::new(profile);
+    parallel.start(sensor, ambient_temp)?;
+    Ok(Sensor::Parallel(parallel))
 }
 
 
 //----------------------------------------------------------------------------------------------------------------------------------
-fn read_sensor(sensor : &mut bme688::Bme688) -> bme688::Summary {
-    // Start sample..
-    sensor.one_shot().unwrap();
-    loop {
-        thread::sleep(Duration::from_secs(1));
-        if sensor.is_ready().unwrap() {
-            break;
+/// Only write the calibration when the sensor's is new, moving a different sensor's aside as its raw readings need it
+fn save_calibration(calibration : &bme688::Calibration, file_name : &str) -> Result<()> {
+    if Path::new(file_name).exists() {
+        if bme688::Calibration::load(file_name).ok().as_ref() == Some(calibration) {
+            return Ok(());
         }
+        let old_name = format!("{}.{}", file_name, chrono::Local::now().timestamp());
+        fs::rename(file_name, &old_name)?;
+        println!("Moved the previous calibration to {}", old_name);
+    }
+    calibration.save(file_name)?;
+    println!("Saved calibration to {}", file_name);
+    Ok(())
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Temperature, humidity and pressure, the gas resistance at each heater step in parallel mode and the raw reading
+fn read_sensor(sensor : &mut Sensor) -> Result<(bme688::Summary, Vec<Option<f32>>, bme688::RawReading)> {
+    match sensor {
+        Sensor::Forced(sensor) => {
+            let (summary, raw) = read_forced(sensor)?;
+            Ok((summary, Vec::new(), raw))
+        },
+        Sensor::Parallel(parallel) => parallel.sample()
     }
-    sensor.sample().unwrap()
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Correct the temperature, humidity and pressure, returning the raw readings of those that are calibrated
+fn calibrate(calibration : &sensor::Calibration, summary : &bme688::Summary) -> (Vec<Channel>, Vec<(String, Option<f64>)>) {
+    let mut channels = sensor::channels(&CALIBRATED, &[Some(summary.get_temperature() as f64),
+                                                       Some(summary.get_humidity() as f64),
+                                                       Some(summary.get_pressure() as f64)]);
+    let raw = calibration.apply(&mut channels);
+    (channels, raw)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn read_forced(sensor : &mut bme688::Bme688) -> Result<(bme688::Summary, bme688::RawReading)> {
+    sensor.one_shot()?;
+    sensor::wait_ready(sensor)?;
+    let raw = sensor.read_raw(0)?;
+    Ok((sensor.compensate(&raw), raw))
 }
 
 
 
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Latest outdoor temperature from the outdoor node, if it has one from the last couple of sample periods.
+/// Sea level pressure falls back to the standard atmosphere without it.
+fn get_outdoor_temp(config : &config::Config, unix_time : i64) -> Option<f32> {
+    let host = config.get_host("outdoor")?;
+    let since = unix_time - 2 * 60 * config.get_sample_period() as i64;
+    match listener::client::fetch_latest(host, config.get_port(), "temperature", since) {
+        Ok(latest) => latest.map(|(_, temp)| temp as f32),
+        Err(error) => {
+            println!("Failed to get outdoor temperature {:?}", error);
+            None
+        }
+    }
+}
+
+
 //----------------------------------------------------------------------------------------------------------------------------------
 fn create_ticker(config : &config::Config) -> clock::Clock {
     clock::Clock::new(config.get_sample_period() * 60)
@@ -65,7 +222,8 @@ fn create_ticker(config : &config::Config) -> clock::Clock {
 
 
 //----------------------------------------------------------------------------------------------------------------------------------
-fn create_db_connection(config : &config::Config)-> (Connection, String) {
+/// Extra columns are the raw readings of calibrated channels and the results of their quality checks
+fn create_db_connection(config : &config::Config, extra_columns : &[String])-> (Connection, String) {
 
     let (db_file, db_table) = config.get_database("indoor");
 
@@ -74,12 +232,13 @@ fn create_db_connection(config : &config::Config)-> (Connection, String) {
 
     println!("Creating/using db table {}", db_table);
 
-    let query = format!("CREATE TABLE IF NOT EXISTS {} (unix_time INT NOT NULL,
-            temperature REAL, humidity REAL, pressure REAL, PRIMARY KEY(unix_time));", db_table);
-
+    let mut columns = COLUMNS.to_vec();
+    columns.extend(DERIVED);
+    columns.extend(ADC_COLUMNS);
+    columns.extend(extra_columns.iter().map(|column| column.as_str()));
     {
         let conn = db_connection.lock().unwrap();
-        (*conn).execute(query).unwrap();
+        database::create_table(&conn, db_table, &columns).unwrap();
     }
     (db_connection, String::from(db_table))
 }
@@ -92,29 +251,84 @@ fn main() {
 
     let mut sensor = create_sensor(&config).unwrap();
 
-    let (db_connection, db_table) = create_db_connection(&config);
+    let calibration = sensor::Calibration::from_config(&config, "indoor");
+    calibration.check(&CALIBRATED.map(|(name, _)| name)).unwrap();
+
+    let measured : Vec<_> = COLUMNS.iter().chain(&DERIVED).copied().collect();
+    let mut checker = sensor::qc::Checker::from_config(&config, "indoor", &measured).unwrap();
+
+    let mut extra_columns = calibration.get_raw_columns();
+    extra_columns.extend(checker.get_qc_columns());
+    let (db_connection, db_table) = create_db_connection(&config, &extra_columns);
 
     let ticker = create_ticker(&config);
 
-    launch_listener(&config, db_connection.clone());
+    launch_listener(&config, db_connection.clone(), &db_table);
+
+    launch_metrics(&config);
+
+    #[cfg(feature = "mqtt")]
+    let publisher = launch_publisher(&config);
 
     loop {
-        wait_tick(&ticker);
+        wait_tick(&ticker).unwrap();
         println!("Tick");
         let unix_time = ticker.get_nearest_tick();
 
-        let measurement = read_sensor(&mut sensor);
-        println!("{}", measurement);
+        let (measurement, gas, adc) = match read_sensor(&mut sensor) {
+            Ok(result) => result,
+            Err(error) => {
+                println!("{:?}", error);
+                continue;
+            }
+        };
+        let (channels, raw) = calibrate(&calibration, &measurement);
+        println!("{} gas {:?}", channels.iter().map(|channel| channel.to_string()).collect::<Vec<_>>().join(" "), gas);
+
+        let [temp, humd, press] = [&channels[0], &channels[1], &channels[2]].map(|channel| channel.value);
+
+        let outdoor_temp = get_outdoor_temp(&config, unix_time);
+        let msl_press = press.map(|press| meteo::msl_pressure(press as f32, config.get_altitude(), outdoor_temp,
+                                                              config.get_latitude()) as f64);
+        if let Some(msl_press) = msl_press {
+            println!("{:.0} millibars at sea level", msl_press);
+        }
+
+        let derived = match (temp, humd) {
+            (Some(temp), Some(humd)) => Some(meteo::Derived::new(temp as f32, humd as f32, None)),
+            _ => None
+        };
+        if let Some(derived) = &derived {
+            println!("{}", derived);
+        }
 
-        let temp = measurement.get_temperature();
-        let humd = measurement.get_humidity();
-        let press = measurement.get_pressure();
+        let mut values = vec![("temperature", temp), ("humidity", humd), ("pressure", press), ("msl_pressure", msl_press),
+                              ("gas_resistance", gas.first().copied().flatten().map(|gas| gas as f64))];
+        match &derived {
+            Some(derived) => values.extend(derived.values().iter()
+                .filter(|(name, _)| DERIVED.contains(name))
+                .map(|(name, value)| (*name, value.map(|value| value as f64)))),
+            None => values.extend(DERIVED.map(|name| (name, None)))
+        }
+        let checked = checker.check(&mut values);
+        update_metrics(unix_time, &values, &gas, &checked.failures);
+
+        #[cfg(feature = "mqtt")]
+        if let Some(publisher) = &publisher {
+            let readings : Vec<_> = values.iter()
+                .filter(|(column, _)| PUBLISHED.contains(column))
+                .filter_map(|(column, value)| value.map(|value| (*column, value as f32)))
+                .collect();
+            publisher.publish(&readings);
+        }
 
-        let query = format!("INSERT INTO {} VALUES ({},{},{},{});", db_table, unix_time, temp, humd, press);
+        values.extend(raw.iter().chain(&checked.columns).map(|(column, value)| (column.as_str(), *value)));
+        values.extend(ADC_COLUMNS.into_iter().zip([adc.temp_adc as f64, adc.press_adc as f64, adc.humd_adc as f64])
+            .map(|(column, value)| (column, Some(value))));
 
         {
             let conn = db_connection.lock().unwrap();
-            (*conn).execute(query).unwrap();
+            database::insert(&conn, &db_table, unix_time, &values).unwrap();
         }
     }
 }
@@ -0,0 +1,162 @@
+//!
+//! BME688 parallel mode, the data fields are read as they arrive and averaged at each tick. Gas resistance is kept
+//! per heater step, as each step's resistance is quite different.
+//!
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use bme688::{Bme688, Field, HeaterProfile, RawReading};
+use weather_err::{Result, WeatherError};
+
+// Three fields buffer at most three steps, shorter than the quickest sensible profile
+const POLL_MS : u64 = 100;
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+struct Readings {
+    temperature : ::stats::Accumulator<i64>,
+    humidity : ::stats::Accumulator<i64>,
+    pressure : ::stats::Accumulator<i64>,
+    gas : Vec<::stats::Accumulator<i64>>,
+    // The newest field's, to store with the means
+    raw : Option<RawReading>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Readings {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn new(steps : usize) -> Self {
+        Self {
+            temperature : ::stats::Accumulator::new(),
+            humidity : ::stats::Accumulator::new(),
+            pressure : ::stats::Accumulator::new(),
+            gas : vec![::stats::Accumulator::new(); steps],
+            raw : None
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn add(&mut self, unix_time : i64, field : &Field) {
+        self.temperature.add(unix_time, field.summary.get_temperature() as f64);
+        self.humidity.add(unix_time, field.summary.get_humidity() as f64);
+        self.pressure.add(unix_time, field.summary.get_pressure() as f64);
+        self.raw = Some(field.raw);
+        if let (Some(gas), Some(step)) = (field.gas, self.gas.get_mut(field.gas_index as usize)) {
+            step.add(unix_time, gas as f64);
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Means since the last call, the mean gas resistance at each step and the newest raw reading
+    fn take(&mut self) -> Option<(bme688::Summary, Vec<Option<f32>>, RawReading)> {
+        let summary = match (self.temperature.get_mean(), self.humidity.get_mean(), self.pressure.get_mean(), self.raw.take()) {
+            (Some(temperature), Some(humidity), Some(pressure), Some(raw)) =>
+                Some((bme688::Summary::new(temperature as f32, humidity as f32, pressure as f32), raw)),
+            _ => None
+        };
+        let gas = self.gas.iter().map(|step| step.get_mean().map(|mean| mean as f32)).collect();
+        self.temperature.reset();
+        self.humidity.reset();
+        self.pressure.reset();
+        self.gas.iter_mut().for_each(|step| step.reset());
+        summary.map(|(summary, raw)| (summary, gas, raw))
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Parallel {
+    profile : HeaterProfile,
+    readings : Arc<Mutex<Readings>>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Parallel {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(profile : HeaterProfile) -> Self {
+        Self {
+            readings : Arc::new(Mutex::new(Readings::new(profile.get_steps().len()))),
+            profile
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Put the sensor in parallel mode and read it until the program ends. The heater settings depend a little on the
+    /// ambient temperature, indoors it doesn't change enough to matter.
+    pub fn start(&self, mut sensor : Bme688, ambient_temp : f32) -> Result<()> {
+        sensor.start_parallel(&self.profile, ambient_temp)?;
+        let readings = self.readings.clone();
+
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_millis(POLL_MS));
+                let fields = match sensor.read_fields() {
+                    Ok(fields) => fields,
+                    Err(error) => {
+                        println!("Failed to read BME688 fields {:?}", error);
+                        continue;
+                    }
+                };
+                let unix_time = chrono::Local::now().timestamp();
+                let mut readings = readings.lock().expect("Unexpected failure to lock mutex");
+                for field in &fields {
+                    readings.add(unix_time, field);
+                }
+            }
+        });
+        Ok(())
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Mean temperature, humidity and pressure since the last sample, the mean gas resistance at each heater step and the
+    /// newest raw reading
+    pub fn sample(&self) -> Result<(bme688::Summary, Vec<Option<f32>>, RawReading)> {
+        self.readings.lock().expect("Unexpected failure to lock mutex").take()
+            .ok_or(WeatherError::from("No BME688 readings since the last sample"))
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn field(gas_index : u8, temperature : f32, gas : Option<f32>) -> Field {
+        let raw = RawReading { temp_adc : (temperature * 10000.0) as u32, press_adc : 300000, humd_adc : 20000 };
+        Field { gas_index, meas_index : 0, summary : bme688::Summary::new(temperature, 50.0, 1000.0), raw, gas }
+    }
+
+    #[test]
+    fn check_readings() {
+        let mut readings = Readings::new(2);
+        assert!(readings.take().is_none());
+
+        readings.add(0, &field(0, 20.0, Some(100000.0)));
+        readings.add(1, &field(1, 21.0, Some(5000.0)));
+        readings.add(2, &field(0, 22.0, Some(120000.0)));
+        readings.add(3, &field(1, 21.0, None));
+        // A step the profile doesn't have
+        readings.add(4, &field(7, 21.0, Some(1.0)));
+        let (summary, gas, raw) = readings.take().unwrap();
+        assert_relative_eq!(summary.get_temperature(), 21.0);
+        assert_relative_eq!(summary.get_pressure(), 1000.0);
+        assert_eq!(gas, [Some(110000.0), Some(5000.0)]);
+        assert_eq!(raw.temp_adc, 210000);
+
+        readings.add(5, &field(1, 23.0, None));
+        let (_, gas, raw) = readings.take().unwrap();
+        assert_eq!(raw.temp_adc, 230000);
+        assert_eq!(gas, [None, None]);
+    }
+}
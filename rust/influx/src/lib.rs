@@ -0,0 +1,316 @@
+//!
+//! Forward readings to InfluxDB using the line protocol over HTTP
+//!
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use toml::Table;
+use weather_err::{Result, WeatherError};
+
+pub mod line_protocol;
+
+// Rejections that mean the endpoint or token is wrong rather than the batch, every batch would fail the same way
+const CONFIG_ERRORS : [u16; 3] = [401, 403, 404];
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// The parts of a http:// write endpoint needed to POST to it
+#[derive(Debug, PartialEq)]
+struct Url {
+    host : String,
+    port : u16,
+    path : String
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Url {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn parse(url : &str) -> Result<Self> {
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None => return Err(WeatherError::from("Influx url must start with http://"))
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/")
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse::<u16>()?),
+            None => (authority, 80)
+        };
+        if host.is_empty() {
+            return Err(WeatherError::from("Influx url has no host"));
+        }
+        Ok(Self {
+            host : String::from(host),
+            port,
+            path : String::from(path)
+        })
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// What became of a batch
+#[derive(Debug, PartialEq)]
+enum Written {
+    Accepted,
+    /// Refused for something in the batch itself, e.g. a bad field or a field type conflict, with the status code
+    Rejected(u16)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Pusher {
+    url : Url,
+    token : Option<String>,
+    station : String,
+    cursor_file : String,
+    batch_size : usize,
+    retries : u32
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Pusher {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// None if no write endpoint is configured
+    pub fn from_config(config : &config::Config) -> Option<Result<Self>> {
+        let url = config.get_influx_url()?;
+        Some(Url::parse(url).map(|url| Self {
+            url,
+            token : config.get_influx_token().map(String::from),
+            station : String::from(config.get_station_name()),
+            cursor_file : String::from(config.get_influx_cursor_file()),
+            batch_size : config.get_influx_batch_size(),
+            retries : config.get_influx_retries()
+        }))
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn read_cursors(&self) -> Result<Table> {
+        match fs::read_to_string(&self.cursor_file) {
+            Ok(cursors) => cursors.parse::<Table>().map_err(|error| WeatherError::from(format!("Bad cursor file {}", error))),
+            Err(..) => Ok(Table::new())
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Unix time of the last row pushed for a node
+    pub fn get_cursor(&self, node : &str) -> Result<i64> {
+        Ok(self.read_cursors()?.get(node).and_then(|value| value.as_integer()).unwrap_or(0))
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn set_cursor(&self, node : &str, unix_time : i64) -> Result<()> {
+        let mut cursors = self.read_cursors()?;
+        cursors.insert(String::from(node), toml::Value::Integer(unix_time));
+
+        // Write then rename, so a crash never leaves a truncated cursor file
+        let tmp_file = format!("{}.tmp", self.cursor_file);
+        fs::write(&tmp_file, cursors.to_string())?;
+        fs::rename(&tmp_file, &self.cursor_file)?;
+        Ok(())
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Returns the HTTP status code
+    fn post(&self, body : &str) -> Result<u16> {
+        let mut stream = TcpStream::connect((self.url.host.as_str(), self.url.port))?;
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+
+        let mut request = format!("POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+                Content-Length: {}\r\nConnection: close\r\n", self.url.path, self.url.host, body.len());
+        if let Some(token) = &self.token {
+            request.push_str(&format!("Authorization: Token {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(body.as_bytes())?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let status = response.split_whitespace().nth(1).ok_or("No HTTP status")?;
+        Ok(status.parse::<u16>()?)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Retry on connection failures and server errors, client errors won't get better by retrying. A batch the server
+    /// refuses is Rejected, but a bad endpoint or token is an error as it isn't the batch's fault.
+    fn post_with_retry(&self, body : &str) -> Result<Written> {
+        let mut delay = 1;
+        let mut attempt = 0;
+        loop {
+            let error = match self.post(body) {
+                Ok(status) if (200..300).contains(&status) => return Ok(Written::Accepted),
+                Ok(status) if status == 429 || status >= 500 => WeatherError::from(format!("Influx returned {}", status)),
+                Ok(status) if CONFIG_ERRORS.contains(&status) =>
+                    return Err(WeatherError::from(format!("Influx refused write with {}, check the url and token", status))),
+                Ok(status) => return Ok(Written::Rejected(status)),
+                Err(error) => error
+            };
+            attempt += 1;
+            if attempt > self.retries {
+                return Err(error);
+            }
+            println!("Influx push failed {:?}, retrying in {} secs", error, delay);
+            thread::sleep(Duration::from_secs(delay));
+            delay *= 2;
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Push everything newer than the cursor for the node, a batch at a time. Returns the number of rows pushed. A
+    /// rejected batch is logged and skipped, otherwise the node would be stuck on it for good.
+    pub fn push(&self, node : &str, db_file : &str, db_table : &str) -> Result<usize> {
+        let conn = database::open(db_file)?;
+        let columns = database::get_columns(&conn, db_table)?;
+        let tags = [("station", self.station.as_str()), ("node", node)];
+
+        let mut cursor = self.get_cursor(node)?;
+        let mut pushed = 0;
+        loop {
+            let rows = database::get_rows(&conn, db_table, &columns, cursor, None, Some(self.batch_size))?;
+            let last = match rows.last() {
+                Some(row) => row.unix_time,
+                None => break
+            };
+            let body = line_protocol::encode_rows(db_table, &tags, &columns, &rows);
+            let written = if body.is_empty() { Written::Accepted } else { self.post_with_retry(&body)? };
+            match written {
+                Written::Accepted => pushed += rows.len(),
+                Written::Rejected(status) => println!("Influx rejected write with {}, skipping {}", status, body)
+            }
+            cursor = last;
+            self.set_cursor(node, cursor)?;
+        }
+        Ok(pushed)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    #[test]
+    fn check_parse_url() {
+        assert_eq!(Url::parse("http://influx:8086/api/v2/write?org=home&bucket=weather&precision=s").unwrap(),
+            Url { host : String::from("influx"), port : 8086, path : String::from("/api/v2/write?org=home&bucket=weather&precision=s") });
+        assert_eq!(Url::parse("http://influx").unwrap(),
+            Url { host : String::from("influx"), port : 80, path : String::from("/") });
+        assert!(Url::parse("https://influx/write").is_err());
+        assert!(Url::parse("http://:8086/write").is_err());
+    }
+
+    // A server answering each request with the next status line, returning the bodies it was sent
+    fn serve(statuses : &'static [&'static str]) -> (u16, thread::JoinHandle<Vec<String>>) {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for status in statuses {
+                let (socket, _) = server.accept().unwrap();
+                let mut reader = BufReader::new(&socket);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse::<usize>().unwrap();
+                    }
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+                let mut body = vec![0_u8; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                (&socket).write_all(format!("HTTP/1.1 {}\r\n\r\n", status).as_bytes()).unwrap();
+            }
+            bodies
+        });
+        (port, handle)
+    }
+
+    // A database of three outdoor rows in its own directory, and a pusher for it sending two rows at a time
+    fn setup(name : &str, port : u16) -> (std::path::PathBuf, String, Pusher) {
+        let dir = std::env::temp_dir().join(format!("influx_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let db_file = dir.join("test.db");
+        let cursor_file = dir.join("cursor.toml");
+        let _ = fs::remove_file(&db_file);
+        let _ = fs::remove_file(&cursor_file);
+
+        let conn = database::open(db_file.to_str().unwrap()).unwrap();
+        conn.execute("CREATE TABLE Outdoor (unix_time INT NOT NULL, temperature REAL, PRIMARY KEY(unix_time));
+            INSERT INTO Outdoor VALUES (100, 10.5);
+            INSERT INTO Outdoor VALUES (200, 11.0);
+            INSERT INTO Outdoor VALUES (300, 11.5);").unwrap();
+
+        let pusher = Pusher {
+            url : Url::parse(&format!("http://127.0.0.1:{}/write?db=weather&precision=s", port)).unwrap(),
+            token : None,
+            station : String::from("home"),
+            cursor_file : String::from(cursor_file.to_str().unwrap()),
+            batch_size : 2,
+            retries : 0
+        };
+        (dir, String::from(db_file.to_str().unwrap()), pusher)
+    }
+
+    #[test]
+    fn check_push() {
+        let (port, handle) = serve(&["204 No Content", "204 No Content"]);
+        let (dir, db_file, pusher) = setup("push", port);
+
+        assert_eq!(pusher.push("outdoor", &db_file, "Outdoor").unwrap(), 3);
+        assert_eq!(pusher.get_cursor("outdoor").unwrap(), 300);
+
+        let bodies = handle.join().unwrap();
+        assert_eq!(bodies, ["Outdoor,station=home,node=outdoor temperature=10.5 100\n\
+                             Outdoor,station=home,node=outdoor temperature=11 200\n",
+                            "Outdoor,station=home,node=outdoor temperature=11.5 300\n"]);
+
+        // Nothing new, so nothing sent
+        assert_eq!(pusher.push("outdoor", &db_file, "Outdoor").unwrap(), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn check_rejected() {
+        // The first batch is refused, e.g. a field type conflict, and is skipped
+        let (port, handle) = serve(&["422 Unprocessable Entity", "204 No Content"]);
+        let (dir, db_file, pusher) = setup("rejected", port);
+
+        assert_eq!(pusher.push("outdoor", &db_file, "Outdoor").unwrap(), 1);
+        assert_eq!(pusher.get_cursor("outdoor").unwrap(), 300);
+        assert_eq!(handle.join().unwrap().len(), 2);
+        let _ = fs::remove_dir_all(&dir);
+
+        // A bad token would refuse every batch, so the cursor stays put until it is fixed
+        let (port, handle) = serve(&["401 Unauthorized"]);
+        let (dir, db_file, pusher) = setup("unauthorized", port);
+
+        assert_eq!(format!("{:?}", pusher.push("outdoor", &db_file, "Outdoor").unwrap_err()),
+                   "Influx refused write with 401, check the url and token");
+        assert_eq!(pusher.get_cursor("outdoor").unwrap(), 0);
+        assert_eq!(handle.join().unwrap().len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,91 @@
+//!
+//! InfluxDB line protocol encoding of table rows
+//!
+
+use database::Row;
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn escape(value : &str, specials : &[char]) -> String {
+    let mut result = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if ch == '\\' || specials.contains(&ch) {
+            result.push('\\');
+        }
+        result.push(ch);
+    }
+    result
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn escape_measurement(value : &str) -> String {
+    escape(value, &[',', ' '])
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn escape_key(value : &str) -> String {
+    escape(value, &[',', '=', ' '])
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Encode one row as a line, with a field per non-null column and a timestamp in seconds.
+/// Returns None if there are no fields as a line must have at least one.
+pub fn encode(measurement : &str, tags : &[(&str, &str)], columns : &[String], row : &Row) -> Option<String> {
+    let fields : Vec<String> = columns.iter()
+        .zip(&row.values)
+        .filter_map(|(column, value)| match value {
+            Some(value) if value.is_finite() => Some(format!("{}={}", escape_key(column), value)),
+            _ => None
+        })
+        .collect();
+
+    if fields.is_empty() {
+        return None;
+    }
+
+    let mut line = escape_measurement(measurement);
+    for (key, value) in tags {
+        line.push_str(&format!(",{}={}", escape_key(key), escape_key(value)));
+    }
+    line.push(' ');
+    line.push_str(&fields.join(","));
+    line.push_str(&format!(" {}\n", row.unix_time));
+    Some(line)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub fn encode_rows(measurement : &str, tags : &[(&str, &str)], columns : &[String], rows : &[Row]) -> String {
+    rows.iter()
+        .filter_map(|row| encode(measurement, tags, columns, row))
+        .collect()
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_encode() {
+        let columns = vec![String::from("temperature"), String::from("humidity"), String::from("pressure")];
+        let row = Row { unix_time : 1700000000, values : vec![Some(21.5), None, Some(1013.0)] };
+        let line = encode("Indoor", &[("station", "home"), ("node", "indoor")], &columns, &row);
+        assert_eq!(line.unwrap(), "Indoor,station=home,node=indoor temperature=21.5,pressure=1013 1700000000\n");
+
+        let row = Row { unix_time : 1700000000, values : vec![None, Some(f64::NAN), None] };
+        assert!(encode("Indoor", &[], &columns, &row).is_none());
+    }
+
+    #[test]
+    fn check_escaping() {
+        let columns = vec![String::from("a b")];
+        let row = Row { unix_time : 1, values : vec![Some(1.5)] };
+        let line = encode("My Table,x", &[("station", "back garden=1")], &columns, &row);
+        assert_eq!(line.unwrap(), "My\\ Table\\,x,station=back\\ garden\\=1 a\\ b=1.5 1\n");
+    }
+}
@@ -0,0 +1,118 @@
+//!
+//! Dump or push a node's table as InfluxDB line protocol, e.g. for backfills
+//!
+//! weather-influx dump <node> [--from <unix_time>] [--to <unix_time>]
+//! weather-influx push <node>
+//!
+
+use std::env;
+use std::io::{self, Write};
+use std::process;
+use weather_err::{Result, WeatherError};
+
+use influx::{line_protocol, Pusher};
+
+const USAGE : &str = "Usage: weather-influx dump <node> [--from <unix_time>] [--to <unix_time>]
+       weather-influx push <node>
+Both unix times are inclusive";
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn dump(config : &config::Config, node : &str, from : i64, to : Option<i64>) -> Result<()> {
+    let (db_file, db_table) = config.get_database(node);
+    let conn = database::open(db_file)?;
+    let tags = [("station", config.get_station_name()), ("node", node)];
+
+    let stdout = io::stdout();
+    write_rows(&conn, db_table, &tags, from, to, &mut stdout.lock())
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn write_rows(conn : &sqlite::Connection, db_table : &str, tags : &[(&str, &str)], from : i64, to : Option<i64>,
+              out : &mut impl Write) -> Result<()> {
+    let columns = database::get_columns(conn, db_table)?;
+
+    // Rows come after the cursor, so start one before to include from
+    let mut cursor = from - 1;
+    loop {
+        let rows = database::get_rows(conn, db_table, &columns, cursor, to, Some(10000))?;
+        let last = match rows.last() {
+            Some(row) => row.unix_time,
+            None => break
+        };
+        out.write_all(line_protocol::encode_rows(db_table, tags, &columns, &rows).as_bytes())?;
+        cursor = last;
+    }
+    Ok(())
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn push(config : &config::Config, node : &str) -> Result<()> {
+    let pusher = match Pusher::from_config(config) {
+        Some(pusher) => pusher?,
+        None => return Err(WeatherError::from("No influx url in config file"))
+    };
+    let (db_file, db_table) = config.get_database(node);
+    let pushed = pusher.push(node, db_file, db_table)?;
+    eprintln!("Pushed {} rows for {}", pushed, node);
+    Ok(())
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn run(args : &[String]) -> Result<()> {
+    let (mode, node) = match args {
+        [mode, node, ..] => (mode.as_str(), node.as_str()),
+        _ => return Err(WeatherError::from(USAGE))
+    };
+
+    let mut from = 0;
+    let mut to = None;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or(USAGE)?;
+        match option.as_str() {
+            "--from" => from = value.parse::<i64>()?,
+            "--to" => to = Some(value.parse::<i64>()?),
+            _ => return Err(WeatherError::from(USAGE))
+        }
+    }
+
+    let config = config::Config::new();
+    match mode {
+        "dump" => dump(&config, node, from, to),
+        "push" => push(&config, node),
+        _ => Err(WeatherError::from(USAGE))
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn main() {
+    let args : Vec<String> = env::args().skip(1).collect();
+    if let Err(error) = run(&args) {
+        eprintln!("{:?}", error);
+        process::exit(1);
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_write_rows_from_to() {
+        let conn = sqlite::open(":memory:").unwrap();
+        conn.execute("CREATE TABLE Test (unix_time INT NOT NULL, temperature REAL, PRIMARY KEY(unix_time));
+            INSERT INTO Test VALUES (100, 1.5), (200, 2.5), (300, 3.5), (400, 4.5);").unwrap();
+
+        // The rows at from and to are both written
+        let mut out = Vec::new();
+        write_rows(&conn, "Test", &[("node", "test")], 200, Some(300), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Test,node=test temperature=2.5 200\nTest,node=test temperature=3.5 300\n");
+    }
+}
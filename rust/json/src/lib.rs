@@ -0,0 +1,35 @@
+//!
+//! The little JSON the station writes by hand, shared by the MQTT discovery messages and weather-export
+//!
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// A string quoted and escaped as a JSON string literal
+pub fn string(value : &str) -> String {
+    let mut result = String::from("\"");
+    for ch in value.chars() {
+        match ch {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            ch if (ch as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => result.push(ch)
+        }
+    }
+    result.push('"');
+    result
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_string() {
+        assert_eq!(string("temperature"), "\"temperature\"");
+        assert_eq!(string("say \"hi\"\\\n\t"), "\"say \\\"hi\\\"\\\\\\n\\u0009\"");
+        assert_eq!(string("°C"), "\"°C\"");
+    }
+}
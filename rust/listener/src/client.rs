@@ -0,0 +1,65 @@
+//!
+//! Client side of the listener protocol, for nodes that want another node's latest readings
+//!
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use weather_err::{Result, WeatherError};
+
+const TIMEOUT : Duration = Duration::from_secs(5);
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Most recent value of a column stored after since, with its unix time. None if there are no such rows or the value was NULL.
+pub fn fetch_latest(host : &str, port : u16, column : &str, since : i64) -> Result<Option<(i64, f64)>> {
+    let address = format!("{}:{}", host, port).to_socket_addrs()?
+        .next()
+        .ok_or(WeatherError::from(format!("No address for {}", host)))?;
+
+    let socket = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    let mut stream_out = &socket;
+    stream_out.write_all(format!("{}\n", since).as_bytes())?;
+
+    parse_latest(BufReader::new(&socket), column)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn parse_latest(stream_in : impl BufRead, column : &str) -> Result<Option<(i64, f64)>> {
+    let mut latest = None;
+    let mut time = 0;
+    for line in stream_in.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once('=').ok_or(WeatherError::from(format!("Bad line {}", line)))?;
+        let name = name.trim();
+        if name == "unix_time" {
+            time = value.trim().parse::<i64>()?;
+        } else if name == column {
+            latest = Some((time, value.trim().parse::<f64>()?));
+        }
+    }
+    Ok(latest)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_parse_latest() {
+        let resp = "unix_time = 100\n\ttemperature = 5.5\n\thumidity = 80\nunix_time = 200\n\ttemperature = 6.25\n\n";
+        assert_eq!(parse_latest(resp.as_bytes(), "temperature").unwrap(), Some((200, 6.25)));
+        assert_eq!(parse_latest(resp.as_bytes(), "humidity").unwrap(), Some((100, 80.0)));
+        assert_eq!(parse_latest(resp.as_bytes(), "pressure").unwrap(), None);
+        assert_eq!(parse_latest("\n".as_bytes(), "temperature").unwrap(), None);
+        assert!(parse_latest("rubbish\n".as_bytes(), "temperature").is_err());
+    }
+}
@@ -2,20 +2,26 @@
 //! Listen to connections to read database data
 //!
 
+use std::collections::HashMap;
 use std::net::TcpListener;
-use sqlite;
 use std::io::{BufReader, BufRead, Write};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use weather_err::{Result, WeatherError};
 
+pub mod client;
+
+/// Extra command, returns the response lines without the terminating blank line
+pub type Command = Arc<dyn Fn() -> Result<String> + Send + Sync>;
+
 
 //----------------------------------------------------------------------------------------------------------------------------------
 pub struct Listener {
     port : u16,
     db_connection : Arc<Mutex<sqlite::Connection>>,
     table_name : Option<String>,
-    column_names : Option<Vec<String>>
+    column_names : Option<Vec<String>>,
+    commands : HashMap<String, Command>
 }
 
 //----------------------------------------------------------------------------------------------------------------------------------
@@ -27,12 +33,21 @@ impl Listener {
             port,
             db_connection,
             table_name : None,
-            column_names : None
+            column_names : None,
+            commands : HashMap::new()
         }
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
+    /// Answer another command, e.g. forecast, as well as columns and unix times
+    pub fn add_command(&mut self, name : &str, command : Command) {
+        self.commands.insert(String::from(name), command);
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    #[allow(clippy::never_loop)]
     fn cfg_table(&mut self) -> Result<()> {
         if self.table_name.is_some() {
             return Ok(());
@@ -81,8 +96,11 @@ impl Listener {
             {
                 // println!("{:?}", row);
                 response += &(format!("unix_time = {}", row.read::<i64, _>("unix_time")) + "\n");
+                // Derived values that don't apply are NULL and not sent
                 for col in column_names {
-                    response += &(format!("\t{} = {}", col, row.read::<f64, _>(col.as_str())) + "\n");
+                    if let Some(value) = row.read::<Option<f64>, _>(col.as_str()) {
+                        response += &(format!("\t{} = {}", col, value) + "\n");
+                    }
                 }
             }
         }
@@ -102,7 +120,7 @@ impl Listener {
         for column in column_names {
             response += &(String::from(column) + "\n");
         }
-        response += &("\n");
+        response += "\n";
         println!("{}", response);
         stream.write_all(response.as_bytes())?;
         Ok(())
@@ -110,7 +128,7 @@ impl Listener {
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    fn process_client(column_names: &Vec<String>, table_name: &str, 
+    fn process_client(column_names: &Vec<String>, table_name: &str, commands : &HashMap<String, Command>,
                 db_connection: &Arc<Mutex<sqlite::Connection>>, mut stream_in : impl BufRead, mut stream_out : impl Write) -> Result<()>{
         loop {
             let mut line = String::new();
@@ -123,7 +141,13 @@ impl Listener {
             line = String::from(line.trim());
 
             if line == "columns" {
-                Self::columns_resp(&column_names, &mut stream_out)?;
+                Self::columns_resp(column_names, &mut stream_out)?;
+            } else if let Some(command) = commands.get(&line) {
+                println!("Rcv'd {}", line);
+                match command() {
+                    Ok(response) => stream_out.write_all(format!("{}\n", response).as_bytes())?,
+                    Err(error) => stream_out.write_all(format!("Error {} failed {:?}\n\n", line, error).as_bytes())?
+                }
             } else {
                 match line.parse::<i64>() {
                     Ok(unix_time) => Self::measurement_resp(db_connection, column_names, table_name,unix_time, &mut stream_out)?,
@@ -142,15 +166,16 @@ impl Listener {
         let column_names = self.column_names.clone().unwrap().clone();
         let db_connection = self.db_connection.clone();
         let table_name = self.table_name.clone().unwrap().clone();
+        let commands = self.commands.clone();
 
         thread::spawn(move || { 
-            let _ = Self::task(port, column_names, table_name, db_connection); 
+            let _ = Self::task(port, column_names, table_name, commands, db_connection); 
         });
     }
 
     //------------------------------------------------------------------------------------------------------------------------------
-    pub fn task(port : u16, column_names: Vec<String>, 
-        table_name: String, db_connection : Arc<Mutex<sqlite::Connection>>) -> Result<()> {
+    pub fn task(port : u16, column_names: Vec<String>, table_name: String,
+        commands : HashMap<String, Command>, db_connection : Arc<Mutex<sqlite::Connection>>) -> Result<()> {
 
         // println!("{:?}", self.column_names);
         let sock_addr = format!("0.0.0.0:{}", port);
@@ -164,12 +189,52 @@ impl Listener {
         loop {
             // Asynchronously wait for an inbound socket.
             let (socket, _) = listener.accept()?;
+            metrics::LISTENER_CONNECTIONS.inc();
 
             let stream_in = BufReader::new(&socket);
             let stream_out = &socket;
 
-            Self::process_client(&column_names, &table_name, &db_connection, stream_in, stream_out)?
+            Self::process_client(&column_names, &table_name, &commands, &db_connection, stream_in, stream_out)?
         }
     }
 }
 
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_process_client() {
+        let conn = sqlite::open(":memory:").unwrap();
+        conn.execute("CREATE TABLE Test (unix_time INT NOT NULL, temperature REAL, dew_point REAL, PRIMARY KEY(unix_time));
+            INSERT INTO Test VALUES (100, 20.5, NULL);").unwrap();
+        let db_connection = Arc::new(Mutex::new(conn));
+        let columns = vec![String::from("temperature"), String::from("dew_point")];
+
+        let mut commands : HashMap<String, Command> = HashMap::new();
+        commands.insert(String::from("forecast"), Arc::new(|| Ok(String::from("\tletter = A\n"))));
+        commands.insert(String::from("broken"), Arc::new(|| Err(WeatherError::from("no history"))));
+
+        let mut out = Vec::new();
+        let requests = "columns\n0\nforecast\nbroken\nrubbish\n";
+        Listener::process_client(&columns, "Test", &commands, &db_connection, requests.as_bytes(), &mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let mut lines = out.lines();
+        assert_eq!(lines.next(), Some("temperature"));
+        assert_eq!(lines.next(), Some("dew_point"));
+        assert_eq!(lines.next(), Some(""));
+        // NULLs are left out
+        assert_eq!(lines.next(), Some("unix_time = 100"));
+        assert_eq!(lines.next(), Some("\ttemperature = 20.5"));
+        assert_eq!(lines.next(), Some(""));
+        assert_eq!(lines.next(), Some("\tletter = A"));
+        assert_eq!(lines.next(), Some(""));
+        assert!(lines.next().unwrap().starts_with("Error broken failed"));
+        assert_eq!(lines.next(), Some(""));
+        assert_eq!(lines.next(), Some("Error unknown command rubbish"));
+    }
+}
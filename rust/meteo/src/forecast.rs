@@ -0,0 +1,295 @@
+//!
+//! Pressure tendency and a Zambretti style local forecast
+//!
+//! The tendency follows the WMO pressure characteristic (code table 0200) over the last three hours.
+//! The forecast is the Negretti and Zambretti algorithm as usually implemented for electronic barometers,
+//! with the sea level pressure adjusted for wind direction and season.
+//!
+
+use std::fmt;
+
+const THREE_HOURS : i64 = 3 * 60 * 60;
+
+// How far from the wanted time a reading can be and still be used
+const TOLERANCE : i64 = 30 * 60;
+
+// Changes smaller than this, in hPa, over half the period count as steady
+const STEADY : f32 = 0.2;
+
+// Change over three hours, in hPa, for Zambretti to treat pressure as rising or falling
+const TREND : f32 = 1.6;
+
+const BARO_TOP : f32 = 1050.0;
+const BARO_BOTTOM : f32 = 950.0;
+
+const FORECASTS : [&str; 26] = [
+    "Settled fine", "Fine weather", "Becoming fine", "Fine, becoming less settled", "Fine, possible showers",
+    "Fairly fine, improving", "Fairly fine, possible showers early", "Fairly fine, showery later",
+    "Showery early, improving", "Changeable, mending", "Fairly fine, showers likely", "Rather unsettled clearing later",
+    "Unsettled, probably improving", "Showery, bright intervals", "Showery, becoming less settled", "Changeable, some rain",
+    "Unsettled, short fine intervals", "Unsettled, rain later", "Unsettled, some rain", "Mostly very unsettled",
+    "Occasional rain, worsening", "Rain at times, very unsettled", "Rain at frequent intervals", "Rain, very unsettled",
+    "Stormy, may improve", "Stormy, much rain"
+];
+
+const RISING : [usize; 22] = [25, 25, 25, 24, 24, 19, 16, 12, 11, 9, 8, 6, 5, 2, 1, 1, 0, 0, 0, 0, 0, 0];
+const STEADY_OPTIONS : [usize; 22] = [25, 25, 25, 25, 25, 25, 23, 23, 22, 18, 15, 13, 10, 4, 1, 1, 0, 0, 0, 0, 0, 0];
+const FALLING : [usize; 22] = [25, 25, 25, 25, 25, 25, 25, 25, 23, 23, 21, 20, 17, 14, 7, 3, 1, 1, 1, 0, 0, 0];
+
+// Adjustment, as a percentage of the barometer range, for wind from each of 16 points starting at north, northern hemisphere.
+// In the southern hemisphere the table is rotated by 180 degrees.
+const WIND_ADJUST : [f32; 16] = [6.0, 5.0, 5.0, 2.0, -0.5, -2.0, -5.0, -8.5, -12.0, -10.0, -6.0, -4.5, -3.0, -0.5, 1.5, 3.0];
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Half {
+    Rising,
+    Steady,
+    Falling
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Half {
+    fn new(change : f32) -> Self {
+        if change >= STEADY {
+            Half::Rising
+        } else if change <= -STEADY {
+            Half::Falling
+        } else {
+            Half::Steady
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Pressure change over the last three hours
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Tendency {
+    change : f32,
+    characteristic : u8
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Tendency {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// From the pressure three hours ago, one and a half hours ago and now
+    pub fn new(start : f32, middle : f32, end : f32) -> Self {
+        let change = end - start;
+        let first = middle - start;
+        let second = end - middle;
+
+        let characteristic = match (Half::new(first), Half::new(second)) {
+            (Half::Rising, Half::Falling) if change >= 0.0 => 0,
+            (Half::Rising, Half::Falling) => 8,
+            (Half::Falling, Half::Rising) if change <= 0.0 => 5,
+            (Half::Falling, Half::Rising) => 3,
+            (Half::Rising, Half::Steady) => 1,
+            (Half::Steady, Half::Rising) => 3,
+            (Half::Falling, Half::Steady) => 6,
+            (Half::Steady, Half::Falling) => 8,
+            (Half::Steady, Half::Steady) => 4,
+            (Half::Rising, Half::Rising) if second > 1.5 * first => 3,
+            (Half::Rising, Half::Rising) if second < 0.5 * first => 1,
+            (Half::Rising, Half::Rising) => 2,
+            (Half::Falling, Half::Falling) if second < 1.5 * first => 8,
+            (Half::Falling, Half::Falling) if second > 0.5 * first => 6,
+            (Half::Falling, Half::Falling) => 7
+        };
+        Self { change, characteristic }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// From (unix_time, pressure) readings, oldest first. None if there are no readings close enough to three hours
+    /// and one and a half hours before the latest.
+    pub fn from_history(history : &[(i64, f32)]) -> Option<Self> {
+        let (end_time, end) = *history.last()?;
+        let nearest = |time : i64| {
+            history.iter()
+                .filter(|(unix_time, _)| (unix_time - time).abs() <= TOLERANCE)
+                .min_by_key(|(unix_time, _)| (unix_time - time).abs())
+                .map(|(_, pressure)| *pressure)
+        };
+        let start = nearest(end_time - THREE_HOURS)?;
+        let middle = nearest(end_time - THREE_HOURS / 2)?;
+        Some(Self::new(start, middle, end))
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Change in hPa over the three hours
+    pub fn get_change(&self) -> f32 {
+        self.change
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// WMO code table 0200 characteristic, 0 to 8
+    pub fn get_characteristic(&self) -> u8 {
+        self.characteristic
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_description(&self) -> &'static str {
+        match self.characteristic {
+            0 => "Increasing, then decreasing",
+            1 => "Increasing, then steady",
+            2 => "Increasing",
+            3 => "Decreasing or steady, then increasing",
+            4 => "Steady",
+            5 => "Decreasing, then increasing",
+            6 => "Decreasing, then steady",
+            7 => "Decreasing",
+            _ => "Steady or increasing, then decreasing"
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Forecast {
+    letter : char,
+    text : String
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Forecast {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// msl_pressure in hPa, month 1 to 12, wind_direction in degrees the wind is coming from
+    pub fn new(msl_pressure : f32, tendency : &Tendency, month : u32, wind_direction : Option<f32>, southern : bool) -> Self {
+        let range = BARO_TOP - BARO_BOTTOM;
+        let mut pressure = msl_pressure;
+
+        if let Some(direction) = wind_direction {
+            let mut point = ((direction.rem_euclid(360.0) / 22.5).round() as usize) % 16;
+            if southern {
+                point = (point + 8) % 16;
+            }
+            pressure += WIND_ADJUST[point] / 100.0 * range;
+        }
+
+        // Summer, when rising or falling pressure matters more
+        let summer = (4..=9).contains(&month) != southern;
+        if summer {
+            if tendency.change >= TREND {
+                pressure += 7.0 / 100.0 * range;
+            } else if tendency.change <= -TREND {
+                pressure -= 7.0 / 100.0 * range;
+            }
+        }
+
+        let option = ((pressure.min(BARO_TOP - 1.0) - BARO_BOTTOM) / (range / 22.0)).floor();
+        let exceptional = !(0.0..22.0).contains(&option);
+        let option = option.clamp(0.0, 21.0) as usize;
+
+        let idx = if tendency.change >= TREND {
+            RISING[option]
+        } else if tendency.change <= -TREND {
+            FALLING[option]
+        } else {
+            STEADY_OPTIONS[option]
+        };
+
+        let text = if exceptional {
+            format!("Exceptional weather, {}", FORECASTS[idx])
+        } else {
+            String::from(FORECASTS[idx])
+        };
+        Self {
+            letter : (b'A' + idx as u8) as char,
+            text
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Zambretti letter, A for settled fine to Z for stormy
+    pub fn get_letter(&self) -> char {
+        self.letter
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl fmt::Display for Forecast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.letter, self.text)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn check_characteristic() {
+        assert_eq!(Tendency::new(1010.0, 1010.1, 1009.9).get_characteristic(), 4);
+        assert_eq!(Tendency::new(1010.0, 1011.0, 1012.0).get_characteristic(), 2);
+        assert_eq!(Tendency::new(1010.0, 1011.5, 1011.6).get_characteristic(), 1);
+        assert_eq!(Tendency::new(1010.0, 1010.1, 1011.0).get_characteristic(), 3);
+        assert_eq!(Tendency::new(1010.0, 1011.0, 1010.5).get_characteristic(), 0);
+        assert_eq!(Tendency::new(1010.0, 1011.0, 1009.5).get_characteristic(), 8);
+        assert_eq!(Tendency::new(1010.0, 1009.0, 1008.0).get_characteristic(), 7);
+        assert_eq!(Tendency::new(1010.0, 1008.5, 1008.4).get_characteristic(), 6);
+        assert_eq!(Tendency::new(1010.0, 1009.9, 1009.0).get_characteristic(), 8);
+        assert_eq!(Tendency::new(1010.0, 1009.0, 1009.5).get_characteristic(), 5);
+        assert_eq!(Tendency::new(1010.0, 1009.0, 1010.5).get_characteristic(), 3);
+        assert_abs_diff_eq!(Tendency::new(1010.0, 1009.0, 1008.0).get_change(), -2.0);
+    }
+
+    #[test]
+    fn check_from_history() {
+        let history : Vec<(i64, f32)> = (0..13).map(|idx| (idx * 900, 1000.0 + idx as f32 * 0.25)).collect();
+        let tendency = Tendency::from_history(&history).unwrap();
+        assert_abs_diff_eq!(tendency.get_change(), 3.0);
+        assert_eq!(tendency.get_characteristic(), 2);
+
+        // Not enough history
+        assert!(Tendency::from_history(&history[6..]).is_none());
+        assert!(Tendency::from_history(&[]).is_none());
+    }
+
+    #[test]
+    fn check_forecast() {
+        let steady = Tendency::new(1020.0, 1020.0, 1020.0);
+        let rising = Tendency::new(1008.0, 1009.0, 1010.0);
+        let falling = Tendency::new(1000.0, 999.0, 998.0);
+
+        let forecast = Forecast::new(1030.0, &steady, 1, None, false);
+        assert_eq!(forecast.get_letter(), 'A');
+        assert_eq!(forecast.get_text(), "Settled fine");
+
+        assert_eq!(Forecast::new(1005.0, &steady, 1, None, false).get_letter(), 'K');
+        assert_eq!(Forecast::new(1010.0, &rising, 1, None, false).get_letter(), 'C');
+        assert_eq!(Forecast::new(998.0, &falling, 1, None, false).get_letter(), 'V');
+        assert_eq!(Forecast::new(998.0, &falling, 1, None, false).get_text(), "Rain at times, very unsettled");
+
+        // Summer makes rising pressure more significant, and summer is the other way round in the south
+        assert_eq!(Forecast::new(1010.0, &rising, 7, None, false).get_letter(), 'B');
+        assert_eq!(Forecast::new(1010.0, &rising, 1, None, true).get_letter(), 'B');
+
+        // Southerly winds are bad news in the north, northerly winds good
+        assert_eq!(Forecast::new(1005.0, &steady, 1, Some(180.0), false).get_letter(), 'S');
+        assert_eq!(Forecast::new(1005.0, &steady, 1, Some(5.0), false).get_letter(), 'E');
+        assert_eq!(Forecast::new(1005.0, &steady, 1, Some(180.0), true).get_letter(), 'E');
+
+        assert_eq!(Forecast::new(940.0, &falling, 1, None, false).get_text(), "Exceptional weather, Stormy, much rain");
+    }
+}
@@ -0,0 +1,343 @@
+//!
+//! Meteorological quantities derived from temperature, humidity, wind speed and pressure
+//!
+
+use std::fmt;
+
+pub mod forecast;
+
+// Magnus coefficients, Sonntag 1990, over water and over ice
+const MAGNUS_A : f32 = 17.62;
+const MAGNUS_B : f32 = 243.12;
+const MAGNUS_ICE_A : f32 = 22.46;
+const MAGNUS_ICE_B : f32 = 272.62;
+const MAGNUS_C : f32 = 6.112;
+
+const STANDARD_GRAVITY : f32 = 9.80665;
+const GAS_CONSTANT_DRY_AIR : f32 = 287.05;
+const LAPSE_RATE : f32 = 0.0065;
+
+// Upper limits in m/s of Beaufort forces 0 to 11, anything above is 12
+const BEAUFORT_LIMITS : [f32; 12] = [0.2, 1.5, 3.3, 5.4, 7.9, 10.7, 13.8, 17.1, 20.7, 24.4, 28.4, 32.6];
+
+/// Column names for the derived quantities, in the order given by Derived::values
+pub const COLUMNS : [&str; 7] = ["dew_point", "frost_point", "absolute_humidity", "heat_index", "humidex",
+                                 "wind_chill", "apparent_temperature"];
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Saturation vapour pressure over water in hPa
+pub fn saturation_vapour_pressure(temp : f32) -> f32 {
+    MAGNUS_C * (MAGNUS_A * temp / (MAGNUS_B + temp)).exp()
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Vapour pressure in hPa
+pub fn vapour_pressure(temp : f32, humidity : f32) -> f32 {
+    humidity / 100.0 * saturation_vapour_pressure(temp)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Dew point in C, using the Magnus formula
+pub fn dew_point(temp : f32, humidity : f32) -> f32 {
+    let gamma = (humidity / 100.0).ln() + MAGNUS_A * temp / (MAGNUS_B + temp);
+    MAGNUS_B * gamma / (MAGNUS_A - gamma)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Frost point in C, the temperature at which air becomes saturated with respect to ice.
+/// Only meaningful below freezing, so None if it is above 0C.
+pub fn frost_point(temp : f32, humidity : f32) -> Option<f32> {
+    let ln_ratio = (vapour_pressure(temp, humidity) / MAGNUS_C).ln();
+    let frost_point = MAGNUS_ICE_B * ln_ratio / (MAGNUS_ICE_A - ln_ratio);
+    if frost_point < 0.0 { Some(frost_point) } else { None }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Absolute humidity in g/m3
+pub fn absolute_humidity(temp : f32, humidity : f32) -> f32 {
+    216.7 * vapour_pressure(temp, humidity) / (273.15 + temp)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Heat index in C, using the US National Weather Service algorithm (Rothfusz regression with adjustments).
+/// None below 80F (26.7C), where the NWS tables start.
+pub fn heat_index(temp : f32, humidity : f32) -> Option<f32> {
+    let t = temp * 9.0 / 5.0 + 32.0;
+    let rh = humidity;
+    if t < 80.0 {
+        return None;
+    }
+
+    let mut hi = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    if (hi + t) / 2.0 >= 80.0 {
+        hi = -42.379 + 2.049_015_2 * t + 10.143_331 * rh - 0.224_755_4 * t * rh - 0.006_837_83 * t * t
+            - 0.054_817_17 * rh * rh + 0.001_228_74 * t * t * rh + 0.000_852_82 * t * rh * rh
+            - 0.000_001_99 * t * t * rh * rh;
+        if rh < 13.0 && t <= 112.0 {
+            hi -= ((13.0 - rh) / 4.0) * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+        } else if rh > 85.0 && t <= 87.0 {
+            hi += ((rh - 85.0) / 10.0) * ((87.0 - t) / 5.0);
+        }
+    }
+    Some((hi - 32.0) * 5.0 / 9.0)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Humidex, as used by Environment Canada. Only reported when it is 25 or more with the temperature at least 20C.
+pub fn humidex(temp : f32, humidity : f32) -> Option<f32> {
+    let dew_point = dew_point(temp, humidity);
+    let e = 6.11 * (5417.753 * (1.0 / 273.16 - 1.0 / (273.15 + dew_point))).exp();
+    let humidex = temp + 0.5555 * (e - 10.0);
+    if temp >= 20.0 && humidex >= 25.0 { Some(humidex) } else { None }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Wind chill in C, using the North American / UK Met Office formula with wind speed in m/s.
+/// None when the formula is not defined, above 10C or wind below 4.8 km/h.
+pub fn wind_chill(temp : f32, wind_speed : f32) -> Option<f32> {
+    let v = wind_speed * 3.6;
+    if temp > 10.0 || v < 4.8 {
+        return None;
+    }
+    let v_pow = v.powf(0.16);
+    Some(13.12 + 0.6215 * temp - 11.37 * v_pow + 0.3965 * temp * v_pow)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Apparent temperature in C, the Australian Bureau of Meteorology version of Steadman's formula (no solar term)
+pub fn apparent_temperature(temp : f32, humidity : f32, wind_speed : f32) -> f32 {
+    let e = humidity / 100.0 * 6.105 * (17.27 * temp / (237.7 + temp)).exp();
+    temp + 0.33 * e - 0.70 * wind_speed - 4.0
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Beaufort force for a mean wind speed in m/s, using the WMO limits rounded to 0.1 m/s
+pub fn beaufort(wind_speed : f32) -> u8 {
+    BEAUFORT_LIMITS.iter().take_while(|limit| wind_speed > **limit).count() as u8
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Acceleration due to gravity at the given latitude in m/s2, the standard value if unknown
+pub fn gravity(latitude : Option<f32>) -> f32 {
+    match latitude {
+        Some(latitude) => {
+            let sin = latitude.to_radians().sin();
+            let sin_2 = (2.0 * latitude).to_radians().sin();
+            9.780_327 * (1.0 + 0.005_302_4 * sin * sin - 0.000_005_8 * sin_2 * sin_2)
+        },
+        None => STANDARD_GRAVITY
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Mean sea level pressure in hPa from the station pressure, using the hypsometric formula.
+/// The air column below the station is taken to be at the outdoor temperature plus the standard lapse rate over half its height.
+/// Without an outdoor temperature the standard atmosphere temperature at the station's altitude is used.
+pub fn msl_pressure(pressure : f32, altitude : f32, temp : Option<f32>, latitude : Option<f32>) -> f32 {
+    let temp = temp.unwrap_or(15.0 - LAPSE_RATE * altitude);
+    let mean_temp = temp + 273.15 + LAPSE_RATE * altitude / 2.0;
+    pressure * (gravity(latitude) * altitude / (GAS_CONSTANT_DRY_AIR * mean_temp)).exp()
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// All the derived quantities for one reading. Those that don't apply, or need a wind speed that isn't known, are None.
+pub struct Derived {
+    pub dew_point : Option<f32>,
+    pub frost_point : Option<f32>,
+    pub absolute_humidity : Option<f32>,
+    pub heat_index : Option<f32>,
+    pub humidex : Option<f32>,
+    pub wind_chill : Option<f32>,
+    pub apparent_temperature : Option<f32>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Derived {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(temp : f32, humidity : f32, wind_speed : Option<f32>) -> Self {
+        // Humidity of 0 makes no sense and would give -inf
+        let humid = humidity > 0.0;
+        Self {
+            dew_point : if humid { Some(dew_point(temp, humidity)) } else { None },
+            frost_point : if humid { frost_point(temp, humidity) } else { None },
+            absolute_humidity : Some(absolute_humidity(temp, humidity)),
+            heat_index : heat_index(temp, humidity),
+            humidex : if humid { humidex(temp, humidity) } else { None },
+            wind_chill : wind_speed.and_then(|speed| wind_chill(temp, speed)),
+            apparent_temperature : wind_speed.map(|speed| apparent_temperature(temp, humidity, speed))
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Outdoor readings, with the average wind speed over the sample period
+    pub fn from_outdoor(summary : &sht31::Summary, wind_speed : f32) -> Self {
+        Self::new(summary.get_temperature(), summary.get_humidity(), Some(wind_speed))
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Column name and value pairs, for storing
+    pub fn values(&self) -> [(&'static str, Option<f32>); 7] {
+        let values = [self.dew_point, self.frost_point, self.absolute_humidity, self.heat_index, self.humidex,
+                      self.wind_chill, self.apparent_temperature];
+        std::array::from_fn(|idx| (COLUMNS[idx], values[idx]))
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl From<&bme688::Summary> for Derived {
+    fn from(summary : &bme688::Summary) -> Self {
+        Self::new(summary.get_temperature(), summary.get_humidity(), None)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl From<&sht31::Summary> for Derived {
+    fn from(summary : &sht31::Summary) -> Self {
+        Self::new(summary.get_temperature(), summary.get_humidity(), None)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl fmt::Display for Derived {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut sep = "";
+        for (name, value) in self.values() {
+            if let Some(value) = value {
+                write!(f, "{}{} {:.1}", sep, name, value)?;
+                sep = " ";
+            }
+        }
+        Ok(())
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    fn f_to_c(f : f32) -> f32 {
+        (f - 32.0) * 5.0 / 9.0
+    }
+
+    #[test]
+    fn check_dew_point() {
+        // Reference values from standard dew point tables
+        assert_abs_diff_eq!(dew_point(20.0, 50.0), 9.3, epsilon = 0.1);
+        assert_abs_diff_eq!(dew_point(25.0, 60.0), 16.7, epsilon = 0.1);
+        assert_abs_diff_eq!(dew_point(30.0, 70.0), 23.9, epsilon = 0.1);
+        assert_abs_diff_eq!(dew_point(0.0, 100.0), 0.0, epsilon = 0.01);
+        assert_abs_diff_eq!(dew_point(-10.0, 80.0), -12.8, epsilon = 0.1);
+    }
+
+    #[test]
+    fn check_frost_point() {
+        assert_abs_diff_eq!(frost_point(-10.0, 80.0).unwrap(), -11.4, epsilon = 0.1);
+        assert_abs_diff_eq!(frost_point(-5.0, 90.0).unwrap(), -5.7, epsilon = 0.1);
+        assert!(frost_point(10.0, 80.0).is_none());
+    }
+
+    #[test]
+    fn check_absolute_humidity() {
+        assert_abs_diff_eq!(absolute_humidity(20.0, 50.0), 8.6, epsilon = 0.1);
+        assert_abs_diff_eq!(absolute_humidity(30.0, 100.0), 30.3, epsilon = 0.2);
+        assert_abs_diff_eq!(absolute_humidity(0.0, 100.0), 4.85, epsilon = 0.05);
+    }
+
+    #[test]
+    fn check_heat_index() {
+        // From the NWS heat index chart, in F
+        assert_abs_diff_eq!(heat_index(f_to_c(80.0), 40.0).unwrap(), f_to_c(80.0), epsilon = 0.5);
+        assert_abs_diff_eq!(heat_index(f_to_c(90.0), 70.0).unwrap(), f_to_c(106.0), epsilon = 0.5);
+        assert_abs_diff_eq!(heat_index(f_to_c(96.0), 65.0).unwrap(), f_to_c(121.0), epsilon = 0.5);
+        assert_abs_diff_eq!(heat_index(f_to_c(100.0), 50.0).unwrap(), f_to_c(118.0), epsilon = 0.5);
+        assert_abs_diff_eq!(heat_index(f_to_c(84.0), 90.0).unwrap(), f_to_c(98.0), epsilon = 0.5);
+        assert!(heat_index(20.0, 90.0).is_none());
+    }
+
+    #[test]
+    fn check_humidex() {
+        // Environment Canada example, 30C with a dew point of 15C gives 34
+        let humidity = 100.0 * saturation_vapour_pressure(15.0) / saturation_vapour_pressure(30.0);
+        assert_abs_diff_eq!(humidex(30.0, humidity).unwrap(), 34.0, epsilon = 0.5);
+        assert!(humidex(15.0, 90.0).is_none());
+    }
+
+    #[test]
+    fn check_wind_chill() {
+        // From the Environment Canada wind chill table, wind in km/h
+        assert_abs_diff_eq!(wind_chill(5.0, 10.0 / 3.6).unwrap(), 3.0, epsilon = 0.5);
+        assert_abs_diff_eq!(wind_chill(-10.0, 20.0 / 3.6).unwrap(), -18.0, epsilon = 0.5);
+        assert_abs_diff_eq!(wind_chill(-20.0, 30.0 / 3.6).unwrap(), -33.0, epsilon = 0.5);
+        assert_abs_diff_eq!(wind_chill(-30.0, 50.0 / 3.6).unwrap(), -49.0, epsilon = 0.5);
+        assert!(wind_chill(15.0, 10.0).is_none());
+        assert!(wind_chill(0.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn check_apparent_temperature() {
+        assert_abs_diff_eq!(apparent_temperature(30.0, 50.0, 2.0), 31.6, epsilon = 0.1);
+        assert_abs_diff_eq!(apparent_temperature(20.0, 50.0, 0.0), 19.8, epsilon = 0.1);
+    }
+
+    #[test]
+    fn check_beaufort() {
+        assert_eq!(beaufort(0.0), 0);
+        assert_eq!(beaufort(0.2), 0);
+        assert_eq!(beaufort(0.3), 1);
+        assert_eq!(beaufort(5.0), 3);
+        assert_eq!(beaufort(17.2), 8);
+        assert_eq!(beaufort(32.6), 11);
+        assert_eq!(beaufort(40.0), 12);
+    }
+
+    #[test]
+    fn check_msl_pressure() {
+        assert_abs_diff_eq!(msl_pressure(1013.25, 0.0, Some(15.0), None), 1013.25, epsilon = 0.01);
+        assert_abs_diff_eq!(msl_pressure(1000.0, 100.0, Some(15.0), None), 1011.9, epsilon = 0.1);
+        assert_abs_diff_eq!(msl_pressure(950.0, 500.0, Some(10.0), Some(52.0)), 1008.8, epsilon = 0.1);
+        assert_abs_diff_eq!(msl_pressure(950.0, 500.0, Some(10.0), Some(0.0)), 1008.6, epsilon = 0.1);
+
+        // The standard atmosphere pressure at 300m comes back to the standard sea level pressure
+        assert_abs_diff_eq!(msl_pressure(977.73, 300.0, None, None), 1013.25, epsilon = 0.1);
+        assert_abs_diff_eq!(gravity(Some(45.0)), 9.806, epsilon = 0.001);
+    }
+
+    #[test]
+    fn check_derived() {
+        let derived = Derived::from_outdoor(&sht31::Summary::new(-5.0, 90.0), 5.0);
+        assert!(derived.dew_point.is_some());
+        assert!(derived.frost_point.is_some());
+        assert!(derived.heat_index.is_none());
+        assert!(derived.wind_chill.is_some());
+        assert!(derived.apparent_temperature.is_some());
+
+        let derived = Derived::from(&bme688::Summary::new(21.0, 0.0, 1013.0));
+        assert!(derived.dew_point.is_none());
+        assert!(derived.wind_chill.is_none());
+        assert!(derived.apparent_temperature.is_none());
+        assert_eq!(derived.values()[0].0, "dew_point");
+    }
+}
@@ -0,0 +1,493 @@
+//!
+//! Prometheus metrics for the weather station processes
+//!
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{BufReader, BufRead, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use weather_err::Result;
+
+// How long a scraper gets to send its request and take the response
+const CLIENT_TIMEOUT : Duration = Duration::from_secs(10);
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub static TEMPERATURE : Gauge = Gauge::new("weather_temperature_celsius", "Latest temperature reading");
+pub static HUMIDITY : Gauge = Gauge::new("weather_humidity_percent", "Latest relative humidity reading");
+pub static PRESSURE : Gauge = Gauge::new("weather_pressure_hpa", "Latest station pressure reading");
+pub static WIND_DIRECTION : Gauge = Gauge::new("weather_wind_direction_degrees", "Latest vector averaged wind direction");
+pub static RAIN_RATE : Gauge = Gauge::new("weather_rain_rate_mm_per_hour", "Rain rate over the last sample period");
+pub static RAIN_DAILY : Gauge = Gauge::new("weather_rain_daily_mm", "Rainfall since the start of the rain day");
+pub static GAS_RESISTANCE : Gauge = Gauge::with_labels("weather_gas_resistance_ohms",
+        "Mean gas resistance over the last sample period at each heater step", &["step"]);
+pub static MSL_PRESSURE : Gauge = Gauge::new("weather_msl_pressure_hpa", "Latest pressure reduced to mean sea level");
+pub static SOLAR_RADIATION : Gauge = Gauge::new("weather_solar_radiation_watts_per_square_metre",
+        "Estimated solar radiation over the last sample period");
+pub static ILLUMINANCE : Gauge = Gauge::new("weather_illuminance_lux", "Illuminance over the last sample period");
+pub static WIND_SPEED : Gauge = Gauge::with_labels("weather_wind_speed_metres_per_second",
+        "Wind speed over the last sample period", &["stat"]);
+pub static COLLECTED : Gauge = Gauge::with_labels("weather_collected_value",
+        "Latest value collected from a node", &["node", "column"]);
+pub static READING : Gauge = Gauge::with_labels("weather_reading",
+        "Latest reading of each channel of a station's sensors", &["sensor", "channel", "unit"]);
+pub static LAST_TICK : Gauge = Gauge::new("weather_last_tick_timestamp_seconds", "Unix time of the last sample tick");
+
+pub static SENSOR_STATUS : Gauge = Gauge::with_labels("weather_sensor_status",
+        "Status register bits from the last read, 1 if set", &["sensor", "bit"]);
+pub static SENSOR_INFO : Gauge = Gauge::with_labels("weather_sensor_info",
+        "Always 1, labelled with each sensor's address and serial number", &["sensor", "address", "serial"]);
+pub static SENSOR_RESETS : Counter = Counter::new("weather_sensor_resets_total",
+        "Sensor resets spotted in status registers");
+pub static I2C_ERRORS : Counter = Counter::new("weather_i2c_errors_total", "I2C transfers that failed");
+pub static CRC_FAILURES : Counter = Counter::new("weather_crc_failures_total", "Sensor responses with a bad checksum");
+pub static WIND_LINES_PARSED : Counter = Counter::new("weather_wind_lines_parsed_total",
+        "Lines from the wind device that were parsed");
+pub static WIND_LINES_DROPPED : Counter = Counter::new("weather_wind_lines_dropped_total",
+        "Lines from the wind device that could not be parsed");
+pub static SERIAL_CHECKSUM_FAILURES : Counter = Counter::new("weather_serial_checksum_failures_total",
+        "Lines from the Arduino with a bad checksum");
+pub static SERIAL_LINES_LOST : Counter = Counter::new("weather_serial_lines_lost_total",
+        "Lines from the Arduino missing according to the sequence numbers");
+pub static SERIAL_REOPENS : Counter = Counter::new("weather_serial_reopens_total",
+        "Times the wind device was reopened after it failed or disappeared");
+pub static QC_FAILURES : Counter = Counter::with_labels("weather_qc_failures_total",
+        "Readings that failed a quality check and were stored as NULL", &["channel", "check"]);
+pub static LISTENER_CONNECTIONS : Counter = Counter::new("weather_listener_connections_total",
+        "Connections accepted by the database listener");
+
+static REGISTRY : &[&(dyn Metric + Sync)] = &[
+    &TEMPERATURE, &HUMIDITY, &PRESSURE, &GAS_RESISTANCE, &MSL_PRESSURE, &WIND_SPEED, &WIND_DIRECTION, &RAIN_RATE, &RAIN_DAILY,
+    &SOLAR_RADIATION, &ILLUMINANCE,
+    &COLLECTED, &READING, &LAST_TICK, &SENSOR_STATUS, &SENSOR_INFO, &SENSOR_RESETS,
+    &I2C_ERRORS, &CRC_FAILURES, &WIND_LINES_PARSED, &WIND_LINES_DROPPED,
+    &SERIAL_CHECKSUM_FAILURES, &SERIAL_LINES_LOST, &SERIAL_REOPENS, &QC_FAILURES, &LISTENER_CONNECTIONS
+];
+
+static START_TIME : AtomicU64 = AtomicU64::new(0);
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+trait Metric {
+    fn render(&self, out : &mut String);
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// A counter, optionally split by labels. Labelled counters are only exported once counted.
+pub struct Counter {
+    name : &'static str,
+    help : &'static str,
+    value : AtomicU64,
+    labels : &'static [&'static str],
+    labelled : Mutex<BTreeMap<Vec<String>, u64>>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Counter {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub const fn new(name : &'static str, help : &'static str) -> Self {
+        Self::with_labels(name, help, &[])
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub const fn with_labels(name : &'static str, help : &'static str, labels : &'static [&'static str]) -> Self {
+        Self {
+            name,
+            help,
+            value : AtomicU64::new(0),
+            labels,
+            labelled : Mutex::new(BTreeMap::new())
+        }
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn add(&self, count : u64) {
+        self.value.fetch_add(count, Ordering::Relaxed);
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn inc_labelled(&self, label_values : &[&str]) {
+        if label_values.len() != self.labels.len() {
+            panic!("Counter {} expects {} label values", self.name, self.labels.len());
+        }
+        let key = label_values.iter().map(|x| String::from(*x)).collect();
+        let mut labelled = self.labelled.lock().expect("Unexpected failure to lock mutex");
+        *(*labelled).entry(key).or_insert(0) += 1;
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_labelled(&self, label_values : &[&str]) -> u64 {
+        let key : Vec<String> = label_values.iter().map(|x| String::from(*x)).collect();
+        let labelled = self.labelled.lock().expect("Unexpected failure to lock mutex");
+        (*labelled).get(&key).copied().unwrap_or(0)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Metric for Counter {
+    fn render(&self, out : &mut String) {
+        if self.labels.is_empty() {
+            let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+            let _ = writeln!(out, "# TYPE {} counter", self.name);
+            let _ = writeln!(out, "{} {}", self.name, self.get());
+            return;
+        }
+        let labelled = self.labelled.lock().expect("Unexpected failure to lock mutex");
+        if (*labelled).is_empty() {
+            return;
+        }
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} counter", self.name);
+        for (label_values, value) in (*labelled).iter() {
+            let labels : Vec<String> = self.labels.iter()
+                .zip(label_values)
+                .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+                .collect();
+            let _ = writeln!(out, "{}{{{}}} {}", self.name, labels.join(","), value);
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// A gauge, optionally split by labels. Gauges that have never been set are not exported.
+pub struct Gauge {
+    name : &'static str,
+    help : &'static str,
+    labels : &'static [&'static str],
+    values : Mutex<BTreeMap<Vec<String>, f64>>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Gauge {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub const fn new(name : &'static str, help : &'static str) -> Self {
+        Self::with_labels(name, help, &[])
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub const fn with_labels(name : &'static str, help : &'static str, labels : &'static [&'static str]) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            values : Mutex::new(BTreeMap::new())
+        }
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn set(&self, value : f64) {
+        self.set_labelled(&[], value);
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn set_labelled(&self, label_values : &[&str], value : f64) {
+        if label_values.len() != self.labels.len() {
+            panic!("Gauge {} expects {} label values", self.name, self.labels.len());
+        }
+        let key = label_values.iter().map(|x| String::from(*x)).collect();
+        let mut values = self.values.lock().expect("Unexpected failure to lock mutex");
+        (*values).insert(key, value);
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get(&self) -> Option<f64> {
+        self.get_labelled(&[])
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_labelled(&self, label_values : &[&str]) -> Option<f64> {
+        let key : Vec<String> = label_values.iter().map(|x| String::from(*x)).collect();
+        let values = self.values.lock().expect("Unexpected failure to lock mutex");
+        (*values).get(&key).copied()
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Metric for Gauge {
+    fn render(&self, out : &mut String) {
+        let values = self.values.lock().expect("Unexpected failure to lock mutex");
+        if (*values).is_empty() {
+            return;
+        }
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} gauge", self.name);
+        for (label_values, value) in (*values).iter() {
+            if label_values.is_empty() {
+                let _ = writeln!(out, "{} {}", self.name, value);
+            } else {
+                let labels : Vec<String> = self.labels.iter()
+                    .zip(label_values)
+                    .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+                    .collect();
+                let _ = writeln!(out, "{}{{{}}} {}", self.name, labels.join(","), value);
+            }
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn escape(value : &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Standard process_* metrics, read from /proc
+fn render_process(out : &mut String) {
+    let start_time = START_TIME.load(Ordering::Relaxed);
+    if start_time > 0 {
+        let _ = writeln!(out, "# HELP process_start_time_seconds Start time of the process since unix epoch in seconds");
+        let _ = writeln!(out, "# TYPE process_start_time_seconds gauge");
+        let _ = writeln!(out, "process_start_time_seconds {}", start_time);
+    }
+
+    // Fields after the command name, which is in brackets and may contain spaces
+    if let Ok(stat) = fs::read_to_string("/proc/self/stat") {
+        let fields : Vec<&str> = stat.rsplit(')').next().unwrap_or("").split_whitespace().collect();
+        let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+        if fields.len() > 21 && ticks_per_sec > 0.0 {
+            let utime = fields[11].parse::<f64>().unwrap_or(0.0);
+            let stime = fields[12].parse::<f64>().unwrap_or(0.0);
+            let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+            let rss = fields[21].parse::<u64>().unwrap_or(0) * page_size;
+
+            let _ = writeln!(out, "# HELP process_cpu_seconds_total Total user and system CPU time spent in seconds");
+            let _ = writeln!(out, "# TYPE process_cpu_seconds_total counter");
+            let _ = writeln!(out, "process_cpu_seconds_total {}", (utime + stime) / ticks_per_sec);
+            let _ = writeln!(out, "# HELP process_resident_memory_bytes Resident memory size in bytes");
+            let _ = writeln!(out, "# TYPE process_resident_memory_bytes gauge");
+            let _ = writeln!(out, "process_resident_memory_bytes {}", rss);
+        }
+    }
+
+    if let Ok(entries) = fs::read_dir("/proc/self/fd") {
+        let _ = writeln!(out, "# HELP process_open_fds Number of open file descriptors");
+        let _ = writeln!(out, "# TYPE process_open_fds gauge");
+        let _ = writeln!(out, "process_open_fds {}", entries.count());
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Render all the metrics in the Prometheus text exposition format
+pub fn render() -> String {
+    let mut out = String::new();
+    for metric in REGISTRY {
+        metric.render(&mut out);
+    }
+    render_process(&mut out);
+    out
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Serves GET /metrics over HTTP so the station can be scraped
+pub struct Exporter {
+    port : u16
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Exporter {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(port : u16) -> Exporter {
+        Exporter {
+            port
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn process_client(mut stream_in : impl BufRead, mut stream_out : impl Write) -> Result<()> {
+        let mut request = String::new();
+        if stream_in.read_line(&mut request)? == 0 {
+            return Ok(());
+        }
+
+        // Skip the headers, not interested in them
+        loop {
+            let mut line = String::new();
+            let n = stream_in.read_line(&mut line)?;
+            if n == 0 || line.trim().is_empty() {
+                break;
+            }
+        }
+
+        let mut tokens = request.split_whitespace();
+        let method = tokens.next().unwrap_or("");
+        let path = tokens.next().unwrap_or("");
+
+        let (status, body) = if method == "GET" && path == "/metrics" {
+            ("200 OK", render())
+        } else {
+            ("404 Not Found", String::from("Not Found\n"))
+        };
+
+        let response = format!("HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status, body.len(), body);
+        stream_out.write_all(response.as_bytes())?;
+        Ok(())
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn start(&self) {
+        let port = self.port;
+
+        if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+            START_TIME.store(now.as_secs(), Ordering::Relaxed);
+        }
+
+        thread::spawn(move || {
+            if let Err(error) = Self::task(port) {
+                println!("Metrics exporter stopped {:?}", error);
+            }
+        });
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn serve(socket : &TcpStream) -> Result<()> {
+        // Clients are served one at a time, so one that stalls mustn't hold up the rest
+        socket.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        socket.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+        Self::process_client(BufReader::new(socket), socket)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn task(port : u16) -> Result<()> {
+        let sock_addr = format!("0.0.0.0:{}", port);
+
+        let listener = TcpListener::bind(&sock_addr)?;
+        println!("Metrics on: {}", sock_addr);
+
+        // A failed connection or misbehaving scraper should not stop the exporter
+        loop {
+            let socket = match listener.accept() {
+                Ok((socket, _)) => socket,
+                Err(error) => {
+                    println!("Metrics accept error {:?}", error);
+                    continue;
+                }
+            };
+
+            if let Err(error) = Self::serve(&socket) {
+                println!("Metrics client error {:?}", error);
+            }
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_render_counter_and_gauge() {
+        static COUNT : Counter = Counter::new("test_count_total", "A count");
+        static LEVEL : Gauge = Gauge::with_labels("test_level", "A level", &["node", "column"]);
+
+        let mut out = String::new();
+        LEVEL.render(&mut out);
+        assert_eq!(out, "");
+
+        COUNT.inc();
+        COUNT.add(2);
+        LEVEL.set_labelled(&["indoor", "temperature"], 21.5);
+        LEVEL.set_labelled(&["indoor", "a\"b"], 1.0);
+
+        COUNT.render(&mut out);
+        LEVEL.render(&mut out);
+        assert_eq!(out, "# HELP test_count_total A count\n\
+                         # TYPE test_count_total counter\n\
+                         test_count_total 3\n\
+                         # HELP test_level A level\n\
+                         # TYPE test_level gauge\n\
+                         test_level{node=\"indoor\",column=\"a\\\"b\"} 1\n\
+                         test_level{node=\"indoor\",column=\"temperature\"} 21.5\n");
+        assert_eq!(LEVEL.get_labelled(&["indoor", "temperature"]), Some(21.5));
+    }
+
+    #[test]
+    fn check_render_labelled_counter() {
+        static FAILURES : Counter = Counter::with_labels("test_failures_total", "Failures", &["check"]);
+
+        let mut out = String::new();
+        FAILURES.render(&mut out);
+        assert_eq!(out, "");
+
+        FAILURES.inc_labelled(&["step"]);
+        FAILURES.inc_labelled(&["range"]);
+        FAILURES.inc_labelled(&["step"]);
+        FAILURES.render(&mut out);
+        assert_eq!(out, "# HELP test_failures_total Failures\n\
+                         # TYPE test_failures_total counter\n\
+                         test_failures_total{check=\"range\"} 1\n\
+                         test_failures_total{check=\"step\"} 2\n");
+        assert_eq!(FAILURES.get_labelled(&["step"]), 2);
+    }
+
+    #[test]
+    fn check_http_response() {
+        let mut out = Vec::new();
+        Exporter::process_client("GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n".as_bytes(), &mut out).unwrap();
+        let response = String::from_utf8(out).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("weather_i2c_errors_total"));
+
+        let mut out = Vec::new();
+        Exporter::process_client("GET / HTTP/1.1\r\n\r\n".as_bytes(), &mut out).unwrap();
+        let response = String::from_utf8(out).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn check_serve_and_bind_error() {
+        let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+            let mut response = String::new();
+            std::io::Read::read_to_string(&mut stream, &mut response).unwrap();
+            response
+        });
+        let (socket, _) = listener.accept().unwrap();
+        Exporter::serve(&socket).unwrap();
+        drop(socket);
+        assert!(client.join().unwrap().starts_with("HTTP/1.1 200 OK\r\n"));
+
+        // The port is taken, so the task returns rather than serving
+        assert!(Exporter::task(port).is_err());
+    }
+}
@@ -0,0 +1,341 @@
+//!
+//! Publish readings to an MQTT broker, with Home Assistant discovery
+//!
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use weather_err::Result;
+
+mod packet;
+
+const MAX_BACKOFF_SECS : u64 = 60;
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Describes one published reading so Home Assistant knows how to show it
+pub struct Channel {
+    pub name : &'static str,
+    pub unit : &'static str,
+    pub device_class : &'static str
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Settings {
+    pub host : String,
+    pub port : u16,
+    pub topic_prefix : String,
+    pub discovery_prefix : String,
+    pub credentials : Option<(String, String)>,
+    pub queue_size : usize,
+    pub keep_alive : u16
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Settings {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// None if no broker is configured
+    pub fn from_config(config : &config::Config) -> Option<Self> {
+        let (host, port) = config.get_mqtt_broker()?;
+        Some(Self {
+            host : String::from(host),
+            port,
+            topic_prefix : String::from(config.get_mqtt_topic_prefix()),
+            discovery_prefix : String::from(config.get_mqtt_discovery_prefix()),
+            credentials : config.get_mqtt_credentials().map(|(user, pass)| (String::from(user), String::from(pass))),
+            queue_size : config.get_mqtt_queue_size(),
+            keep_alive : 60
+        })
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+struct Message {
+    topic : String,
+    payload : Vec<u8>,
+    retain : bool
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+type Queue = Arc<(Mutex<VecDeque<Message>>, Condvar)>;
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Publisher {
+    settings : Arc<Settings>,
+    node : String,
+    discovery : Arc<Vec<Message>>,
+    queue : Queue
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn title_case(value : &str) -> String {
+    value.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().unwrap().to_uppercase();
+            first.chain(chars).collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Publisher {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(settings : Settings, node : &str, channels : &[Channel]) -> Self {
+        let discovery = channels.iter().map(|channel| Self::discovery_message(&settings, node, channel)).collect();
+        Self {
+            settings : Arc::new(settings),
+            node : String::from(node),
+            discovery : Arc::new(discovery),
+            queue : Arc::new((Mutex::new(VecDeque::new()), Condvar::new()))
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn state_topic(settings : &Settings, node : &str, name : &str) -> String {
+        format!("{}/{}/{}", settings.topic_prefix, node, name)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn availability_topic(settings : &Settings, node : &str) -> String {
+        format!("{}/{}/availability", settings.topic_prefix, node)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn discovery_message(settings : &Settings, node : &str, channel : &Channel) -> Message {
+        let object_id = format!("weather_{}_{}", node, channel.name);
+        let topic = format!("{}/sensor/weather_{}/{}/config", settings.discovery_prefix, node, channel.name);
+
+        let payload = format!("{{\"name\":{},\"unique_id\":{},\"object_id\":{},\"state_topic\":{},\
+                \"availability_topic\":{},\"unit_of_measurement\":{},\"device_class\":{},\"state_class\":\"measurement\",\
+                \"device\":{{\"identifiers\":[{}],\"name\":{},\"model\":\"Weather Station\"}}}}",
+            json::string(&title_case(channel.name)),
+            json::string(&object_id),
+            json::string(&object_id),
+            json::string(&Self::state_topic(settings, node, channel.name)),
+            json::string(&Self::availability_topic(settings, node)),
+            json::string(channel.unit),
+            json::string(channel.device_class),
+            json::string(&format!("weather_{}", node)),
+            json::string(&format!("Weather Station {}", title_case(node))));
+
+        Message {
+            topic,
+            payload : payload.into_bytes(),
+            retain : true
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Queue the latest readings, retained so new subscribers see them straight away.
+    /// If the broker is unreachable the oldest messages are dropped once the queue is full.
+    pub fn publish(&self, values : &[(&str, f32)]) {
+        let (lock, condvar) = &*self.queue;
+        let mut queue = lock.lock().expect("Unexpected failure to lock mutex");
+        for (name, value) in values {
+            if queue.len() >= self.settings.queue_size {
+                queue.pop_front();
+            }
+            queue.push_back(Message {
+                topic : Self::state_topic(&self.settings, &self.node, name),
+                payload : format!("{:.2}", value).into_bytes(),
+                retain : true
+            });
+        }
+        condvar.notify_one();
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn start(&self) {
+        let settings = self.settings.clone();
+        let node = self.node.clone();
+        let discovery = self.discovery.clone();
+        let queue = self.queue.clone();
+
+        thread::spawn(move || {
+            Self::task(settings, node, discovery, queue);
+        });
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn connect(settings : &Settings, node : &str, discovery : &[Message]) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect((settings.host.as_str(), settings.port))?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+
+        let availability = Self::availability_topic(settings, node);
+        let will = packet::Will { topic : &availability, payload : b"offline" };
+        let credentials = settings.credentials.as_ref().map(|(user, pass)| (user.as_str(), pass.as_str()));
+        let client_id = format!("weather_{}", node);
+
+        stream.write_all(&packet::connect(&client_id, settings.keep_alive, Some(&will), credentials))?;
+        packet::read_connack(&mut stream)?;
+
+        stream.write_all(&packet::publish(&availability, b"online", true))?;
+        for message in discovery {
+            stream.write_all(&packet::publish(&message.topic, &message.payload, message.retain))?;
+        }
+        Ok(stream)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Send queued messages until the connection fails. A message is only removed from the queue once written.
+    fn drain(settings : &Settings, stream : &mut TcpStream, queue : &Queue) -> Result<()> {
+        let ping_interval = Duration::from_secs((settings.keep_alive / 2).max(1) as u64);
+        let mut last_sent = Instant::now();
+        let (lock, condvar) = &**queue;
+
+        loop {
+            let message = {
+                let mut guard = lock.lock().expect("Unexpected failure to lock mutex");
+                if guard.is_empty() {
+                    guard = condvar.wait_timeout(guard, ping_interval).expect("Unexpected failure to lock mutex").0;
+                }
+                guard.pop_front()
+            };
+
+            match message {
+                Some(message) => {
+                    let result = stream.write_all(&packet::publish(&message.topic, &message.payload, message.retain));
+                    if let Err(error) = result {
+                        let mut guard = lock.lock().expect("Unexpected failure to lock mutex");
+                        guard.push_front(message);
+                        return Err(error.into());
+                    }
+                    last_sent = Instant::now();
+                },
+                None => {
+                    if last_sent.elapsed() >= ping_interval {
+                        stream.write_all(&packet::pingreq())?;
+                        packet::read_pingresp(stream)?;
+                        last_sent = Instant::now();
+                    }
+                }
+            }
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn task(settings : Arc<Settings>, node : String, discovery : Arc<Vec<Message>>, queue : Queue) {
+        let mut backoff = 1;
+        loop {
+            match Self::connect(&settings, &node, &discovery) {
+                Ok(mut stream) => {
+                    println!("Connected to MQTT broker {}:{}", settings.host, settings.port);
+                    backoff = 1;
+                    if let Err(error) = Self::drain(&settings, &mut stream, &queue) {
+                        println!("Lost MQTT broker connection {:?}", error);
+                    }
+                    let _ = stream.write_all(&packet::disconnect());
+                },
+                Err(error) => println!("Failed to connect to MQTT broker {:?}", error)
+            }
+            thread::sleep(Duration::from_secs(backoff));
+            backoff = (backoff * 2).min(MAX_BACKOFF_SECS);
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn settings(port : u16) -> Settings {
+        Settings {
+            host : String::from("127.0.0.1"),
+            port,
+            topic_prefix : String::from("weather"),
+            discovery_prefix : String::from("homeassistant"),
+            credentials : None,
+            queue_size : 2,
+            keep_alive : 60
+        }
+    }
+
+    fn read_packet(stream : &mut TcpStream) -> Vec<u8> {
+        let mut packet = vec![0_u8; 1];
+        stream.read_exact(&mut packet).unwrap();
+        let mut length = 0;
+        let mut shift = 0;
+        loop {
+            let mut byte = [0_u8; 1];
+            stream.read_exact(&mut byte).unwrap();
+            packet.push(byte[0]);
+            length |= ((byte[0] & 0x7F) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0_u8; length];
+        stream.read_exact(&mut body).unwrap();
+        packet.extend(body);
+        packet
+    }
+
+    #[test]
+    fn check_discovery_payload() {
+        let channel = Channel { name : "max_speed", unit : "m/s", device_class : "wind_speed" };
+        let message = Publisher::discovery_message(&settings(1883), "outdoor", &channel);
+        assert_eq!(message.topic, "homeassistant/sensor/weather_outdoor/max_speed/config");
+        assert!(message.retain);
+        let payload = String::from_utf8(message.payload).unwrap();
+        assert!(payload.starts_with("{\"name\":\"Max Speed\",\"unique_id\":\"weather_outdoor_max_speed\","));
+        assert!(payload.contains("\"state_topic\":\"weather/outdoor/max_speed\""));
+        assert!(payload.contains("\"unit_of_measurement\":\"m/s\",\"device_class\":\"wind_speed\""));
+        assert!(payload.ends_with("\"device\":{\"identifiers\":[\"weather_outdoor\"],\"name\":\"Weather Station Outdoor\",\"model\":\"Weather Station\"}}"));
+    }
+
+    #[test]
+    fn check_queue_drops_oldest() {
+        let publisher = Publisher::new(settings(1883), "indoor", &[]);
+        publisher.publish(&[("temperature", 20.0), ("humidity", 50.0), ("pressure", 1013.0)]);
+        let queue = publisher.queue.0.lock().unwrap();
+        let topics : Vec<&str> = queue.iter().map(|message| message.topic.as_str()).collect();
+        assert_eq!(topics, ["weather/indoor/humidity", "weather/indoor/pressure"]);
+    }
+
+    #[test]
+    fn check_publish_to_broker() {
+        let broker = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = broker.local_addr().unwrap().port();
+
+        let channel = Channel { name : "temperature", unit : "°C", device_class : "temperature" };
+        let publisher = Publisher::new(settings(port), "indoor", &[channel]);
+        publisher.publish(&[("temperature", 21.25)]);
+        publisher.start();
+
+        let (mut stream, _) = broker.accept().unwrap();
+        assert_eq!(read_packet(&mut stream)[0], 0x10);
+        stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+
+        assert_eq!(read_packet(&mut stream), packet::publish("weather/indoor/availability", b"online", true));
+        assert_eq!(read_packet(&mut stream)[0], 0x31);
+        assert_eq!(read_packet(&mut stream), packet::publish("weather/indoor/temperature", b"21.25", true));
+    }
+}
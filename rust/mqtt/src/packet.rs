@@ -0,0 +1,214 @@
+//!
+//! Encoding of the handful of MQTT 3.1.1 control packets a publisher needs
+//!
+
+use std::io::Read;
+use weather_err::{Result, WeatherError};
+
+const CONNECT : u8 = 0x10;
+const CONNACK : u8 = 0x20;
+const PUBLISH : u8 = 0x30;
+const PINGREQ : u8 = 0xC0;
+const PINGRESP : u8 = 0xD0;
+const DISCONNECT : u8 = 0xE0;
+
+// Connect flags
+const CLEAN_SESSION : u8 = 0x02;
+const WILL_FLAG : u8 = 0x04;
+const WILL_RETAIN : u8 = 0x20;
+const PASSWORD_FLAG : u8 = 0x40;
+const USERNAME_FLAG : u8 = 0x80;
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Will<'a> {
+    pub topic : &'a str,
+    pub payload : &'a [u8]
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn push_length(packet : &mut Vec<u8>, mut length : usize) {
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn push_bytes(packet : &mut Vec<u8>, bytes : &[u8]) {
+    packet.push((bytes.len() >> 8) as u8);
+    packet.push(bytes.len() as u8);
+    packet.extend_from_slice(bytes);
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn finish(packet_type : u8, body : Vec<u8>) -> Vec<u8> {
+    let mut packet = vec![packet_type];
+    push_length(&mut packet, body.len());
+    packet.extend(body);
+    packet
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub fn connect(client_id : &str, keep_alive : u16, will : Option<&Will>, credentials : Option<(&str, &str)>) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_bytes(&mut body, b"MQTT");
+    body.push(4);   // Protocol level 3.1.1
+
+    let mut flags = CLEAN_SESSION;
+    if will.is_some() {
+        flags |= WILL_FLAG | WILL_RETAIN;
+    }
+    if credentials.is_some() {
+        flags |= USERNAME_FLAG | PASSWORD_FLAG;
+    }
+    body.push(flags);
+    body.push((keep_alive >> 8) as u8);
+    body.push(keep_alive as u8);
+
+    push_bytes(&mut body, client_id.as_bytes());
+    if let Some(will) = will {
+        push_bytes(&mut body, will.topic.as_bytes());
+        push_bytes(&mut body, will.payload);
+    }
+    if let Some((username, password)) = credentials {
+        push_bytes(&mut body, username.as_bytes());
+        push_bytes(&mut body, password.as_bytes());
+    }
+    finish(CONNECT, body)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// QoS 0 publish, so no packet identifier
+pub fn publish(topic : &str, payload : &[u8], retain : bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_bytes(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    finish(if retain { PUBLISH | 0x01 } else { PUBLISH }, body)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub fn pingreq() -> Vec<u8> {
+    vec![PINGREQ, 0]
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub fn disconnect() -> Vec<u8> {
+    vec![DISCONNECT, 0]
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn read_packet(stream : &mut impl Read, expected : u8) -> Result<Vec<u8>> {
+    let mut header = [0_u8; 1];
+    stream.read_exact(&mut header)?;
+
+    let mut length : usize = 0;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0_u8; 1];
+        stream.read_exact(&mut byte)?;
+        length |= ((byte[0] & 0x7F) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift > 21 {
+            return Err(WeatherError::from("Malformed MQTT remaining length"));
+        }
+    }
+    let mut body = vec![0_u8; length];
+    stream.read_exact(&mut body)?;
+
+    if header[0] & 0xF0 != expected {
+        return Err(WeatherError::from("Unexpected MQTT packet"));
+    }
+    Ok(body)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub fn read_connack(stream : &mut impl Read) -> Result<()> {
+    let body = read_packet(stream, CONNACK)?;
+    if body.len() != 2 {
+        return Err(WeatherError::from("Malformed MQTT CONNACK"));
+    }
+    match body[1] {
+        0 => Ok(()),
+        1 => Err(WeatherError::from("MQTT broker refused protocol version")),
+        2 => Err(WeatherError::from("MQTT broker rejected client id")),
+        3 => Err(WeatherError::from("MQTT broker unavailable")),
+        4 => Err(WeatherError::from("MQTT broker rejected username or password")),
+        5 => Err(WeatherError::from("MQTT broker says not authorised")),
+        _ => Err(WeatherError::from("MQTT broker refused connection"))
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub fn read_pingresp(stream : &mut impl Read) -> Result<()> {
+    read_packet(stream, PINGRESP)?;
+    Ok(())
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_remaining_length() {
+        let mut packet = Vec::new();
+        push_length(&mut packet, 0);
+        push_length(&mut packet, 127);
+        push_length(&mut packet, 128);
+        push_length(&mut packet, 16383);
+        push_length(&mut packet, 2097152);
+        assert_eq!(packet, [0x00, 0x7F, 0x80, 0x01, 0xFF, 0x7F, 0x80, 0x80, 0x80, 0x01]);
+    }
+
+    #[test]
+    fn check_connect() {
+        let will = Will { topic : "w/s", payload : b"offline" };
+        let packet = connect("id", 60, Some(&will), Some(("u", "p")));
+        assert_eq!(packet, [0x10, 34,
+            0, 4, b'M', b'Q', b'T', b'T', 4, 0xE6, 0, 60,
+            0, 2, b'i', b'd',
+            0, 3, b'w', b'/', b's',
+            0, 7, b'o', b'f', b'f', b'l', b'i', b'n', b'e',
+            0, 1, b'u',
+            0, 1, b'p']);
+
+        let packet = connect("id", 30, None, None);
+        assert_eq!(packet, [0x10, 14, 0, 4, b'M', b'Q', b'T', b'T', 4, 0x02, 0, 30, 0, 2, b'i', b'd']);
+    }
+
+    #[test]
+    fn check_publish() {
+        assert_eq!(publish("a/b", b"1.5", true), [0x31, 8, 0, 3, b'a', b'/', b'b', b'1', b'.', b'5']);
+        assert_eq!(publish("a", b"", false), [0x30, 3, 0, 1, b'a']);
+    }
+
+    #[test]
+    fn check_connack() {
+        assert!(read_connack(&mut &[0x20_u8, 2, 0, 0][..]).is_ok());
+        assert!(read_connack(&mut &[0x20_u8, 2, 0, 5][..]).is_err());
+        assert!(read_connack(&mut &[0xD0_u8, 0][..]).is_err());
+        assert!(read_pingresp(&mut &[0xD0_u8, 0][..]).is_ok());
+    }
+}
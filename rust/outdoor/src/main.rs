@@ -1,24 +1,41 @@
-///
-/// Reading of Outdoor sensors
-///
+//!
+//! Reading of Outdoor sensors
+//!
 
 use std::time::Duration;
 use std::sync::{Arc, Mutex};
 use chrono::DateTime;
 use std::thread;
-use config;
 
-use clock;
 use listener::Listener;
 
+use crate::rain::Rain;
+use crate::solar::Solar;
+use crate::temp::{Heater, Temperature};
+use crate::vane::Vane;
 use crate::wind::Wind;
-use sht31::{self, Sht31};
+use sensor::Channel;
+use sht31::{self, AlertLimits, Rate, Repeatability, Sht31};
 
+mod protocol;
+mod rain;
+mod serial;
+mod solar;
 mod stats;
+mod temp;
+mod vane;
 mod wind;
 
 type Connection = Arc<Mutex<sqlite::Connection>>;
 
+const COLUMNS : [&str; 19] = ["max_speed", "ave_speed", "min_speed", "temperature", "humidity", "heated", "precipitation",
+                              "solar", "solar_max", "illuminance", "illuminance_max", "rain_rate", "rain_daily",
+                              "wind_direction", "gust_direction", "gust_speed", "sustained_speed_2min", "sustained_speed_10min",
+                              "beaufort"];
+
+// Channels that can be calibrated
+const CALIBRATED : [(&str, &str); 2] = [("temperature", "°C"), ("humidity", "%")];
+
 //----------------------------------------------------------------------------------------------------------------------------------
 /// Aync wait for a tick event
 fn wait_tick(ticker : &clock::Clock) -> Result<(), ()> {
@@ -37,66 +54,178 @@ fn create_ticker(config : &config::Config) -> clock::Clock {
 
 //----------------------------------------------------------------------------------------------------------------------------------
 /// Create a database connection
-fn create_db_connection(config : &config::Config)-> (Connection, String) {
+/// Extra columns are the raw readings of calibrated channels and the results of their quality checks
+fn create_db_connection(config : &config::Config, extra_columns : &[String])-> (Connection, String) {
 
     let (db_file, db_table) = config.get_database("outdoor");
     println!("Opening database {}", db_file);
     let db_connection = Arc::new(Mutex::new(sqlite::open(db_file).unwrap()));
     println!("Creating/using db table {}", db_table);
 
-    let query = format!("CREATE TABLE IF NOT EXISTS {} (unix_time INT NOT NULL,
-            max_speed REAL, ave_speed REAL, min_speed REAL,
-            temperature REAL, humidity REAL, precipitation REAL, solar REAL,
-            PRIMARY KEY(unix_time));", db_table);
+    let mut columns = COLUMNS.to_vec();
+    columns.extend(meteo::COLUMNS);
+    columns.extend(extra_columns.iter().map(|column| column.as_str()));
     {
         let conn = db_connection.lock().unwrap();
-        (*conn).execute(query).unwrap();
+        database::create_table(&conn, db_table, &columns).unwrap();
     }
     (db_connection, String::from(db_table))
 }
 
 //----------------------------------------------------------------------------------------------------------------------------------
-fn send_to_database(db_connection : &Connection, db_table : &str, unix_time : i64, wind : stats::Summary, temp : sht31::Summary) {
-    let dt = DateTime::from_timestamp(unix_time, 0).expect("invalid timestamp");
-    println!("{} {} {}", dt, wind, temp);
+/// Everything measured at a tick
+struct Measurements {
+    wind : stats::Summary,
+    direction : vane::Summary,
+    // Corrected temperature and humidity, None if the sensor couldn't be read
+    temperature : Option<f64>,
+    humidity : Option<f64>,
+    heated : Option<bool>,
+    // Uncorrected readings of calibrated channels, by column
+    raw : Vec<(String, Option<f64>)>,
+    rain : rain::Summary,
+    solar : solar::Summary
+}
 
-    let query = format!("INSERT INTO {} VALUES ({},{},{},{},{},{},0.0,0.0);",
-            db_table, unix_time, wind.get_max(), wind.get_average(), wind.get_min(),
-            temp.get_temperature(), temp.get_humidity());
-    println!("{}", query);
 
-    {
-        let conn = db_connection.lock().unwrap();
-        (*conn).execute(query).unwrap();
+//----------------------------------------------------------------------------------------------------------------------------------
+/// A tick's row, before its quality checks
+fn get_values(unix_time : i64, measurements : &Measurements) -> Vec<(&'static str, Option<f64>)> {
+    let Measurements { wind, direction, temperature, humidity, heated, rain, solar, .. } = measurements;
+    let dt = DateTime::from_timestamp(unix_time, 0).expect("invalid timestamp");
+    let derived = match (temperature, humidity) {
+        (Some(temperature), Some(humidity)) => Some(meteo::Derived::new(*temperature as f32, *humidity as f32,
+                                                                        Some(wind.get_average()))),
+        _ => None
+    };
+    let temp = sensor::channels(&CALIBRATED, &[*temperature, *humidity]);
+    let temp = temp.iter().map(|channel| channel.to_string()).collect::<Vec<_>>().join(" ");
+    match &derived {
+        Some(derived) => println!("{} {} {} {} {} {} {}", dt, wind, direction, temp, rain, solar, derived),
+        None => println!("{} {} {} {} {} {}", dt, wind, direction, temp, rain, solar)
+    }
+
+    let mut values = vec![
+        ("max_speed", Some(wind.get_max() as f64)),
+        ("ave_speed", Some(wind.get_average() as f64)),
+        ("min_speed", Some(wind.get_min() as f64)),
+        ("temperature", *temperature),
+        ("humidity", *humidity),
+        ("heated", heated.map(|heated| if heated { 1.0 } else { 0.0 })),
+        ("precipitation", Some(rain.get_rainfall() as f64)),
+        ("solar", solar.get_irradiance().map(|value| value as f64)),
+        ("solar_max", solar.get_max_irradiance().map(|value| value as f64)),
+        ("illuminance", solar.get_illuminance().map(|value| value as f64)),
+        ("illuminance_max", solar.get_max_illuminance().map(|value| value as f64)),
+        ("rain_rate", Some(rain.get_rate() as f64)),
+        ("rain_daily", Some(rain.get_daily() as f64)),
+        ("wind_direction", direction.get_mean_direction().map(|value| value as f64)),
+        ("gust_direction", direction.get_gust_direction().map(|value| value as f64)),
+        ("gust_speed", Some(wind.get_gust() as f64)),
+        ("sustained_speed_2min", Some(wind.get_sustained_2min() as f64)),
+        ("sustained_speed_10min", Some(wind.get_sustained_10min() as f64)),
+        ("beaufort", Some(wind.get_beaufort() as f64))];
+    match derived {
+        Some(derived) => values.extend(derived.values().iter().map(|(name, value)| (*name, value.map(|value| value as f64)))),
+        None => values.extend(meteo::COLUMNS.map(|name| (name, None)))
     }
+    values
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Values that failed their quality checks are stored as NULL
+fn send_to_database(db_connection : &Connection, db_table : &str, unix_time : i64, values : &[(&str, Option<f64>)]) {
+    let conn = db_connection.lock().unwrap();
+    database::insert(&conn, db_table, unix_time, values).unwrap();
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Value of a column that passed its checks
+fn get_value(values : &[(&str, Option<f64>)], name : &str) -> Option<f64> {
+    values.iter().find(|(column, _)| *column == name).and_then(|(_, value)| *value)
+}
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Correct the temperature and humidity, returning them with the raw readings of those that are calibrated. A tick
+/// without readings, e.g. after an I2C glitch, leaves them empty rather than losing the row.
+fn calibrate(calibration : &sensor::Calibration, summary : Option<&sht31::Summary>)
+    -> (Vec<Channel>, Vec<(String, Option<f64>)>) {
+    let values = [summary.map(|summary| summary.get_temperature() as f64),
+                  summary.map(|summary| summary.get_humidity() as f64)];
+    let mut channels = sensor::channels(&CALIBRATED, &values);
+    let raw = calibration.apply(&mut channels);
+    (channels, raw)
 }
 
 //----------------------------------------------------------------------------------------------------------------------------------
-fn create_wind_sensor(config : &config::Config) -> Wind {
+fn create_wind_sensor(config : &config::Config, rain : &Rain) -> Wind {
 
     let dev_name = config.get_wind_dev_name();
-    println!("Reading from {} for wind speeds", dev_name);
+    println!("Reading from {} for wind speeds, direction and rain", dev_name);
 
-    Wind::new(dev_name)
+    let settings = match serial::Settings::new(config.get_wind_baud(), config.get_wind_framing()) {
+        Ok(settings) => settings,
+        Err(error) => panic!("Bad wind device settings {:?}", error)
+    };
+
+    Wind::new(dev_name, settings, config.get_wind_window_secs(), rain.get_gauge(), Vane::new(config.get_vane_table()))
 }
 
 
 //----------------------------------------------------------------------------------------------------------------------------------
-fn create_temp_sensor(config : &config::Config) -> Sht31 {
+/// Create the rain gauge, carrying on with today's total from the database
+fn create_rain_gauge(config : &config::Config, db_connection : &Connection, db_table : &str) -> Rain {
+    let mut rain = Rain::new(config.get_rain_mm_per_tip(), config.get_rain_reset_hour());
+    let conn = db_connection.lock().unwrap();
+    if let Err(error) = rain.restore(&conn, db_table, chrono::Local::now().timestamp()) {
+        println!("Failed to restore daily rainfall {:?}", error);
+    }
+    rain
+}
 
-    let dev_name = config.get_dev_name("outdoor");
-    println!("Reading from {} for temp/humidity speeds", dev_name);
 
-    Sht31::new(dev_name)
+//----------------------------------------------------------------------------------------------------------------------------------
+fn create_temp_sensor(config : &config::Config) -> Temperature {
+
+    let dev_name = config.get_dev_name("outdoor");
+    println!("Reading from {} at {:#x} for temp/humidity", dev_name, config.get_temp_addr());
+
+    let repeatability = config.get_temp_repeatability();
+    let repeatability = Repeatability::from_name(repeatability)
+        .unwrap_or_else(|| panic!("temp_repeatability {} should be low, medium or high", repeatability));
+    let rate = config.get_temp_rate().map(|mps| Rate::from_mps(mps)
+        .unwrap_or_else(|| panic!("temp_rate {} should be 0.5, 1, 2, 4 or 10", mps)));
+
+    // Bursts start half way between ticks so the sensor has cooled by the next one
+    let heater = config.get_heater_interval_mins().map(|mins| {
+        let (burst_secs, recovery_secs) = (config.get_heater_secs(), config.get_heater_recovery_secs());
+        println!("Heating {} for {}s every {} minutes", dev_name, burst_secs, mins);
+        Heater::new(mins as i64 * 60, burst_secs as i64, recovery_secs as i64, config.get_sample_period() as i64 * 30)
+    });
+
+    let alert_limits = config.get_frost_alert_temp().map(|frost_temp| {
+        if rate.is_none() {
+            println!("The frost alert needs temp_rate, the SHT31 only checks its limits when measuring periodically");
+        }
+        println!("Frost alert at {}C", frost_temp);
+        AlertLimits::frost(frost_temp)
+    });
+
+    Temperature::new(Sht31::with_address(dev_name, config.get_temp_addr()), repeatability, rate, heater, alert_limits)
 }
 
 
 //----------------------------------------------------------------------------------------------------------------------------------
-fn read_temp(sensor : &mut Sht31) -> sht31::Summary {
-    // Start sample..
-    sensor.one_shot().unwrap();
-    thread::sleep(Duration::from_secs(1));
-    sensor.sample().unwrap()
+/// Start reading the light sensor, if there is one
+fn create_solar_sensor(config : &config::Config) -> Solar {
+    let solar = Solar::new();
+    if let Some(dev_name) = config.get_solar_dev_name() {
+        println!("Reading from {} for solar radiation", dev_name);
+        solar.start(bh1750::Bh1750::new(dev_name));
+    }
+    solar
 }
 
 
@@ -109,32 +238,142 @@ fn launch_listener(config : &config::Config, db_connection : Connection)
 }
 
 
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Start the metrics exporter, if configured
+fn launch_metrics(config : &config::Config)
+{
+    if let Some(port) = config.get_metrics_port("outdoor") {
+        metrics::Exporter::new(port).start();
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// From the values that passed their checks, and the checks that failed
+fn update_metrics(unix_time : i64, wind : &stats::Summary, values : &[(&str, Option<f64>)],
+                  failures : &[(String, sensor::qc::Failure)]) {
+    metrics::LAST_TICK.set(unix_time as f64);
+    for (label, column) in [("max", "max_speed"), ("average", "ave_speed"), ("min", "min_speed"), ("gust", "gust_speed"),
+                            ("sustained_2min", "sustained_speed_2min"), ("sustained_10min", "sustained_speed_10min")] {
+        if let Some(speed) = get_value(values, column) {
+            metrics::WIND_SPEED.set_labelled(&[label], speed);
+        }
+    }
+    metrics::WIND_SPEED.set_labelled(&["std_dev"], wind.get_std_dev() as f64);
+    for (gauge, column) in [(&metrics::WIND_DIRECTION, "wind_direction"), (&metrics::TEMPERATURE, "temperature"),
+                            (&metrics::HUMIDITY, "humidity"), (&metrics::RAIN_RATE, "rain_rate"),
+                            (&metrics::RAIN_DAILY, "rain_daily"), (&metrics::SOLAR_RADIATION, "solar"),
+                            (&metrics::ILLUMINANCE, "illuminance")] {
+        if let Some(value) = get_value(values, column) {
+            gauge.set(value);
+        }
+    }
+    for (name, failure) in failures {
+        metrics::QC_FAILURES.inc_labelled(&[name, &failure.to_string()]);
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Start publishing to the MQTT broker, if configured
+#[cfg(feature = "mqtt")]
+fn launch_publisher(config : &config::Config) -> Option<mqtt::Publisher> {
+    let settings = mqtt::Settings::from_config(config)?;
+    let publisher = mqtt::Publisher::new(settings, "outdoor", &[
+        mqtt::Channel { name : "max_speed", unit : "m/s", device_class : "wind_speed" },
+        mqtt::Channel { name : "ave_speed", unit : "m/s", device_class : "wind_speed" },
+        mqtt::Channel { name : "min_speed", unit : "m/s", device_class : "wind_speed" },
+        mqtt::Channel { name : "gust_speed", unit : "m/s", device_class : "wind_speed" },
+        mqtt::Channel { name : "sustained_speed_10min", unit : "m/s", device_class : "wind_speed" },
+        mqtt::Channel { name : "temperature", unit : "°C", device_class : "temperature" },
+        mqtt::Channel { name : "humidity", unit : "%", device_class : "humidity" },
+        mqtt::Channel { name : "rain_rate", unit : "mm/h", device_class : "precipitation_intensity" },
+        mqtt::Channel { name : "rain_daily", unit : "mm", device_class : "precipitation" },
+        mqtt::Channel { name : "solar", unit : "W/m²", device_class : "irradiance" },
+        mqtt::Channel { name : "illuminance", unit : "lx", device_class : "illuminance" }
+    ]);
+    publisher.start();
+    Some(publisher)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(feature = "mqtt")]
+/// The values that passed their checks
+fn publish(publisher : &mqtt::Publisher, values : &[(&str, Option<f64>)]) {
+    let readings : Vec<_> = ["max_speed", "ave_speed", "min_speed", "gust_speed", "sustained_speed_10min", "temperature",
+                             "humidity", "rain_rate", "rain_daily", "solar", "illuminance"].into_iter()
+        .filter_map(|column| get_value(values, column).map(|value| (column, value as f32)))
+        .collect();
+    publisher.publish(&readings);
+}
+
+
 //----------------------------------------------------------------------------------------------------------------------------------
 /// Application entry point
 fn main() -> Result<(), ()> {
     let config = config::Config::new();
 
-    let (db_connection, db_table) = create_db_connection(&config);
+    let calibration = sensor::Calibration::from_config(&config, "outdoor");
+    calibration.check(&CALIBRATED.map(|(name, _)| name)).unwrap();
+
+    let measured : Vec<_> = COLUMNS.iter().chain(&meteo::COLUMNS).copied().collect();
+    let mut checker = sensor::qc::Checker::from_config(&config, "outdoor", &measured).unwrap();
 
-    let wind = create_wind_sensor(&config);
-    let mut temp = create_temp_sensor(&config);
+    let mut extra_columns = calibration.get_raw_columns();
+    extra_columns.extend(checker.get_qc_columns());
+    let (db_connection, db_table) = create_db_connection(&config, &extra_columns);
+
+    let mut rain = create_rain_gauge(&config, &db_connection, &db_table);
+    let wind = create_wind_sensor(&config, &rain);
+    let temp = create_temp_sensor(&config);
+    let solar = create_solar_sensor(&config);
 
     let ticker = create_ticker(&config);
 
     wind.start();
+    temp.start();
 
     launch_listener(&config, db_connection.clone());
 
+    launch_metrics(&config);
+
+    #[cfg(feature = "mqtt")]
+    let publisher = launch_publisher(&config);
+
     loop {
         wait_tick(&ticker).unwrap();
         println!("Tick");
         let unix_time = ticker.get_nearest_tick();
 
-        let wind_measurement = wind.sample();
-
-        // Start sample..
-        let temp_measurement = read_temp(&mut temp);
-
-        send_to_database(&db_connection, &db_table, unix_time, wind_measurement, temp_measurement);
+        let (temp_measurement, heated) = match temp.sample() {
+            Ok((summary, heated)) => (Some(summary), Some(heated)),
+            Err(error) => {
+                println!("Failed to read temperature {:?}", error);
+                (None, None)
+            }
+        };
+        let (temp_channels, raw) = calibrate(&calibration, temp_measurement.as_ref());
+        let measurements = Measurements {
+            wind : wind.sample(unix_time),
+            direction : wind.sample_direction(),
+            rain : rain.sample(unix_time, config.get_sample_period() * 60),
+            solar : solar.sample(),
+            temperature : temp_channels[0].value,
+            humidity : temp_channels[1].value,
+            heated,
+            raw
+        };
+
+        let mut values = get_values(unix_time, &measurements);
+        let checked = checker.check(&mut values);
+        update_metrics(unix_time, &measurements.wind, &values, &checked.failures);
+
+        #[cfg(feature = "mqtt")]
+        if let Some(publisher) = &publisher {
+            publish(publisher, &values);
+        }
+        values.extend(measurements.raw.iter().chain(&checked.columns).map(|(column, value)| (column.as_str(), *value)));
+        send_to_database(&db_connection, &db_table, unix_time, &values);
     }
 }
@@ -0,0 +1,177 @@
+//!
+//! Line protocol with the Arduino
+//!
+//! Each line is <type>:<sequence>:<payload>*<checksum>, e.g. W:17:12.3*4F
+//!  - type is a single character, see Message
+//!  - sequence counts 0 to 255 and wraps, so lost lines can be spotted. It starts at 0 with the hello.
+//!  - checksum is the XOR of every byte before the '*', as two hex digits
+//!
+//! Lines to the Arduino use the same framing, P sets the measurement window in seconds and is answered with a P
+//!
+
+use weather_err::{Result, WeatherError};
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    /// H, sent when the firmware starts, with its version
+    Hello(String),
+    /// W, wind speed in m/s
+    Wind(f32),
+    /// D, wind vane ADC reading
+    Direction(u16),
+    /// R, running count of rain gauge tips
+    Rain(u16),
+    /// E, diagnostics from the firmware
+    Diagnostic(String),
+    /// P, measurement window in seconds now in use
+    Window(u8)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    pub sequence : u8,
+    pub message : Message
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub fn checksum(data : &str) -> u8 {
+    data.bytes().fold(0, |checksum, byte| checksum ^ byte)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Frame a message the way the firmware does
+pub fn encode(msg_type : char, sequence : u8, payload : &str) -> String {
+    let body = format!("{}:{}:{}", msg_type, sequence, payload);
+    format!("{}*{:02X}\n", body, checksum(&body))
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub fn parse(line : &str) -> Result<Frame> {
+    let line = line.trim_end();
+    let (body, check) = line.rsplit_once('*').ok_or(WeatherError::from(format!("No checksum in {:?}", line)))?;
+
+    let check = u8::from_str_radix(check, 16).map_err(|_| WeatherError::from(format!("Bad checksum in {:?}", line)))?;
+    if checksum(body) != check {
+        metrics::SERIAL_CHECKSUM_FAILURES.inc();
+        return Err(WeatherError::from(format!("Checksum mismatch in {:?}", line)));
+    }
+
+    let mut fields = body.splitn(3, ':');
+    let msg_type = fields.next().unwrap_or_default();
+    let sequence = fields.next().and_then(|sequence| sequence.parse::<u8>().ok())
+        .ok_or(WeatherError::from(format!("Bad sequence in {:?}", line)))?;
+    let payload = fields.next().ok_or(WeatherError::from(format!("No payload in {:?}", line)))?;
+
+    let message = match msg_type {
+        "H" => Message::Hello(String::from(payload)),
+        "W" => Message::Wind(payload.trim().parse::<f32>()?),
+        "D" => Message::Direction(payload.parse::<u16>()?),
+        "R" => Message::Rain(payload.parse::<u16>()?),
+        "E" => Message::Diagnostic(String::from(payload)),
+        "P" => Message::Window(payload.parse::<u8>()?),
+        _ => return Err(WeatherError::from(format!("Unknown message type in {:?}", line)))
+    };
+    Ok(Frame { sequence, message })
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Tracks sequence numbers to count lost lines
+pub struct Sequence {
+    expected : Option<u8>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Sequence {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub const fn new() -> Self {
+        Self {
+            expected : None
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Number of lines lost before this frame. A hello starts the count again.
+    pub fn check(&mut self, frame : &Frame) -> u8 {
+        let lost = match (&frame.message, self.expected) {
+            (Message::Hello(_), _) | (_, None) => 0,
+            (_, Some(expected)) => frame.sequence.wrapping_sub(expected)
+        };
+        self.expected = Some(frame.sequence.wrapping_add(1));
+        lost
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_checksum() {
+        assert_eq!(checksum(""), 0);
+        assert_eq!(checksum("W:17:12.3"), 0x4F);
+        assert_eq!(encode('W', 17, "12.3"), "W:17:12.3*4F\n");
+    }
+
+    #[test]
+    fn check_parse() {
+        assert_eq!(parse("W:17:12.3*4F\r\n").unwrap(), Frame { sequence : 17, message : Message::Wind(12.3) });
+        assert_eq!(parse(&encode('H', 0, "wind 2.1")).unwrap().message, Message::Hello(String::from("wind 2.1")));
+        assert_eq!(parse(&encode('D', 1, "785")).unwrap().message, Message::Direction(785));
+        assert_eq!(parse(&encode('R', 255, "65535")).unwrap(), Frame { sequence : 255, message : Message::Rain(65535) });
+        assert_eq!(parse(&encode('E', 2, "overflow:3")).unwrap().message, Message::Diagnostic(String::from("overflow:3")));
+        assert_eq!(parse(&encode('P', 3, "1")).unwrap().message, Message::Window(1));
+    }
+
+    #[test]
+    fn check_firmware_lines() {
+        // As sent by outdoor/ardunio/main.c
+        let lines = ["H:0:wind 2.1*61", "W:1:12.3*78", "W:2:0.0*4B", "D:4:785*4A", "R:5:65535*57", "E:6:overflow:12*56",
+                     "P:5:10*64"];
+        let messages : Vec<Message> = lines.iter().map(|line| parse(line).unwrap().message).collect();
+        assert_eq!(messages, [Message::Hello(String::from("wind 2.1")), Message::Wind(12.3), Message::Wind(0.0),
+                              Message::Direction(785), Message::Rain(65535), Message::Diagnostic(String::from("overflow:12")),
+                              Message::Window(10)]);
+    }
+
+    #[test]
+    fn check_parse_errors() {
+        // Old firmware's bare speeds
+        assert!(parse("  12.3").is_err());
+        // Corrupt
+        assert!(parse("W:17:12.4*4F").is_err());
+        assert!(parse("W:17:12.3*ZZ").is_err());
+        assert!(parse(&encode('W', 0, "fast")).is_err());
+        assert!(parse(&encode('Q', 0, "1")).is_err());
+        assert!(parse(&encode('D', 0, "-1")).is_err());
+        assert!(parse(&format!("W:256:1*{:02X}", checksum("W:256:1"))).is_err());
+        assert!(parse(&encode('W', 0, "")[2..]).is_err());
+        assert!(parse(&encode('P', 0, "300")).is_err());
+    }
+
+    #[test]
+    fn check_sequence() {
+        let mut sequence = Sequence::new();
+        let frame = |seq, message| Frame { sequence : seq, message };
+
+        assert_eq!(sequence.check(&frame(10, Message::Wind(1.0))), 0);
+        assert_eq!(sequence.check(&frame(11, Message::Wind(1.0))), 0);
+        assert_eq!(sequence.check(&frame(14, Message::Rain(1))), 2);
+        assert_eq!(sequence.check(&frame(255, Message::Wind(1.0))), 240);
+        assert_eq!(sequence.check(&frame(0, Message::Wind(1.0))), 0);
+        assert_eq!(sequence.check(&frame(0, Message::Hello(String::new()))), 0);
+        assert_eq!(sequence.check(&frame(1, Message::Wind(1.0))), 0);
+    }
+}
@@ -0,0 +1,261 @@
+//!
+//! Tipping bucket rain gauge, the Arduino counts tips and reports the running count on the wind serial link
+//!
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone};
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Tip counts as reported by the Arduino. The count is a running total that wraps at 16 bits, so a lost line
+/// loses no tips, and starts again from zero when the Arduino restarts.
+pub struct Gauge {
+    last_count : Option<u16>,
+    tips : u32
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Gauge {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub const fn new() -> Self {
+        Self {
+            last_count : None,
+            tips : 0
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// The Arduino has restarted, so its count starts from zero again
+    pub fn restart(&mut self) {
+        self.last_count = Some(0);
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn add_count(&mut self, count : u16) {
+        // The first count seen after we start could include tips from before, so it is only the baseline
+        if let Some(last_count) = self.last_count {
+            self.tips += count.wrapping_sub(last_count) as u32;
+        }
+        self.last_count = Some(count);
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Tips since the last call
+    pub fn take_tips(&mut self) -> u32 {
+        std::mem::take(&mut self.tips)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Summary {
+    rainfall : f32,
+    rate : f32,
+    daily : f32
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Summary {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Rainfall in mm over the sample period
+    pub fn get_rainfall(&self) -> f32 {
+        self.rainfall
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Average rain rate in mm/h over the sample period
+    pub fn get_rate(&self) -> f32 {
+        self.rate
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Rainfall in mm since the start of the rain day
+    pub fn get_daily(&self) -> f32 {
+        self.daily
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1}mm ({:.1} mm/h) {:.1}mm today", self.rainfall, self.rate, self.daily)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// The rain day a time falls in, rain days start at reset_hour local time
+fn rain_day<Tz : TimeZone>(unix_time : i64, reset_hour : u32, tz : &Tz) -> NaiveDate {
+    let time = DateTime::from_timestamp(unix_time, 0).expect("invalid timestamp").with_timezone(tz);
+    (time.naive_local() - Duration::hours(reset_hour as i64)).date()
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Rain {
+    gauge : Arc<Mutex<Gauge>>,
+    mm_per_tip : f32,
+    reset_hour : u32,
+    day : Option<NaiveDate>,
+    daily : f32
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Rain {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(mm_per_tip : f32, reset_hour : u32) -> Self {
+        Self {
+            gauge : Arc::new(Mutex::new(Gauge::new())),
+            mm_per_tip,
+            reset_hour,
+            day : None,
+            daily : 0.0
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// For the serial thread to add tip counts to
+    pub fn get_gauge(&self) -> Arc<Mutex<Gauge>> {
+        self.gauge.clone()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Carry on with the daily total from the stored rainfall, so a restart doesn't lose it
+    pub fn restore(&mut self, conn : &sqlite::Connection, db_table : &str, unix_time : i64) -> weather_err::Result<()> {
+        let day = rain_day(unix_time, self.reset_hour, &Local);
+        let start = Local.from_local_datetime(&day.and_hms_opt(self.reset_hour, 0, 0).unwrap()).earliest()
+            .map_or(unix_time - 24 * 60 * 60, |start| start.timestamp());
+
+        let rows = database::get_rows(conn, db_table, &[String::from("precipitation")], start, Some(unix_time), None)?;
+        self.daily = rows.iter().filter_map(|row| row.values[0]).sum::<f64>() as f32;
+        self.day = Some(day);
+        Ok(())
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Rainfall since the last sample, period_secs ago
+    pub fn sample(&mut self, unix_time : i64, period_secs : u32) -> Summary {
+        self.sample_in(unix_time, period_secs, &Local)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn sample_in<Tz : TimeZone>(&mut self, unix_time : i64, period_secs : u32, tz : &Tz) -> Summary {
+        let tips = self.gauge.lock().expect("Unexpected failure to lock mutex").take_tips();
+        let rainfall = tips as f32 * self.mm_per_tip;
+
+        // The tick at the reset hour ends the old day, so its rain belongs to it
+        let day = rain_day(unix_time - 1, self.reset_hour, tz);
+        if self.day != Some(day) {
+            self.day = Some(day);
+            self.daily = 0.0;
+        }
+        self.daily += rainfall;
+
+        Summary {
+            rainfall,
+            rate : rainfall * 3600.0 / period_secs as f32,
+            daily : self.daily
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use chrono::{FixedOffset, Utc};
+
+    #[test]
+    fn check_gauge() {
+        let mut gauge = Gauge::new();
+        gauge.add_count(100);
+        assert_eq!(gauge.take_tips(), 0);
+        gauge.add_count(102);
+        gauge.add_count(105);
+        assert_eq!(gauge.take_tips(), 5);
+        assert_eq!(gauge.take_tips(), 0);
+
+        gauge.add_count(65535);
+        gauge.take_tips();
+        gauge.add_count(2);
+        assert_eq!(gauge.take_tips(), 3);
+
+        gauge.restart();
+        gauge.add_count(1);
+        assert_eq!(gauge.take_tips(), 1);
+    }
+
+    #[test]
+    fn check_rain_day() {
+        // 2024-07-15 08:30 and 09:30 UTC
+        assert_eq!(rain_day(1721032200, 9, &Utc), NaiveDate::from_ymd_opt(2024, 7, 14).unwrap());
+        assert_eq!(rain_day(1721035800, 9, &Utc), NaiveDate::from_ymd_opt(2024, 7, 15).unwrap());
+        assert_eq!(rain_day(1721032200, 0, &Utc), NaiveDate::from_ymd_opt(2024, 7, 15).unwrap());
+        // 09:30 local in UTC+2
+        assert_eq!(rain_day(1721028600, 9, &FixedOffset::east_opt(7200).unwrap()), NaiveDate::from_ymd_opt(2024, 7, 15).unwrap());
+    }
+
+    #[test]
+    fn check_sample() {
+        let mut rain = Rain::new(0.2, 9);
+        let gauge = rain.get_gauge();
+        gauge.lock().unwrap().add_count(0);
+
+        // 2024-07-15 08:45 UTC
+        gauge.lock().unwrap().add_count(3);
+        let summary = rain.sample_in(1721033100, 900, &Utc);
+        assert_relative_eq!(summary.get_rainfall(), 0.6);
+        assert_relative_eq!(summary.get_rate(), 2.4);
+        assert_relative_eq!(summary.get_daily(), 0.6);
+
+        // 09:00, still yesterday's rain
+        gauge.lock().unwrap().add_count(5);
+        let summary = rain.sample_in(1721034000, 900, &Utc);
+        assert_relative_eq!(summary.get_daily(), 1.0);
+
+        // 09:15, new day
+        gauge.lock().unwrap().add_count(6);
+        let summary = rain.sample_in(1721034900, 900, &Utc);
+        assert_relative_eq!(summary.get_rainfall(), 0.2);
+        assert_relative_eq!(summary.get_daily(), 0.2);
+
+        let summary = rain.sample_in(1721035800, 900, &Utc);
+        assert_relative_eq!(summary.get_rainfall(), 0.0);
+        assert_relative_eq!(summary.get_daily(), 0.2);
+    }
+
+    #[test]
+    fn check_restore() {
+        use chrono::Timelike;
+
+        let conn = sqlite::open(":memory:").unwrap();
+        database::create_table(&conn, "Outdoor", &["precipitation"]).unwrap();
+        let now = Local::now();
+        let unix_time = now.timestamp();
+        database::insert(&conn, "Outdoor", unix_time - 3600, &[("precipitation", Some(1.5))]).unwrap();
+        database::insert(&conn, "Outdoor", unix_time - 1800, &[("precipitation", Some(0.5))]).unwrap();
+        database::insert(&conn, "Outdoor", unix_time - 13 * 3600, &[("precipitation", Some(10.0))]).unwrap();
+
+        // The rain day started between 11 and 12 hours ago
+        let mut rain = Rain::new(0.2, (now.hour() + 12) % 24);
+        rain.restore(&conn, "Outdoor", unix_time).unwrap();
+        assert_relative_eq!(rain.daily, 2.0);
+    }
+}
@@ -0,0 +1,174 @@
+//!
+//! Serial port set up, so the port doesn't depend on whatever state the last user left it in
+//!
+
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use weather_err::{Result, WeatherError};
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Parity {
+    None,
+    Even,
+    Odd
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[derive(Clone, PartialEq, Debug)]
+pub struct Settings {
+    baud : u32,
+    data_bits : u8,
+    parity : Parity,
+    stop_bits : u8
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Settings {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// framing is data bits, parity and stop bits, e.g. 8N2
+    pub fn new(baud : u32, framing : &str) -> Result<Self> {
+        let bad_framing = || WeatherError::from(format!("Bad serial framing {}, should be like 8N1", framing));
+        let framing : Vec<char> = framing.chars().collect();
+        if framing.len() != 3 {
+            return Err(bad_framing());
+        }
+        let data_bits = match framing[0] {
+            '5' => 5, '6' => 6, '7' => 7, '8' => 8,
+            _ => return Err(bad_framing())
+        };
+        let parity = match framing[1].to_ascii_uppercase() {
+            'N' => Parity::None,
+            'E' => Parity::Even,
+            'O' => Parity::Odd,
+            _ => return Err(bad_framing())
+        };
+        let stop_bits = match framing[2] {
+            '1' => 1, '2' => 2,
+            _ => return Err(bad_framing())
+        };
+        speed(baud)?;
+        Ok(Self { baud, data_bits, parity, stop_bits })
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn speed(baud : u32) -> Result<libc::speed_t> {
+    Ok(match baud {
+        1200 => libc::B1200,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        _ => return Err(WeatherError::from(format!("Unsupported baud rate {}", baud)))
+    })
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn check(result : libc::c_int) -> Result<()> {
+    if result < 0 {
+        return Err(WeatherError::from(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Raw mode, so no echo, line editing or translation of CR/LF, with reads blocking until there is at least one byte
+pub fn configure(fd : libc::c_int, settings : &Settings) -> Result<()> {
+    let mut tios : libc::termios = unsafe { std::mem::zeroed() };
+    check(unsafe { libc::tcgetattr(fd, &mut tios) })?;
+
+    unsafe { libc::cfmakeraw(&mut tios) };
+
+    tios.c_cflag &= !(libc::CSIZE | libc::PARENB | libc::PARODD | libc::CSTOPB | libc::CRTSCTS);
+    tios.c_cflag |= libc::CREAD | libc::CLOCAL | match settings.data_bits {
+        5 => libc::CS5,
+        6 => libc::CS6,
+        7 => libc::CS7,
+        _ => libc::CS8
+    };
+    match settings.parity {
+        Parity::None => (),
+        Parity::Even => tios.c_cflag |= libc::PARENB,
+        Parity::Odd => tios.c_cflag |= libc::PARENB | libc::PARODD
+    }
+    if settings.stop_bits == 2 {
+        tios.c_cflag |= libc::CSTOPB;
+    }
+    tios.c_cc[libc::VMIN] = 1;
+    tios.c_cc[libc::VTIME] = 0;
+
+    let speed = speed(settings.baud)?;
+    check(unsafe { libc::cfsetispeed(&mut tios, speed) })?;
+    check(unsafe { libc::cfsetospeed(&mut tios, speed) })?;
+    check(unsafe { libc::tcsetattr(fd, libc::TCSANOW, &tios) })?;
+
+    // Anything already buffered was read with the old settings
+    check(unsafe { libc::tcflush(fd, libc::TCIFLUSH) })
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Open and configure the device, not as the controlling terminal
+pub fn open(dev_name : &str, settings : &Settings) -> Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(dev_name)?;
+    configure(file.as_raw_fd(), settings)?;
+    Ok(file)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_settings() {
+        let settings = Settings::new(9600, "8N2").unwrap();
+        assert_eq!(settings, Settings { baud : 9600, data_bits : 8, parity : Parity::None, stop_bits : 2 });
+        assert_eq!(Settings::new(115200, "7e1").unwrap().parity, Parity::Even);
+
+        assert!(Settings::new(9600, "8N").is_err());
+        assert!(Settings::new(9600, "9N1").is_err());
+        assert!(Settings::new(9600, "8X1").is_err());
+        assert!(Settings::new(9600, "8N3").is_err());
+        assert!(Settings::new(9601, "8N1").is_err());
+    }
+
+    #[test]
+    fn check_configure() {
+        // A pseudo terminal takes the same settings as a real serial port, except Linux always makes it 8 bits without parity
+        let (mut master, mut slave) = (0, 0);
+        let result = unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), std::ptr::null()) };
+        assert_eq!(result, 0);
+
+        configure(slave, &Settings::new(19200, "8N2").unwrap()).unwrap();
+
+        let mut tios : libc::termios = unsafe { std::mem::zeroed() };
+        assert_eq!(unsafe { libc::tcgetattr(slave, &mut tios) }, 0);
+        assert_eq!(unsafe { libc::cfgetospeed(&tios) }, libc::B19200);
+        assert_eq!(tios.c_cflag & libc::CSIZE, libc::CS8);
+        assert_ne!(tios.c_cflag & libc::CSTOPB, 0);
+        assert_eq!(tios.c_lflag & (libc::ICANON | libc::ECHO), 0);
+
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//!
+//! Solar radiation, the light sensor is read every few seconds and summarised at each tick
+//!
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use bh1750::Bh1750;
+use sensor::Sensor;
+use weather_err::Result;
+
+const READ_INTERVAL_SECS : u64 = 10;
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Summary {
+    mean_lux : Option<f32>,
+    max_lux : Option<f32>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Summary {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Mean illuminance in lx over the sample period, None without a sensor or readings
+    pub fn get_illuminance(&self) -> Option<f32> {
+        self.mean_lux
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_max_illuminance(&self) -> Option<f32> {
+        self.max_lux
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Mean estimated irradiance in W/m²
+    pub fn get_irradiance(&self) -> Option<f32> {
+        self.mean_lux.map(bh1750::irradiance)
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_max_irradiance(&self) -> Option<f32> {
+        self.max_lux.map(bh1750::irradiance)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.mean_lux, self.max_lux) {
+            (Some(mean), Some(max)) => write!(f, "{:.0} lx ({:.0} W/m²) max {:.0} lx", mean, bh1750::irradiance(mean), max),
+            _ => write!(f, "no light readings")
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Solar {
+    readings : Arc<Mutex<::stats::Accumulator<i64>>>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Solar {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new() -> Self {
+        Self {
+            readings : Arc::new(Mutex::new(::stats::Accumulator::new()))
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn read(sensor : &mut Bh1750) -> Result<f32> {
+        sensor.trigger()?;
+        sensor::wait_ready(sensor)?;
+        sensor.sample()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Read the sensor until the program ends. Without a sensor the summaries are empty.
+    pub fn start(&self, mut sensor : Bh1750) {
+        let readings = self.readings.clone();
+
+        thread::spawn(move || {
+            let mut initialised = false;
+            loop {
+                // A sensor that lost power has forgotten its settings
+                if !initialised {
+                    match sensor.init() {
+                        Ok(()) => initialised = true,
+                        Err(error) => println!("Failed to initialise light sensor {:?}", error)
+                    }
+                }
+                match Self::read(&mut sensor) {
+                    Ok(lux) => {
+                        let unix_time = chrono::Local::now().timestamp();
+                        readings.lock().expect("Unexpected failure to lock mutex").add(unix_time, lux as f64);
+                    },
+                    Err(error) => {
+                        println!("Failed to read light sensor {:?}", error);
+                        initialised = false;
+                    }
+                }
+                thread::sleep(Duration::from_secs(READ_INTERVAL_SECS));
+            }
+        });
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn sample(&self) -> Summary {
+        let mut readings = self.readings.lock().expect("Unexpected failure to lock mutex");
+        let result = Summary {
+            mean_lux : readings.get_mean().map(|mean| mean as f32),
+            max_lux : readings.get_max().map(|(_, max)| max as f32)
+        };
+        readings.reset();
+        result
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn check_sample() {
+        let solar = Solar::new();
+        let summary = solar.sample();
+        assert!(summary.get_irradiance().is_none());
+        assert_eq!(format!("{}", summary), "no light readings");
+
+        for (time, lux) in [(0, 20000.0), (10, 60000.0), (20, 40000.0)] {
+            solar.readings.lock().unwrap().add(time, lux);
+        }
+        let summary = solar.sample();
+        assert_relative_eq!(summary.get_illuminance().unwrap(), 40000.0);
+        assert_relative_eq!(summary.get_max_illuminance().unwrap(), 60000.0);
+        assert_relative_eq!(summary.get_irradiance().unwrap(), 315.7, max_relative = 0.001);
+        assert_relative_eq!(summary.get_max_irradiance().unwrap(), 473.6, max_relative = 0.001);
+        assert!(solar.sample().get_illuminance().is_none());
+    }
+}
@@ -1,12 +1,25 @@
+use std::collections::VecDeque;
 use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// WMO averaging periods, in seconds
+const GUST_SECS : f64 = 3.0;
+const SHORT_MEAN_SECS : f64 = 2.0 * 60.0;
+const LONG_MEAN_SECS : f64 = 10.0 * 60.0;
+
+/// The Arduino firmware's measurement window unless it is told otherwise
+pub const DEFAULT_WINDOW_SECS : u8 = 2;
 
 
 //----------------------------------------------------------------------------------------------------------------------------------
+/// Wind speeds from the Arduino, each the mean over its measurement window. It only sends speeds once the
+/// anemometer turns, so time without readings is calm.
 pub struct Accumulated {
-    max_value : f32,
-    min_value : f32,
-    sum : f64,
-    num_of : u16,
+    speeds : ::stats::Accumulator<f64>,
+    gust_value : f32,
+    window_secs : f64,
+    started : Option<f64>,
+    history : VecDeque<(f64, f32)>
 }
 
 
@@ -15,6 +28,16 @@ pub struct Summary {
     max_value : f32,
     min_value : f32,
     ave_value : f32,
+    std_dev : f32,
+    gust_value : f32,
+    short_mean : f32,
+    long_mean : f32
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub fn now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).expect("Clock before 1970").as_secs_f64()
 }
 
 
@@ -22,19 +45,16 @@ pub struct Summary {
 impl Summary {
 
     //------------------------------------------------------------------------------------------------------------------------------
-    pub fn new(accum : &Accumulated) -> Summary {
-        if accum.num_of > 0 {
-            Summary {
-                max_value : accum.max_value,
-                min_value : accum.min_value,
-                ave_value : (accum.sum / (accum.num_of as f64)) as f32
-            }
-        } else {
-            Summary {
-                max_value : 0.0,
-                min_value : 0.0,
-                ave_value : 0.0
-            }
+    pub fn new(accum : &Accumulated, time : f64) -> Summary {
+        let speeds = &accum.speeds;
+        Summary {
+            max_value : speeds.get_max().map_or(0.0, |(_, max)| max as f32),
+            min_value : speeds.get_min().map_or(0.0, |(_, min)| min as f32),
+            ave_value : speeds.get_mean().unwrap_or(0.0) as f32,
+            std_dev : speeds.get_std_dev().unwrap_or(0.0) as f32,
+            gust_value : accum.gust_value,
+            short_mean : accum.running_mean(time, SHORT_MEAN_SECS),
+            long_mean : accum.running_mean(time, LONG_MEAN_SECS)
         }
     }
 
@@ -52,13 +72,44 @@ impl Summary {
     pub fn get_average(&self) -> f32 {
         self.ave_value
     }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Standard deviation of the readings, a measure of how gusty it was
+    pub fn get_std_dev(&self) -> f32 {
+        self.std_dev
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Highest 3 second mean
+    pub fn get_gust(&self) -> f32 {
+        self.gust_value
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Mean over the 2 minutes up to the sample
+    pub fn get_sustained_2min(&self) -> f32 {
+        self.short_mean
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Mean over the 10 minutes up to the sample
+    pub fn get_sustained_10min(&self) -> f32 {
+        self.long_mean
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Beaufort force of the 10 minute mean
+    pub fn get_beaufort(&self) -> u8 {
+        meteo::beaufort(self.long_mean)
+    }
 }
 
 
 //----------------------------------------------------------------------------------------------------------------------------------
 impl fmt::Display for Summary {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "({:.1} - {:.1} -  {:.1}) m/s", self.min_value, self.ave_value, self.max_value)
+        write!(f, "({:.1} - {:.1} -  {:.1}) m/s gust {:.1} m/s sustained {:.1}/{:.1} m/s F{}", self.min_value, self.ave_value,
+               self.max_value, self.gust_value, self.short_mean, self.long_mean, self.get_beaufort())
     }
 }
 
@@ -69,36 +120,71 @@ impl Accumulated {
     //------------------------------------------------------------------------------------------------------------------------------
     pub const fn new() -> Self {
         Accumulated {
-            max_value : 0.0,
-            min_value : 0.0,
-            sum : 0.0,
-            num_of : 0
+            speeds : ::stats::Accumulator::new(),
+            gust_value : 0.0,
+            window_secs : DEFAULT_WINDOW_SECS as f64,
+            started : None,
+            history : VecDeque::new()
         }
     }
 
 
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Readings each covering window_secs, with calm from start_time until the first
+    pub fn with_window(window_secs : u8, start_time : f64) -> Self {
+        Accumulated {
+            window_secs : window_secs as f64,
+            started : Some(start_time),
+            ..Self::new()
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Mean over the period up to time, or since the start if that is shorter. Each reading counts if most of
+    /// its window is in the period.
+    fn running_mean(&self, time : f64, period : f64) -> f32 {
+        let started = match self.started {
+            Some(started) => started,
+            None => return 0.0
+        };
+        let windows = (period / self.window_secs).floor().max(1.0);
+        let span = (windows * self.window_secs).min(time - started).max(self.window_secs);
+
+        let run : f64 = self.history.iter().rev()
+            .take_while(|(reading_time, _)| *reading_time > time - span + self.window_secs / 2.0)
+            .filter(|(reading_time, _)| *reading_time <= time)
+            .map(|(_, value)| *value as f64 * self.window_secs)
+            .sum();
+        (run / span) as f32
+    }
+
+
     //------------------------------------------------------------------------------------------------------------------------------
     pub fn add(&mut self, value : f32) {
-        if self.num_of > 0 {
-            if value > self.max_value {
-                self.max_value = value;
-            } else if value < self.min_value {
-                self.min_value = value;
-            }
-            self.num_of += 1;
-            self.sum += value as f64;
-        } else {
-            self.max_value = value;
-            self.min_value = value;
-            self.sum = value as f64;
-            self.num_of = 1;
+        self.add_at(now(), value);
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// A reading for the window ending at time
+    pub fn add_at(&mut self, time : f64, value : f32) {
+        self.started.get_or_insert(time - self.window_secs);
+        self.history.push_back((time, value));
+        while self.history.front().is_some_and(|(reading_time, _)| *reading_time <= time - LONG_MEAN_SECS) {
+            self.history.pop_front();
         }
+        self.gust_value = self.gust_value.max(self.running_mean(time, GUST_SECS));
+
+        self.speeds.add(time, value as f64);
     }
 
     //------------------------------------------------------------------------------------------------------------------------------
-    pub fn sample(&mut self) -> Summary {
-        let result = Summary::new(&self);
-        self.num_of = 0;
+    /// Statistics since the last sample, with the sustained means up to time
+    pub fn sample(&mut self, time : f64) -> Summary {
+        let result = Summary::new(self, time);
+        self.speeds.reset();
+        self.gust_value = 0.0;
         result
     }
 }
@@ -108,7 +194,10 @@ impl fmt::Debug for Accumulated {
 
     //------------------------------------------------------------------------------------------------------------------------------
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Acc({},{},{},{})", self.max_value, self.min_value, self.sum, self.num_of)
+        let max = self.speeds.get_max().map_or(0.0, |(_, max)| max);
+        let min = self.speeds.get_min().map_or(0.0, |(_, min)| min);
+        let sum = self.speeds.get_mean().unwrap_or(0.0) * self.speeds.get_count() as f64;
+        write!(f, "Acc({},{},{},{})", max, min, sum, self.speeds.get_count())
     }
 }
 
@@ -124,25 +213,71 @@ mod tests {
         acc.add(20.4);
         acc.add(10.6);
         acc.add(5.6);
-        assert_eq!(acc.max_value, 20.4);
-        assert_eq!(acc.min_value, 5.6);
-        assert_eq!(acc.num_of, 3);
-        assert_relative_eq!(acc.sum, 20.4 + 10.6 + 5.6, max_relative = 0.01);
+        assert_eq!(acc.speeds.get_max().map(|(_, max)| max as f32), Some(20.4));
+        assert_eq!(acc.speeds.get_min().map(|(_, min)| min as f32), Some(5.6));
+        assert_eq!(acc.speeds.get_count(), 3);
 
-        let summary = acc.sample();
-        assert_eq!(acc.num_of, 0);
+        let summary = acc.sample(now());
+        assert_eq!(acc.speeds.get_count(), 0);
 
         assert_eq!(summary.max_value, 20.4);
         assert_eq!(summary.min_value, 5.6);
         assert_relative_eq!(summary.ave_value, (20.4 + 10.6 + 5.6) / 3.0, max_relative = 0.01);
+        assert_relative_eq!(summary.get_std_dev(), 7.53, max_relative = 0.01);
 
-
+        // Rising after a fall has to update the max as well as the min
         acc.add(3.4);
         acc.add(9.6);
-        assert_eq!(acc.max_value, 9.6);
-        assert_eq!(acc.min_value, 3.4);
-        assert_eq!(acc.num_of, 2);
-        assert_relative_eq!(acc.sum, 9.6 + 3.4, max_relative = 0.01);
+        assert_eq!(acc.speeds.get_max().map(|(_, max)| max as f32), Some(9.6));
+        assert_eq!(acc.speeds.get_min().map(|(_, min)| min as f32), Some(3.4));
+        assert_eq!(acc.speeds.get_count(), 2);
+        acc.add(1.0);
+        acc.add(12.0);
+        let summary = acc.sample(now());
+        assert_eq!(summary.get_min(), 1.0);
+        assert_eq!(summary.get_max(), 12.0);
+    }
+
+    #[test]
+    fn check_gust_and_sustained() {
+        // One second readings, 12 m/s for 3 seconds in 5 m/s, after 30 seconds of calm
+        let mut acc = Accumulated::with_window(1, 0.0);
+        for second in 31..=180 {
+            acc.add_at(second as f64, if (41..=43).contains(&second) { 12.0 } else { 5.0 });
+        }
+        let summary = acc.sample(180.0);
+        assert_eq!(summary.get_max(), 12.0);
+        assert_relative_eq!(summary.get_gust(), 12.0);
+        assert_relative_eq!(summary.get_sustained_2min(), 5.0);
+        // Only 3 minutes so far
+        assert_relative_eq!(summary.get_sustained_10min(), (147.0 * 5.0 + 3.0 * 12.0) / 180.0, max_relative = 0.001);
+
+        // A 2 second spike is not a 3 second gust
+        for second in 181..=800 {
+            acc.add_at(second as f64, if (190..=191).contains(&second) { 12.0 } else { 5.0 });
+        }
+        let summary = acc.sample(800.0);
+        assert_relative_eq!(summary.get_gust(), (12.0 * 2.0 + 5.0) / 3.0, max_relative = 0.001);
+        assert_relative_eq!(summary.get_sustained_10min(), 5.0);
+        assert_eq!(summary.get_beaufort(), 3);
+
+        // Calm since
+        let summary = acc.sample(860.0);
+        assert_eq!(summary.get_gust(), 0.0);
+        assert_relative_eq!(summary.get_sustained_2min(), 2.5);
+        assert_relative_eq!(summary.get_sustained_10min(), 4.5);
+    }
+
+    #[test]
+    fn check_window() {
+        // The default 2 second window rounds the gust period down to one reading
+        let mut acc = Accumulated::with_window(2, 0.0);
+        for time in (2..=120).step_by(2) {
+            acc.add_at(time as f64, if time == 60 { 9.0 } else { 3.0 });
+        }
+        let summary = acc.sample(120.0);
+        assert_eq!(summary.get_gust(), 9.0);
+        assert_relative_eq!(summary.get_sustained_2min(), 3.1);
     }
 
     #[test]
@@ -0,0 +1,344 @@
+//!
+//! Outdoor temperature and humidity, either a single shot at each tick or the mean of the SHT31's periodic measurements.
+//! The sensor's heater can be run in short bursts to clear condensation, readings taken while it is on or cooling
+//! down afterwards are flagged. Alert limits, e.g. for frost, drive the sensor's ALERT pin while it is measuring periodically.
+//!
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use sensor::Sensor;
+use sht31::{AlertLimits, Rate, Repeatability, Sht31};
+use weather_err::{Result, WeatherError};
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeaterState {
+    Off,
+    On,
+    Recovering
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// A heater burst every interval, starting offset_secs into it, followed by time for the sensor to cool down
+#[derive(Clone, Copy)]
+pub struct Heater {
+    interval_secs : i64,
+    burst_secs : i64,
+    recovery_secs : i64,
+    offset_secs : i64
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Heater {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(interval_secs : i64, burst_secs : i64, recovery_secs : i64, offset_secs : i64) -> Self {
+        Self { interval_secs, burst_secs, recovery_secs, offset_secs }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn state_at(&self, unix_time : i64) -> HeaterState {
+        let phase = (unix_time - self.offset_secs).rem_euclid(self.interval_secs);
+        if phase < self.burst_secs {
+            HeaterState::On
+        } else if phase < self.burst_secs + self.recovery_secs {
+            HeaterState::Recovering
+        } else {
+            HeaterState::Off
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+struct Means {
+    temperature : ::stats::Accumulator<i64>,
+    humidity : ::stats::Accumulator<i64>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Means {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    const fn new() -> Self {
+        Self {
+            temperature : ::stats::Accumulator::new(),
+            humidity : ::stats::Accumulator::new()
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn add(&mut self, unix_time : i64, summary : &sht31::Summary) {
+        self.temperature.add(unix_time, summary.get_temperature() as f64);
+        self.humidity.add(unix_time, summary.get_humidity() as f64);
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn take(&mut self) -> Option<sht31::Summary> {
+        let result = match (self.temperature.get_mean(), self.humidity.get_mean()) {
+            (Some(temperature), Some(humidity)) => Some(sht31::Summary::new(temperature as f32, humidity as f32)),
+            _ => None
+        };
+        self.temperature.reset();
+        self.humidity.reset();
+        result
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Periodic readings, those affected by the heater kept apart
+struct Readings {
+    clean : Means,
+    heated : Means
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Readings {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    const fn new() -> Self {
+        Self {
+            clean : Means::new(),
+            heated : Means::new()
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn add(&mut self, unix_time : i64, summary : &sht31::Summary, heated : bool) {
+        if heated {
+            self.heated.add(unix_time, summary);
+        } else {
+            self.clean.add(unix_time, summary);
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Means since the last call, only using heated readings if there is nothing else
+    fn take(&mut self) -> Option<(sht31::Summary, bool)> {
+        let heated = self.heated.take();
+        match self.clean.take() {
+            Some(summary) => Some((summary, false)),
+            None => heated.map(|summary| (summary, true))
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Log anything wrong in the status register and clear it
+fn check_status(sensor : &mut Sht31) -> Option<sht31::Status> {
+    match sensor.read_status() {
+        Ok(status) => {
+            if let Err(error) = status.check() {
+                println!("{:?}", error);
+                if let Err(error) = sensor.clear_status() {
+                    println!("Failed to clear SHT31 status {:?}", error);
+                }
+            }
+            Some(status)
+        },
+        Err(error) => {
+            println!("Failed to read SHT31 status {:?}", error);
+            None
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Temperature {
+    sensor : Arc<Mutex<Sht31>>,
+    repeatability : Repeatability,
+    rate : Option<Rate>,
+    heater : Option<Heater>,
+    alert_limits : Option<AlertLimits>,
+    readings : Arc<Mutex<Readings>>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Temperature {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// With a rate the sensor measures continuously and each sample is the mean since the last. Alert limits only have
+    /// an effect with a rate.
+    pub fn new(mut sensor : Sht31, repeatability : Repeatability, rate : Option<Rate>, heater : Option<Heater>,
+               alert_limits : Option<AlertLimits>) -> Self {
+        sensor.set_repeatability(repeatability);
+        Self {
+            sensor : Arc::new(Mutex::new(sensor)),
+            repeatability,
+            rate,
+            heater,
+            alert_limits,
+            readings : Arc::new(Mutex::new(Readings::new()))
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Reset the sensor, as it could still be in periodic mode from before, then start periodic measurements and the
+    /// heater schedule if there are any
+    pub fn start(&self) {
+        {
+            let mut sensor = self.sensor.lock().expect("Unexpected failure to lock mutex");
+            if let Err(error) = sensor.soft_reset() {
+                println!("Failed to reset SHT31 {:?}", error);
+            }
+            thread::sleep(Duration::from_millis(2));
+            check_status(&mut sensor);
+
+            let address = format!("{:#x}", sensor.get_address());
+            match sensor.read_serial_number() {
+                Ok(serial) => {
+                    println!("SHT31 at {} has serial number {:08x}", address, serial);
+                    metrics::SENSOR_INFO.set_labelled(&["sht31", &address, &format!("{:08x}", serial)], 1.0);
+                },
+                Err(error) => println!("Failed to read SHT31 serial number {:?}", error)
+            }
+        }
+        if self.rate.is_none() && self.heater.is_none() {
+            return;
+        }
+
+        let sensor = self.sensor.clone();
+        let readings = self.readings.clone();
+        let (rate, heater, repeatability, alert_limits) = (self.rate, self.heater, self.repeatability, self.alert_limits);
+
+        thread::spawn(move || {
+            let mut periodic = false;
+            let mut heater_on = false;
+            loop {
+                thread::sleep(Duration::from_millis(rate.map_or(1000, |rate| rate.get_period_ms())));
+                let unix_time = chrono::Local::now().timestamp();
+                let state = heater.map_or(HeaterState::Off, |heater| heater.state_at(unix_time));
+                let mut sensor = sensor.lock().expect("Unexpected failure to lock mutex");
+
+                if (state == HeaterState::On) != heater_on {
+                    // The heater and status commands aren't accepted in periodic mode
+                    if periodic {
+                        if let Err(error) = sensor.stop_periodic() {
+                            println!("Failed to stop periodic measurements {:?}", error);
+                        }
+                        periodic = false;
+                        thread::sleep(Duration::from_millis(2));
+                    }
+                    match sensor.set_heater(!heater_on) {
+                        Ok(()) => println!("SHT31 heater {}", if heater_on { "off" } else { "on" }),
+                        Err(error) => println!("Failed to switch SHT31 heater {:?}", error)
+                    }
+                    // A reset turns the heater off, so go by what the sensor says
+                    if let Some(status) = check_status(&mut sensor) {
+                        heater_on = status.get_heater_on();
+                    }
+                }
+
+                let rate = match rate {
+                    Some(rate) => rate,
+                    None => continue
+                };
+                if !periodic {
+                    // A reset puts the limits back to the defaults, so set them each time
+                    if let Some(limits) = &alert_limits {
+                        if let Err(error) = sensor.set_alert_limits(limits) {
+                            println!("Failed to set SHT31 alert limits {:?}", error);
+                        }
+                    }
+                    match sensor.start_periodic(rate, repeatability) {
+                        Ok(()) => periodic = true,
+                        Err(error) => println!("Failed to start periodic measurements {:?}", error)
+                    }
+                    continue;
+                }
+                match sensor.fetch() {
+                    Ok(summary) => {
+                        readings.lock().expect("Unexpected failure to lock mutex").add(unix_time, &summary, state != HeaterState::Off);
+                    },
+                    Err(error) => {
+                        println!("Failed to fetch temperature {:?}", error);
+                        let _ = sensor.stop_periodic();
+                        thread::sleep(Duration::from_millis(2));
+                        if let Some(status) = check_status(&mut sensor) {
+                            heater_on = status.get_heater_on();
+                        }
+                        periodic = false;
+                    }
+                }
+            }
+        });
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Temperature and humidity, and whether they were measured with the heater on or cooling down
+    pub fn sample(&self) -> Result<(sht31::Summary, bool)> {
+        if self.rate.is_some() {
+            return self.readings.lock().expect("Unexpected failure to lock mutex").take()
+                .ok_or(WeatherError::from("No temperature readings since the last sample"));
+        }
+        let mut sensor = self.sensor.lock().expect("Unexpected failure to lock mutex");
+        check_status(&mut sensor);
+        sensor.trigger()?;
+        sensor::wait_ready(&mut *sensor)?;
+
+        let unix_time = chrono::Local::now().timestamp();
+        let heated = self.heater.is_some_and(|heater| heater.state_at(unix_time) != HeaterState::Off);
+        Ok((sensor.sample()?, heated))
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn check_readings() {
+        let mut readings = Readings::new();
+        assert!(readings.take().is_none());
+
+        readings.add(0, &sht31::Summary::new(10.0, 80.0), false);
+        readings.add(1, &sht31::Summary::new(11.0, 70.0), false);
+        readings.add(2, &sht31::Summary::new(15.0, 40.0), true);
+        let (summary, heated) = readings.take().unwrap();
+        assert_relative_eq!(summary.get_temperature(), 10.5);
+        assert_relative_eq!(summary.get_humidity(), 75.0);
+        assert!(!heated);
+        assert!(readings.take().is_none());
+
+        // Only heated readings
+        readings.add(3, &sht31::Summary::new(15.0, 40.0), true);
+        let (summary, heated) = readings.take().unwrap();
+        assert_relative_eq!(summary.get_temperature(), 15.0);
+        assert!(heated);
+    }
+
+    #[test]
+    fn check_heater() {
+        // Hourly 30s bursts with 2 minutes to cool, starting 7.5 minutes past the hour
+        let heater = Heater::new(3600, 30, 120, 450);
+        assert_eq!(heater.state_at(0), HeaterState::Off);
+        assert_eq!(heater.state_at(449), HeaterState::Off);
+        assert_eq!(heater.state_at(450), HeaterState::On);
+        assert_eq!(heater.state_at(479), HeaterState::On);
+        assert_eq!(heater.state_at(480), HeaterState::Recovering);
+        assert_eq!(heater.state_at(599), HeaterState::Recovering);
+        assert_eq!(heater.state_at(600), HeaterState::Off);
+        assert_eq!(heater.state_at(3600 + 460), HeaterState::On);
+        assert_eq!(heater.state_at(-3600 + 460), HeaterState::On);
+    }
+}
@@ -0,0 +1,197 @@
+//!
+//! Wind vane, the Arduino reports the ADC reading of the vane's resistor network
+//!
+
+use std::fmt;
+
+// ADC readings for the common 16 position vane (Sparkfun, Misol, etc.) with a 10k pull up to the 5V ADC reference
+const DEFAULT_TABLE : [(u16, f32); 16] = [
+    (785, 0.0), (406, 22.5), (461, 45.0), (84, 67.5), (93, 90.0), (66, 112.5), (184, 135.0), (126, 157.5),
+    (287, 180.0), (244, 202.5), (630, 225.0), (599, 247.5), (944, 270.0), (827, 292.5), (886, 315.0), (702, 337.5)
+];
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Lookup from ADC reading to the compass direction the wind is coming from, in degrees
+pub struct Vane {
+    table : Vec<(u16, f32)>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Vane {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(table : Option<Vec<(u16, f32)>>) -> Self {
+        Self {
+            table : table.unwrap_or(DEFAULT_TABLE.to_vec())
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Direction for the table entry nearest the reading
+    pub fn get_direction(&self, adc : u16) -> Option<f32> {
+        self.table.iter()
+            .min_by_key(|(reading, _)| reading.abs_diff(adc))
+            .map(|(_, direction)| *direction)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Directions paired with the wind speed measured at the same time
+pub struct Accumulated {
+    direction : Option<f32>,
+    sum_east : f64,
+    sum_north : f64,
+    gust_speed : f32,
+    gust_direction : Option<f32>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Summary {
+    mean_direction : Option<f32>,
+    gust_direction : Option<f32>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Summary {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Vector averaged direction, weighted by speed. None when calm.
+    pub fn get_mean_direction(&self) -> Option<f32> {
+        self.mean_direction
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Direction at the time of the highest speed
+    pub fn get_gust_direction(&self) -> Option<f32> {
+        self.gust_direction
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.mean_direction, self.gust_direction) {
+            (Some(mean), Some(gust)) => write!(f, "from {:.0}° gusting from {:.0}°", mean, gust),
+            (Some(mean), None) => write!(f, "from {:.0}°", mean),
+            _ => write!(f, "calm")
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Accumulated {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub const fn new() -> Self {
+        Accumulated {
+            direction : None,
+            sum_east : 0.0,
+            sum_north : 0.0,
+            gust_speed : 0.0,
+            gust_direction : None
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// The Arduino sends the direction just before the speed it goes with
+    pub fn set_direction(&mut self, direction : f32) {
+        self.direction = Some(direction);
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn add_speed(&mut self, speed : f32) {
+        let direction = match self.direction.take() {
+            Some(direction) => direction,
+            None => return
+        };
+        let radians = (direction as f64).to_radians();
+        self.sum_east += speed as f64 * radians.sin();
+        self.sum_north += speed as f64 * radians.cos();
+
+        if speed > 0.0 && (self.gust_direction.is_none() || speed > self.gust_speed) {
+            self.gust_speed = speed;
+            self.gust_direction = Some(direction);
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn sample(&mut self) -> Summary {
+        let mean_direction = if self.sum_east.hypot(self.sum_north) > 1e-6 {
+            Some(self.sum_east.atan2(self.sum_north).to_degrees().rem_euclid(360.0) as f32)
+        } else {
+            None
+        };
+        let result = Summary {
+            mean_direction,
+            gust_direction : self.gust_direction
+        };
+        *self = Self::new();
+        result
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn check_vane() {
+        let vane = Vane::new(None);
+        assert_eq!(vane.get_direction(785), Some(0.0));
+        assert_eq!(vane.get_direction(90), Some(90.0));
+        assert_eq!(vane.get_direction(950), Some(270.0));
+        assert_eq!(vane.get_direction(600), Some(247.5));
+
+        let vane = Vane::new(Some(vec![(100, 0.0), (500, 180.0)]));
+        assert_eq!(vane.get_direction(250), Some(0.0));
+        assert_eq!(Vane::new(Some(Vec::new())).get_direction(250), None);
+    }
+
+    #[test]
+    fn check_vector_average() {
+        // Arithmetic mean of 350 and 10 would be 180
+        let mut acc = Accumulated::new();
+        acc.set_direction(350.0);
+        acc.add_speed(5.0);
+        acc.set_direction(10.0);
+        acc.add_speed(5.0);
+        let summary = acc.sample();
+        assert_abs_diff_eq!(summary.get_mean_direction().unwrap() % 360.0, 0.0, epsilon = 0.01);
+
+        // Stronger wind counts for more
+        acc.set_direction(90.0);
+        acc.add_speed(10.0);
+        acc.set_direction(180.0);
+        acc.add_speed(2.0);
+        // Speed without a new direction is ignored
+        acc.add_speed(20.0);
+        let summary = acc.sample();
+        assert_abs_diff_eq!(summary.get_mean_direction().unwrap(), 101.3, epsilon = 0.1);
+        assert_eq!(summary.get_gust_direction(), Some(90.0));
+    }
+
+    #[test]
+    fn check_calm() {
+        let mut acc = Accumulated::new();
+        acc.set_direction(90.0);
+        acc.add_speed(0.0);
+        let summary = acc.sample();
+        assert!(summary.get_mean_direction().is_none());
+        assert!(summary.get_gust_direction().is_none());
+        assert_eq!(format!("{}", summary), "calm");
+    }
+}
@@ -1,61 +1,274 @@
+use crate::protocol::{self, Message, Sequence};
+use crate::rain;
+use crate::serial;
 use crate::stats;
+use crate::vane::{self, Vane};
+use sensor::{Channel, Sensor};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use weather_err::Result;
+use std::time::{Duration, Instant};
+use std::io::{BufRead, BufReader, Write};
+use weather_err::{Result, WeatherError};
+
+// Wait before reopening the device, doubling each time it fails straight away
+const MIN_REOPEN_SECS : u64 = 1;
+const MAX_REOPEN_SECS : u64 = 60;
+
+const CHANNELS : [(&str, &str); 9] = [("max_speed", "m/s"), ("ave_speed", "m/s"), ("min_speed", "m/s"), ("gust_speed", "m/s"),
+                                      ("sustained_speed_2min", "m/s"), ("sustained_speed_10min", "m/s"), ("beaufort", "Bft"),
+                                      ("wind_direction", "°"), ("gust_direction", "°")];
 
 //----------------------------------------------------------------------------------------------------------------------------------
+#[derive(Clone)]
 pub struct Wind {
     pub speed : Arc<Mutex<stats::Accumulated>>,
-    pub dev_name : String
+    pub direction : Arc<Mutex<vane::Accumulated>>,
+    pub rain : Arc<Mutex<rain::Gauge>>,
+    pub vane : Arc<Vane>,
+    pub dev_name : String,
+    pub settings : serial::Settings,
+    pub window_secs : Option<u8>
 }
 
 //----------------------------------------------------------------------------------------------------------------------------------
 impl Wind {
 
     //------------------------------------------------------------------------------------------------------------------------------
-    pub fn new(dev_name : &str) -> Self {
+    /// The Arduino also reports rain gauge tips on the same link, they go to rain.
+    /// With window_secs the Arduino is told to measure over that many seconds instead of its default.
+    pub fn new(dev_name : &str, settings : serial::Settings, window_secs : Option<u8>, rain : Arc<Mutex<rain::Gauge>>,
+               vane : Vane) -> Self {
+        let window = window_secs.unwrap_or(stats::DEFAULT_WINDOW_SECS);
         Self {
             dev_name : dev_name.to_string(),
-            speed : Arc::new(Mutex::new(stats::Accumulated::new()))
+            settings,
+            window_secs,
+            speed : Arc::new(Mutex::new(stats::Accumulated::with_window(window, stats::now()))),
+            direction : Arc::new(Mutex::new(vane::Accumulated::new())),
+            rain,
+            vane : Arc::new(vane)
         }
     }
 
     //------------------------------------------------------------------------------------------------------------------------------
     pub fn start(&self) {
-        let dev_name = self.dev_name.clone();
-        let speed = self.speed.clone();
+        let wind = self.clone();
 
-        thread::spawn(move || { 
-            let _ = Self::task(dev_name, speed);
+        thread::spawn(move || {
+            wind.task();
         });
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    pub fn sample(&self) -> stats::Summary {
+    pub fn sample(&self, unix_time : i64) -> stats::Summary {
         let mut data = self.speed.lock().expect("Unexpected failure to lock mutex");
+        (*data).sample(unix_time as f64)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn sample_direction(&self) -> vane::Summary {
+        let mut data = self.direction.lock().expect("Unexpected failure to lock mutex");
         (*data).sample()
     }
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    pub fn task(dev_name: String, speed: Arc<Mutex<stats::Accumulated>>) -> Result<()> {
-        let f = File::open(&dev_name)?;
+    fn handle(&self, message : Message) {
+        match message {
+            Message::Hello(version) => {
+                println!("Arduino firmware {}", version);
+                self.rain.lock().expect("Unexpected failure to lock mutex").restart();
+            },
+            Message::Wind(value) => {
+                let mut data = self.speed.lock().expect("Unexpected failure to lock mutex");
+                (*data).add(value);
+                self.direction.lock().expect("Unexpected failure to lock mutex").add_speed(value);
+            },
+            Message::Direction(adc) => {
+                if let Some(value) = self.vane.get_direction(adc) {
+                    self.direction.lock().expect("Unexpected failure to lock mutex").set_direction(value);
+                }
+            },
+            Message::Rain(count) => self.rain.lock().expect("Unexpected failure to lock mutex").add_count(count),
+            Message::Diagnostic(text) => println!("Arduino reports {}", text),
+            Message::Window(secs) => println!("Arduino measuring wind over {} s", secs)
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Read until the device fails or goes away
+    fn read_device(&self) -> Result<()> {
+        let f = serial::open(&self.dev_name, &self.settings)?;
+        let mut writer = f.try_clone()?;
         let mut reader = BufReader::new(f);
+        let mut sequence = Sequence::new();
+        let mut sent = 0u8;
+        let mut window_set = false;
 
         loop {
-            let mut buffer = String::new();
-            reader.read_line(&mut buffer)?;
+            // Noise on the line needn't be valid UTF-8, so read bytes and let the checksum reject it
+            let mut buffer = Vec::new();
+            if reader.read_until(b'\n', &mut buffer)? == 0 {
+                return Err(WeatherError::from("End of file"));
+            }
+
+            match protocol::parse(&String::from_utf8_lossy(&buffer)) {
+                Ok(frame) => {
+                    metrics::WIND_LINES_PARSED.inc();
+                    metrics::SERIAL_LINES_LOST.add(sequence.check(&frame) as u64);
 
-            match buffer.trim().parse::<f32>() {
-                Ok(value) => {
-                    let mut data = speed.lock().expect("Unexpected failure to lock mutex");
-                   (*data).add(value);
+                    // A restart puts the Arduino back to its default window. Not every board restarts when
+                    // the port is opened, so the first line will do too.
+                    if let Some(window_secs) = self.window_secs {
+                        if !window_set || matches!(frame.message, Message::Hello(_)) {
+                            writer.write_all(protocol::encode('P', sent, &window_secs.to_string()).as_bytes())?;
+                            sent = sent.wrapping_add(1);
+                            window_set = true;
+                        }
+                    }
+                    self.handle(frame.message);
                 },
-                Err(..) => ()
-            };
+                Err(error) => {
+                    metrics::WIND_LINES_DROPPED.inc();
+                    println!("Dropped line from Arduino {:?}", error);
+                }
+            }
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Keep reading, reopening the device when it fails, e.g. after a USB reset takes it away for a while
+    pub fn task(&self) {
+        let mut delay_secs = MIN_REOPEN_SECS;
+
+        loop {
+            let opened = Instant::now();
+            if let Err(error) = self.read_device() {
+                println!("Wind device {} failed {:?}", self.dev_name, error);
+            }
+
+            // Only back off when it keeps failing
+            if opened.elapsed() > Duration::from_secs(MAX_REOPEN_SECS) {
+                delay_secs = MIN_REOPEN_SECS;
+            }
+            thread::sleep(Duration::from_secs(delay_secs));
+            delay_secs = (delay_secs * 2).min(MAX_REOPEN_SECS);
+
+            println!("Reopening wind device {}", self.dev_name);
+            metrics::SERIAL_REOPENS.inc();
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// The Arduino measures all the time, so reading summarises what it has reported since the last read. Start it first.
+impl Sensor for Wind {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_name(&self) -> &str {
+        "wind"
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_channels(&self) -> &[(&'static str, &'static str)] {
+        &CHANNELS
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn read(&mut self, unix_time : i64) -> Result<Vec<Channel>> {
+        let speed = self.sample(unix_time);
+        let direction = self.sample_direction();
+        let speeds = [speed.get_max(), speed.get_average(), speed.get_min(), speed.get_gust(), speed.get_sustained_2min(),
+                      speed.get_sustained_10min(), speed.get_beaufort() as f32];
+        let mut values : Vec<_> = speeds.iter().map(|&value| Some(value as f64)).collect();
+        values.push(direction.get_mean_direction().map(|value| value as f64));
+        values.push(direction.get_gust_direction().map(|value| value as f64));
+        Ok(sensor::channels(&CHANNELS, &values))
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    // Poll until ready, failing if it takes far longer than it should
+    fn wait_until(mut ready : impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !ready() {
+            assert!(Instant::now() < deadline, "Timed out waiting for the reader");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    // A line from the reader, waiting for each part of it with a timeout
+    fn read_line(fd : i32) -> Vec<u8> {
+        let mut line = Vec::new();
+        while !line.ends_with(b"\n") {
+            let mut poll_fd = libc::pollfd { fd, events : libc::POLLIN, revents : 0 };
+            assert_eq!(unsafe { libc::poll(&mut poll_fd, 1, 5000) }, 1, "Timed out waiting for a line");
+            let mut buffer = [0u8; 64];
+            let length = unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+            assert!(length > 0);
+            line.extend_from_slice(&buffer[..length as usize]);
+        }
+        line
+    }
+
+    #[test]
+    fn check_read_device() {
+        let (mut master, mut slave) = (0, 0);
+        let result = unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), std::ptr::null()) };
+        assert_eq!(result, 0);
+        let dev_name = unsafe { CStr::from_ptr(libc::ttyname(slave)) }.to_str().unwrap().to_string();
+
+        let wind = Wind::new(&dev_name, serial::Settings::new(9600, "8N2").unwrap(), Some(1),
+                             Arc::new(Mutex::new(rain::Gauge::new())), Vane::new(None));
+        let reader = wind.clone();
+        let handle = thread::spawn(move || reader.read_device());
+
+        // Writing before the reader has the device in raw mode would have the lines echoed back
+        wait_until(|| {
+            let mut tios : libc::termios = unsafe { std::mem::zeroed() };
+            assert_eq!(unsafe { libc::tcgetattr(slave, &mut tios) }, 0);
+            tios.c_lflag & (libc::ICANON | libc::ECHO) == 0
+        });
+        let lines = b"H:0:wind 2.1*61\n\xff\xfe\nW:1:12.3*78\n";
+        assert_eq!(unsafe { libc::write(master, lines.as_ptr() as *const libc::c_void, lines.len()) }, lines.len() as isize);
+
+        // The hello gets the measurement window sent back
+        assert_eq!(read_line(master), b"P:0:1*51\n");
+        wait_until(|| stats::Summary::new(&wind.speed.lock().unwrap(), stats::now()).get_max() == 12.3);
+
+        // Like a USB reset, the reader should see the device fail rather than wait forever
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+        assert!(handle.join().unwrap().is_err());
+        assert_eq!(wind.sample(stats::now() as i64).get_max(), 12.3);
+    }
+
+    #[test]
+    fn check_sensor() {
+        let mut wind = Wind::new("/dev/null", serial::Settings::new(9600, "8N1").unwrap(), None,
+                                 Arc::new(Mutex::new(rain::Gauge::new())), Vane::new(None));
+        wind.handle(Message::Wind(4.5));
+        let channels = sensor::measure(&mut wind, stats::now() as i64).unwrap();
+        assert_eq!(channels.len(), wind.get_channels().len());
+        assert_eq!(channels[0], Channel::new("max_speed", "m/s", Some(4.5)));
+        assert_eq!(channels[7], Channel::new("wind_direction", "°", None));
+        for channel in &channels {
+            assert_eq!(database::get_unit(channel.name), Some(channel.unit));
         }
     }
 }
@@ -0,0 +1,252 @@
+//!
+//! Corrections applied to readings between the drivers and storage, e.g. for a sensor that reads high in its
+//! radiation screen. The raw readings are stored alongside in columns ending _raw.
+//!
+
+use crate::Channel;
+use weather_err::{Result, WeatherError};
+
+const RAW_SUFFIX : &str = "_raw";
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Column the uncorrected readings of a channel go in
+pub fn raw_column(name : &str) -> String {
+    format!("{}{}", name, RAW_SUFFIX)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Either gain × raw + offset, or interpolation in a table of raw and true values, carrying on with the slope of the
+/// end points outside it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Correction {
+    offset : f64,
+    gain : f64,
+    table : Vec<(f64, f64)>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Correction {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(offset : f64, gain : f64) -> Self {
+        Self { offset, gain, table : Vec::new() }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Pairs of raw and true values, at least two, in order of raw value
+    pub fn with_table(table : Vec<(f64, f64)>) -> Result<Self> {
+        if table.len() < 2 {
+            return Err(WeatherError::from("A calibration table needs at least two points"));
+        }
+        if table.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(WeatherError::from("Calibration table raw values must increase"));
+        }
+        Ok(Self { offset : 0.0, gain : 1.0, table })
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Least squares gain and offset from pairs of raw and reference readings. With one pair, or readings all at the
+    /// same raw value, only the offset can be found.
+    pub fn fit(pairs : &[(f64, f64)]) -> Result<Self> {
+        if pairs.is_empty() {
+            return Err(WeatherError::from("No readings to calibrate from"));
+        }
+        let count = pairs.len() as f64;
+        let mean_raw = pairs.iter().map(|(raw, _)| raw).sum::<f64>() / count;
+        let mean_reference = pairs.iter().map(|(_, reference)| reference).sum::<f64>() / count;
+        let variance = pairs.iter().map(|(raw, _)| (raw - mean_raw).powi(2)).sum::<f64>();
+        let covariance = pairs.iter().map(|(raw, reference)| (raw - mean_raw) * (reference - mean_reference)).sum::<f64>();
+
+        let gain = if variance > f64::EPSILON { covariance / variance } else { 1.0 };
+        Ok(Self::new(mean_reference - gain * mean_raw, gain))
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_offset(&self) -> f64 {
+        self.offset
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_gain(&self) -> f64 {
+        self.gain
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn apply(&self, raw : f64) -> f64 {
+        if self.table.is_empty() {
+            return self.gain * raw + self.offset;
+        }
+        // The segment raw falls in, or the one at the nearest end
+        let upper = self.table.iter().position(|(point, _)| raw < *point).unwrap_or(self.table.len()).clamp(1, self.table.len() - 1);
+        let ((raw0, true0), (raw1, true1)) = (self.table[upper - 1], self.table[upper]);
+        true0 + (raw - raw0) * (true1 - true0) / (raw1 - raw0)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Corrections by channel name, channels without one are left alone
+#[derive(Clone, Debug, Default)]
+pub struct Calibration {
+    corrections : Vec<(String, Correction)>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Calibration {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Corrections to a node's readings by channel, from its calibration table, e.g.
+    /// temperature = { offset = -0.4 } or pressure = { table = [[950, 951.2], [1050, 1050.5]] }
+    pub fn from_config(config : &config::Config, name : &str) -> Self {
+        let mut calibration = Self::new();
+        let channels = match config.get_channel_settings(name, "calibration") {
+            Some(channels) => channels,
+            None => return calibration
+        };
+        let number = |value : &toml::Value, what : &str| value.as_float().or(value.as_integer().map(|value| value as f64))
+            .unwrap_or_else(|| panic!("calibration {} must be a number", what));
+
+        for (channel, settings) in channels {
+            let settings = settings.as_table().unwrap_or_else(|| panic!("calibration for {} must be a table", channel));
+            if let Some(key) = settings.keys().find(|key| !["offset", "gain", "table"].contains(&key.as_str())) {
+                panic!("Unknown calibration setting {} for {}, should be offset, gain or table", key, channel);
+            }
+            let offset = settings.get("offset").map_or(0.0, |offset| number(offset, "offset"));
+            let gain = settings.get("gain").map_or(1.0, |gain| number(gain, "gain"));
+            let correction = match settings.get("table") {
+                Some(_) if settings.contains_key("offset") || settings.contains_key("gain") =>
+                    panic!("calibration for {} should have a table or an offset and gain, not both", channel),
+                Some(table) => {
+                    let table = table.as_array().unwrap_or_else(|| panic!("calibration table for {} must be an array", channel));
+                    let table = table.iter().map(|entry| match entry.as_array().map(|pair| pair.as_slice()) {
+                        Some([raw, reference]) => (number(raw, "table raw value"), number(reference, "table true value")),
+                        _ => panic!("calibration table entries for {} must be [raw, true]", channel)
+                    }).collect();
+                    Correction::with_table(table)
+                        .unwrap_or_else(|error| panic!("Bad calibration table for {} {:?}", channel, error))
+                },
+                None => Correction::new(offset, gain)
+            };
+            calibration.add(channel, correction);
+        }
+        calibration
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn add(&mut self, name : &str, correction : Correction) {
+        self.corrections.push((name.to_string(), correction));
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get(&self, name : &str) -> Option<&Correction> {
+        self.corrections.iter().find(|(channel, _)| channel == name).map(|(_, correction)| correction)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// An error for a correction to a channel that isn't measured, most likely a typo
+    pub fn check(&self, channels : &[&str]) -> Result<()> {
+        match self.corrections.iter().find(|(name, _)| !channels.contains(&name.as_str())) {
+            Some((name, _)) => Err(WeatherError::from(format!("Calibration for {}, which isn't measured", name))),
+            None => Ok(())
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Columns for the raw readings of the calibrated channels
+    pub fn get_raw_columns(&self) -> Vec<String> {
+        self.corrections.iter().map(|(name, _)| raw_column(name)).collect()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// A reading corrected if its channel has a correction
+    pub fn correct(&self, name : &str, raw : f64) -> f64 {
+        self.get(name).map_or(raw, |correction| correction.apply(raw))
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Correct the channels in place, returning the raw readings by raw column for those that were calibrated
+    pub fn apply(&self, channels : &mut [Channel]) -> Vec<(String, Option<f64>)> {
+        let mut raw = Vec::new();
+        for channel in channels.iter_mut() {
+            if let Some(correction) = self.get(channel.name) {
+                raw.push((raw_column(channel.name), channel.value));
+                channel.value = channel.value.map(|value| correction.apply(value));
+            }
+        }
+        raw
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_correction() {
+        let correction = Correction::new(-0.4, 1.0);
+        assert!((correction.apply(20.0) - 19.6).abs() < 1e-9);
+
+        let table = Correction::with_table(vec![(0.0, 1.0), (10.0, 10.0), (20.0, 21.0)]).unwrap();
+        assert!((table.apply(5.0) - 5.5).abs() < 1e-9);
+        assert!((table.apply(10.0) - 10.0).abs() < 1e-9);
+        assert!((table.apply(15.0) - 15.5).abs() < 1e-9);
+        // Beyond the ends carries on with their slopes
+        assert!((table.apply(-10.0) + 8.0).abs() < 1e-9);
+        assert!((table.apply(30.0) - 32.0).abs() < 1e-9);
+
+        assert!(Correction::with_table(vec![(0.0, 1.0)]).is_err());
+        assert!(Correction::with_table(vec![(10.0, 1.0), (0.0, 2.0)]).is_err());
+    }
+
+    #[test]
+    fn check_fit() {
+        let correction = Correction::fit(&[(10.0, 9.5), (20.0, 19.7), (30.0, 29.9)]).unwrap();
+        assert!((correction.get_gain() - 1.02).abs() < 1e-9);
+        assert!((correction.get_offset() + 0.7).abs() < 1e-9);
+
+        let correction = Correction::fit(&[(20.4, 20.0), (20.4, 20.1)]).unwrap();
+        assert_eq!(correction.get_gain(), 1.0);
+        assert!((correction.get_offset() + 0.35).abs() < 1e-9);
+        assert!(Correction::fit(&[]).is_err());
+    }
+
+    #[test]
+    fn check_calibration() {
+        let mut calibration = Calibration::new();
+        calibration.add("temperature", Correction::new(-0.4, 1.0));
+        assert!(calibration.check(&["temperature", "humidity"]).is_ok());
+        assert_eq!(format!("{:?}", calibration.check(&["humidity"]).unwrap_err()),
+                   "Calibration for temperature, which isn't measured");
+        assert_eq!(calibration.get_raw_columns(), ["temperature_raw"]);
+
+        let mut channels = vec![Channel::new("temperature", "°C", Some(10.4)), Channel::new("humidity", "%", Some(50.0))];
+        let raw = calibration.apply(&mut channels);
+        assert_eq!(raw, [(String::from("temperature_raw"), Some(10.4))]);
+        assert!((channels[0].value.unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!(channels[1].value, Some(50.0));
+        assert_eq!(calibration.correct("humidity", 50.0), 50.0);
+    }
+}
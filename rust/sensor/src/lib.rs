@@ -0,0 +1,190 @@
+//!
+//! What every sensor driver can do, so a node can sample whatever sensors it has without knowing about each one
+//!
+
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+use weather_err::{Result, WeatherError};
+
+pub mod calibration;
+pub mod qc;
+
+pub use calibration::{Calibration, Correction};
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// A named value with its unit. The name is the database column it goes in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Channel {
+    pub name : &'static str,
+    pub unit : &'static str,
+    pub value : Option<f64>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Channel {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(name : &'static str, unit : &'static str, value : Option<f64>) -> Self {
+        Self { name, unit, value }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Channels from name and unit pairs and their values, in the same order
+pub fn channels(names : &[(&'static str, &'static str)], values : &[Option<f64>]) -> Vec<Channel> {
+    names.iter().zip(values).map(|(&(name, unit), &value)| Channel::new(name, unit, value)).collect()
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.value {
+            Some(value) => write!(f, "{} {:.1}{}", self.name, value, self.unit),
+            None => write!(f, "{} -", self.name)
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Measurements are started with trigger and read once ready. Sensors that measure by themselves in the
+/// background can leave trigger and is_ready as they are and summarise in read.
+pub trait Sensor {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// For logs, e.g. bme688
+    fn get_name(&self) -> &str;
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Names and units of the channels read returns, in the same order, so a table can be made before any reading
+    fn get_channels(&self) -> &[(&'static str, &'static str)];
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Start a measurement
+    fn trigger(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// How long a measurement should take after trigger
+    fn get_duration(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Whether the triggered measurement has finished, for sensors that can say
+    fn is_ready(&mut self) -> Result<bool> {
+        Ok(true)
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// The channels measured. unix_time is the tick, for sensors that summarise over the sample period.
+    fn read(&mut self, unix_time : i64) -> Result<Vec<Channel>>;
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Wait for a triggered measurement, giving up after ten times as long as it should take or a second, whichever is
+/// more
+pub fn wait_ready<S : Sensor + ?Sized>(sensor : &mut S) -> Result<()> {
+    let duration = sensor.get_duration();
+    let timeout = (duration * 10).max(Duration::from_secs(1));
+    let poll = (duration / 10).max(Duration::from_millis(1));
+    let start = Instant::now();
+
+    thread::sleep(duration);
+    while !sensor.is_ready()? {
+        if start.elapsed() > timeout {
+            return Err(WeatherError::from(format!("Timed out waiting for {}", sensor.get_name())));
+        }
+        thread::sleep(poll);
+    }
+    Ok(())
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Trigger a measurement, wait for it and read it
+pub fn measure<S : Sensor + ?Sized>(sensor : &mut S, unix_time : i64) -> Result<Vec<Channel>> {
+    sensor.trigger()?;
+    wait_ready(sensor)?;
+    sensor.read(unix_time)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fake {
+        polls : u32,
+        triggered : bool
+    }
+
+    impl Sensor for Fake {
+        fn get_name(&self) -> &str {
+            "fake"
+        }
+
+        fn get_channels(&self) -> &[(&'static str, &'static str)] {
+            &[("temperature", "°C"), ("humidity", "%")]
+        }
+
+        fn trigger(&mut self) -> Result<()> {
+            self.triggered = true;
+            Ok(())
+        }
+
+        fn get_duration(&self) -> Duration {
+            Duration::from_millis(5)
+        }
+
+        fn is_ready(&mut self) -> Result<bool> {
+            self.polls += 1;
+            Ok(self.polls > 3)
+        }
+
+        fn read(&mut self, unix_time : i64) -> Result<Vec<Channel>> {
+            Ok(channels(self.get_channels(), &[Some(unix_time as f64), None]))
+        }
+    }
+
+    struct Stuck;
+
+    impl Sensor for Stuck {
+        fn get_name(&self) -> &str {
+            "stuck"
+        }
+
+        fn get_channels(&self) -> &[(&'static str, &'static str)] {
+            &[]
+        }
+
+        fn is_ready(&mut self) -> Result<bool> {
+            Ok(false)
+        }
+
+        fn read(&mut self, _unix_time : i64) -> Result<Vec<Channel>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[test]
+    fn check_measure() {
+        let mut fake = Fake { polls : 0, triggered : false };
+        let channels = measure(&mut fake, 20).unwrap();
+        assert!(fake.triggered);
+        assert_eq!(fake.polls, 4);
+        assert_eq!(channels[0], Channel::new("temperature", "°C", Some(20.0)));
+        assert_eq!(format!("{} {}", channels[0], channels[1]), "temperature 20.0°C humidity -");
+
+        let sensor : &mut dyn Sensor = &mut Stuck;
+        assert_eq!(format!("{:?}", measure(sensor, 0).unwrap_err()), "Timed out waiting for stuck");
+    }
+}
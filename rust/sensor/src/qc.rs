@@ -0,0 +1,331 @@
+//!
+//! Quality checks on each tick's values before they are stored. A value that fails is stored as NULL, with the check
+//! it failed in a column ending _qc, 0 when it passed.
+//!
+
+use std::fmt;
+use weather_err::{Result, WeatherError};
+
+const QC_SUFFIX : &str = "_qc";
+
+// Readings that can't be more than another, checked whenever both are stored. The first fails, along with the measured
+// reading that made it too high where it is derived, e.g. dew point is only above temperature with humidity over 100%.
+const RULES : [(&str, &str, Option<&str>); 3] = [("dew_point", "temperature", Some("humidity")), ("min_speed", "ave_speed", None),
+                                                 ("ave_speed", "max_speed", None)];
+
+// Rounding allowed in the rules, dew point equals temperature when saturated
+const TOLERANCE : f64 = 0.01;
+
+// Derived quantities and what they are worked out from, they are dropped along with a failed input
+const INPUTS : [(&str, &[&str]); 8] = [
+    ("dew_point", &["temperature", "humidity"]),
+    ("frost_point", &["temperature", "humidity"]),
+    ("absolute_humidity", &["temperature", "humidity"]),
+    ("heat_index", &["temperature", "humidity"]),
+    ("humidex", &["temperature", "humidity"]),
+    ("wind_chill", &["temperature", "ave_speed"]),
+    ("apparent_temperature", &["temperature", "humidity", "ave_speed"]),
+    ("msl_pressure", &["pressure"])
+];
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Column holding the result of a channel's checks
+pub fn qc_column(name : &str) -> String {
+    format!("{}{}", name, QC_SUFFIX)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Failure {
+    /// Outside the plausible range
+    Range,
+    /// Changed too much since the last tick
+    Step,
+    /// Unchanged for too many ticks, the sensor is likely stuck
+    Flat,
+    /// Disagrees with another reading, e.g. dew point above temperature
+    Consistency
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Failure {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Stored in the _qc column
+    pub fn get_code(&self) -> u8 {
+        match self {
+            Self::Range => 1,
+            Self::Step => 2,
+            Self::Flat => 3,
+            Self::Consistency => 4
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Range => write!(f, "range"),
+            Self::Step => write!(f, "step"),
+            Self::Flat => write!(f, "flat"),
+            Self::Consistency => write!(f, "consistency")
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Checks for one channel, those left out aren't made
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+    pub min : Option<f64>,
+    pub max : Option<f64>,
+    /// Largest believable change from one tick to the next
+    pub max_step : Option<f64>,
+    /// Ticks in a row with exactly the same reading before it counts as stuck
+    pub flat_ticks : Option<u32>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// What a tick's checks found
+#[derive(Debug, PartialEq)]
+pub struct Checked {
+    /// Channels that failed and the check they failed, e.g. to count them
+    pub failures : Vec<(String, Failure)>,
+    /// Values of the _qc columns, None where there was nothing to check
+    pub columns : Vec<(String, Option<f64>)>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// What the step and flat line checks need from earlier ticks
+#[derive(Clone, Copy, Debug, Default)]
+struct History {
+    last : Option<f64>,
+    last_failed : bool,
+    repeats : u32
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+pub struct Checker {
+    limits : Vec<(String, Limits)>,
+    history : Vec<History>,
+    rules : Vec<(&'static str, &'static str, Option<&'static str>)>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Checker {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Limits by channel, and the columns stored so the rules that apply are known. Limits for a column that isn't
+    /// stored are an error, most likely a typo.
+    pub fn new(limits : Vec<(String, Limits)>, columns : &[&str]) -> Result<Self> {
+        if let Some((name, _)) = limits.iter().find(|(name, _)| !columns.contains(&name.as_str())) {
+            return Err(WeatherError::from(format!("QC limits for {}, which isn't measured", name)));
+        }
+        let rules = RULES.iter()
+            .filter(|(lower, upper, _)| columns.contains(lower) && columns.contains(upper))
+            .map(|&(lower, upper, cause)| (lower, upper, cause.filter(|cause| columns.contains(cause))))
+            .collect();
+        Ok(Self {
+            history : vec![History::default(); limits.len()],
+            limits,
+            rules
+        })
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Limits by channel from a node's qc table, e.g. temperature = { min = -30, max = 45, max_step = 5, flat_ticks = 8 }
+    pub fn from_config(config : &config::Config, name : &str, columns : &[&str]) -> Result<Self> {
+        let channels = match config.get_channel_settings(name, "qc") {
+            Some(channels) => channels,
+            None => return Self::new(Vec::new(), columns)
+        };
+        let limits = channels.iter().map(|(channel, settings)| {
+            let settings = settings.as_table().unwrap_or_else(|| panic!("qc for {} must be a table", channel));
+            let mut limits = Limits::default();
+            for (key, value) in settings {
+                let number = value.as_float().or(value.as_integer().map(|value| value as f64));
+                match (key.as_str(), number) {
+                    ("min", Some(min)) => limits.min = Some(min),
+                    ("max", Some(max)) => limits.max = Some(max),
+                    ("max_step", Some(max_step)) => limits.max_step = Some(max_step),
+                    ("flat_ticks", _) => limits.flat_ticks = match value.as_integer() {
+                        Some(ticks) if ticks > 1 => Some(ticks as u32),
+                        _ => panic!("qc flat_ticks for {} must be a whole number more than 1", channel)
+                    },
+                    ("min" | "max" | "max_step", None) => panic!("qc {} for {} must be a number", key, channel),
+                    _ => panic!("Unknown qc setting {} for {}, should be min, max, max_step or flat_ticks", key, channel)
+                }
+            }
+            (channel.clone(), limits)
+        }).collect();
+        Self::new(limits, columns)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn checked(&self) -> Vec<&str> {
+        let mut checked : Vec<&str> = self.limits.iter().map(|(name, _)| name.as_str()).collect();
+        for (lower, _, cause) in &self.rules {
+            for name in std::iter::once(lower).chain(cause) {
+                if !checked.contains(name) {
+                    checked.push(name);
+                }
+            }
+        }
+        checked
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// A _qc column for each channel with limits or a rule
+    pub fn get_qc_columns(&self) -> Vec<String> {
+        self.checked().iter().map(|name| qc_column(name)).collect()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn check_limits(limits : &Limits, history : &mut History, value : f64) -> Option<Failure> {
+        let repeats = if history.last == Some(value) { history.repeats + 1 } else { 0 };
+        let failure = if limits.min.is_some_and(|min| value < min) || limits.max.is_some_and(|max| value > max) {
+            Some(Failure::Range)
+        } else if limits.max_step.is_some_and(|max_step| !history.last_failed &&
+                                                         history.last.is_some_and(|last| (value - last).abs() > max_step)) {
+            // A spike fails once, and the next reading isn't judged against it
+            Some(Failure::Step)
+        } else if limits.flat_ticks.is_some_and(|flat_ticks| repeats + 1 >= flat_ticks) {
+            Some(Failure::Flat)
+        } else {
+            None
+        };
+        *history = History { last : Some(value), last_failed : failure.is_some(), repeats };
+        failure
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Check a tick's values, setting those that fail to None along with anything derived from them
+    pub fn check(&mut self, values : &mut [(&str, Option<f64>)]) -> Checked {
+        let mut failures : Vec<(&str, Failure)> = Vec::new();
+        let get = |values : &[(&str, Option<f64>)], name : &str|
+            values.iter().find(|(column, _)| *column == name).and_then(|(_, value)| *value);
+
+        for ((name, limits), history) in self.limits.iter().zip(self.history.iter_mut()) {
+            match get(values, name) {
+                Some(value) => if let Some(failure) = Self::check_limits(limits, history, value) {
+                    failures.push((name, failure));
+                },
+                None => *history = History::default()
+            }
+        }
+        let passed = |values : &[(&str, Option<f64>)], failures : &[(&str, Failure)], name : &str|
+            get(values, name).filter(|_| !failures.iter().any(|(failed, _)| *failed == name));
+        for (lower, upper, cause) in &self.rules {
+            if let (Some(low), Some(high)) = (passed(values, &failures, lower), passed(values, &failures, upper)) {
+                if low > high + TOLERANCE {
+                    failures.push((lower, Failure::Consistency));
+                    if let Some(cause) = cause.filter(|cause| passed(values, &failures, cause).is_some()) {
+                        failures.push((cause, Failure::Consistency));
+                    }
+                }
+            }
+        }
+
+        for (name, failure) in &failures {
+            println!("{} {:?} failed the {} check", name, get(values, name), failure);
+        }
+        for (column, value) in values.iter_mut() {
+            let failed = |name : &str| failures.iter().any(|(failed, _)| *failed == name);
+            let input_failed = INPUTS.iter().any(|(derived, inputs)| derived == column && inputs.iter().any(|input| failed(input)));
+            if failed(column) || input_failed {
+                *value = None;
+            }
+        }
+
+        let columns = self.checked().iter().map(|name| {
+            let result = match failures.iter().find(|(failed, _)| failed == name) {
+                Some((_, failure)) => Some(failure.get_code() as f64),
+                None => get(values, name).map(|_| 0.0)
+            };
+            (qc_column(name), result)
+        }).collect();
+        Checked { failures : failures.into_iter().map(|(name, failure)| (name.to_string(), failure)).collect(), columns }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> Vec<(String, Limits)> {
+        vec![(String::from("temperature"), Limits { min : Some(-30.0), max : Some(45.0), max_step : Some(5.0), flat_ticks : Some(3) })]
+    }
+
+    #[test]
+    fn check_limits() {
+        let mut checker = Checker::new(limits(), &["temperature", "humidity"]).unwrap();
+        assert_eq!(checker.get_qc_columns(), ["temperature_qc"]);
+
+        let mut tick = |value : Option<f64>| {
+            let mut values = [("temperature", value), ("humidity", Some(50.0))];
+            let checked = checker.check(&mut values);
+            (values[0].1, checked.columns[0].1)
+        };
+        assert_eq!(tick(Some(10.0)), (Some(10.0), Some(0.0)));
+        assert_eq!(tick(Some(60.0)), (None, Some(1.0)));
+        // Not judged against the failed reading
+        assert_eq!(tick(Some(10.5)), (Some(10.5), Some(0.0)));
+        assert_eq!(tick(Some(20.0)), (None, Some(2.0)));
+        assert_eq!(tick(Some(20.5)), (Some(20.5), Some(0.0)));
+        assert_eq!(tick(Some(20.5)), (Some(20.5), Some(0.0)));
+        assert_eq!(tick(Some(20.5)), (None, Some(3.0)));
+        assert_eq!(tick(None), (None, None));
+        assert_eq!(tick(Some(20.5)), (Some(20.5), Some(0.0)));
+
+        assert_eq!(format!("{:?}", Checker::new(limits(), &["humidity"]).err().unwrap()),
+                   "QC limits for temperature, which isn't measured");
+    }
+
+    #[test]
+    fn check_rules() {
+        let columns = ["temperature", "humidity", "dew_point", "heat_index", "msl_pressure"];
+        let mut checker = Checker::new(limits(), &columns).unwrap();
+        assert_eq!(checker.get_qc_columns(), ["temperature_qc", "dew_point_qc", "humidity_qc"]);
+
+        // Dew point above temperature means the humidity is over 100%, which fails too and takes heat index with it
+        let mut values = [("temperature", Some(10.0)), ("humidity", Some(105.0)), ("dew_point", Some(10.7)),
+                          ("heat_index", Some(9.5)), ("msl_pressure", Some(1010.0))];
+        let checked = checker.check(&mut values);
+        assert_eq!(values.map(|(_, value)| value), [Some(10.0), None, None, None, Some(1010.0)]);
+        assert_eq!(checked.failures, [(String::from("dew_point"), Failure::Consistency),
+                                      (String::from("humidity"), Failure::Consistency)]);
+        assert_eq!(checked.columns, [(String::from("temperature_qc"), Some(0.0)), (String::from("dew_point_qc"), Some(4.0)),
+                                     (String::from("humidity_qc"), Some(4.0))]);
+
+        // A failed temperature takes what is derived from it with it
+        let mut values = [("temperature", Some(99.0)), ("humidity", Some(50.0)), ("dew_point", Some(0.0)),
+                          ("heat_index", Some(100.0)), ("msl_pressure", Some(1010.0))];
+        let checked = checker.check(&mut values);
+        assert_eq!(values.map(|(_, value)| value), [None, Some(50.0), None, None, Some(1010.0)]);
+        assert_eq!(checked.failures, [(String::from("temperature"), Failure::Range)]);
+        assert_eq!(checked.columns[1].1, None);
+
+        // Without humidity stored, only dew point fails
+        let mut checker = Checker::new(Vec::new(), &["temperature", "dew_point"]).unwrap();
+        assert_eq!(checker.get_qc_columns(), ["dew_point_qc"]);
+        let checked = checker.check(&mut [("temperature", Some(10.0)), ("dew_point", Some(10.7))]);
+        assert_eq!(checked.failures, [(String::from("dew_point"), Failure::Consistency)]);
+    }
+}
@@ -1,10 +1,296 @@
 use i2cdev::linux::LinuxI2CDevice;
 use i2cdev::core::*;
+use sensor::{Channel, Sensor};
 use std::fmt;
+use std::time::Duration;
 use weather_err::{Result, WeatherError};
 
-const SHT31_ADDR : u16 = 0x44;
+/// ADDR pin low, 0x45 with it high
+pub const SHT31_ADDR : u16 = 0x44;
 
+const CHANNELS : [(&str, &str); 2] = [("temperature", "°C"), ("humidity", "%")];
+
+const FETCH_DATA : [u8; 2] = [0xE0, 0x00];
+const ART : [u8; 2] = [0x2B, 0x32];
+const BREAK : [u8; 2] = [0x30, 0x93];
+const READ_STATUS : [u8; 2] = [0xF3, 0x2D];
+const CLEAR_STATUS : [u8; 2] = [0x30, 0x41];
+const SOFT_RESET : [u8; 2] = [0x30, 0xA2];
+const HEATER_ON : [u8; 2] = [0x30, 0x6D];
+const HEATER_OFF : [u8; 2] = [0x30, 0x66];
+const READ_SERIAL_NUMBER : [u8; 2] = [0x37, 0x80];
+
+// Alert limit commands in the order of AlertLimits' fields, read then write
+const ALERT_LIMIT_COMMANDS : [([u8; 2], [u8; 2]); 4] = [
+    ([0xE1, 0x1F], [0x61, 0x1D]), ([0xE1, 0x14], [0x61, 0x16]), ([0xE1, 0x09], [0x61, 0x0B]), ([0xE1, 0x02], [0x61, 0x00])
+];
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Status register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Status {
+    bits : u16
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Status {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(bits : u16) -> Self {
+        Self { bits }
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn is_set(&self, bit : u8) -> bool {
+        self.bits & (1 << bit) != 0
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_alert_pending(&self) -> bool {
+        self.is_set(15)
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_heater_on(&self) -> bool {
+        self.is_set(13)
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_humidity_alert(&self) -> bool {
+        self.is_set(11)
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_temperature_alert(&self) -> bool {
+        self.is_set(10)
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Set after power up, a soft reset or a brown out, until cleared
+    pub fn get_reset_detected(&self) -> bool {
+        self.is_set(4)
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// The last command wasn't processed, e.g. it was invalid or in the wrong mode
+    pub fn get_command_failed(&self) -> bool {
+        self.is_set(1)
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// The checksum of the last write was wrong
+    pub fn get_write_crc_failed(&self) -> bool {
+        self.is_set(0)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// An error for the bits that mean something went wrong
+    pub fn check(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        if self.get_reset_detected() {
+            problems.push("reset detected");
+        }
+        if self.get_command_failed() {
+            problems.push("command failed");
+        }
+        if self.get_write_crc_failed() {
+            problems.push("command checksum failed");
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(WeatherError::from(format!("SHT31 status {:04X} {}", self.bits, problems.join(", "))))
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn update_metrics(&self) {
+        let bits = [("alert_pending", self.get_alert_pending()), ("heater_on", self.get_heater_on()),
+                    ("humidity_alert", self.get_humidity_alert()), ("temperature_alert", self.get_temperature_alert()),
+                    ("reset_detected", self.get_reset_detected()), ("command_failed", self.get_command_failed()),
+                    ("write_crc_failed", self.get_write_crc_failed())];
+        for (name, set) in bits {
+            metrics::SENSOR_STATUS.set_labelled(&["sht31", name], if set { 1.0 } else { 0.0 });
+        }
+        if self.get_reset_detected() {
+            metrics::SENSOR_RESETS.inc();
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// An alert limit. The sensor only keeps the top 7 bits of humidity and 9 of temperature, so steps of about 0.8% and 0.35C.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limit {
+    pub temperature : f32,
+    pub humidity : f32
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Limit {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn new(temperature : f32, humidity : f32) -> Self {
+        Self { temperature, humidity }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn to_word(self) -> u16 {
+        let temperature = ((self.temperature.clamp(-45.0, 130.0) + 45.0) / 175.0 * 65535.0).round() as u16;
+        let humidity = (self.humidity.clamp(0.0, 100.0) / 100.0 * 65535.0).round() as u16;
+        (humidity & 0xFE00) | (temperature >> 7)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn from_word(word : u16) -> Self {
+        let temperature = (word & 0x01FF) << 7;
+        let humidity = word & 0xFE00;
+        Self {
+            temperature : -45.0 + 175.0 * (temperature as f32) / 65535.0,
+            humidity : 100.0 * (humidity as f32) / 65535.0
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// The ALERT pin goes high when temperature or humidity go above high_set or below low_set, and low again once they are
+/// back past the clear limits. Alerts only work in periodic mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AlertLimits {
+    pub high_set : Limit,
+    pub high_clear : Limit,
+    pub low_clear : Limit,
+    pub low_set : Limit
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl AlertLimits {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Alert when the temperature falls to frost_temp, clearing a degree above. Humidity and heat never alert.
+    pub fn frost(frost_temp : f32) -> Self {
+        Self {
+            high_set : Limit::new(130.0, 100.0),
+            high_clear : Limit::new(125.0, 100.0),
+            low_clear : Limit::new(frost_temp + 1.0, 0.0),
+            low_set : Limit::new(frost_temp, 0.0)
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Higher repeatability is less noisy but takes longer, up to 15ms for a single shot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Repeatability {
+    Low,
+    Medium,
+    High
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Repeatability {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "low" => Some(Self::Low),
+            "medium" => Some(Self::Medium),
+            "high" => Some(Self::High),
+            _ => None
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Longest a single shot measurement takes, from the datasheet
+    pub fn get_duration(&self) -> Duration {
+        match self {
+            Self::Low => Duration::from_millis(5),
+            Self::Medium => Duration::from_millis(7),
+            Self::High => Duration::from_millis(16)
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Measurements per second in periodic mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rate {
+    Half,
+    One,
+    Two,
+    Four,
+    Ten
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Rate {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn from_mps(mps : f32) -> Option<Self> {
+        [(0.5, Self::Half), (1.0, Self::One), (2.0, Self::Two), (4.0, Self::Four), (10.0, Self::Ten)].into_iter()
+            .find(|(rate_mps, _)| *rate_mps == mps)
+            .map(|(_, rate)| rate)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Time between measurements in milliseconds
+    pub fn get_period_ms(&self) -> u64 {
+        match self {
+            Self::Half => 2000,
+            Self::One => 1000,
+            Self::Two => 500,
+            Self::Four => 250,
+            Self::Ten => 100
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn single_shot_command(repeatability : Repeatability, clock_stretching : bool) -> [u8; 2] {
+    match (clock_stretching, repeatability) {
+        (true, Repeatability::High) => [0x2C, 0x06],
+        (true, Repeatability::Medium) => [0x2C, 0x0D],
+        (true, Repeatability::Low) => [0x2C, 0x10],
+        (false, Repeatability::High) => [0x24, 0x00],
+        (false, Repeatability::Medium) => [0x24, 0x0B],
+        (false, Repeatability::Low) => [0x24, 0x16]
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn periodic_command(rate : Rate, repeatability : Repeatability) -> [u8; 2] {
+    let (msb, lsbs) = match rate {
+        Rate::Half => (0x20, [0x32, 0x24, 0x2F]),
+        Rate::One => (0x21, [0x30, 0x26, 0x2D]),
+        Rate::Two => (0x22, [0x36, 0x20, 0x2B]),
+        Rate::Four => (0x23, [0x34, 0x22, 0x29]),
+        Rate::Ten => (0x27, [0x37, 0x21, 0x2A])
+    };
+    let lsb = match repeatability {
+        Repeatability::High => lsbs[0],
+        Repeatability::Medium => lsbs[1],
+        Repeatability::Low => lsbs[2]
+    };
+    [msb, lsb]
+}
 
 
 //----------------------------------------------------------------------------------------------------------------------------------
@@ -43,6 +329,9 @@ impl fmt::Display for Summary{
 //----------------------------------------------------------------------------------------------------------------------------------
 pub struct Sht31 {
     dev : LinuxI2CDevice,
+    address : u16,
+    // For measurements started through the Sensor trait
+    repeatability : Repeatability
 }
 
 
@@ -52,15 +341,48 @@ impl Sht31 {
 
     //------------------------------------------------------------------------------------------------------------------------------
     pub fn new(dev_name : &str) -> Self {
+        Self::with_address(dev_name, SHT31_ADDR)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn with_address(dev_name : &str, address : u16) -> Self {
         Self {
-            dev : match LinuxI2CDevice::new(dev_name, SHT31_ADDR) {
+            dev : match LinuxI2CDevice::new(dev_name, address) {
                 Ok(dev) => dev,
-                Err(error) => panic!("Failed to open {} with address {} - {}", dev_name, SHT31_ADDR, error)
-            }
+                Err(error) => panic!("Failed to open {} with address {} - {}", dev_name, address, error)
+            },
+            address,
+            repeatability : Repeatability::High
         }
     }
 
 
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_address(&self) -> u16 {
+        self.address
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Repeatability of measurements triggered through the Sensor trait, high by default
+    pub fn set_repeatability(&mut self, repeatability : Repeatability) {
+        self.repeatability = repeatability;
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn write(&mut self, data : &[u8]) -> Result<()> {
+        Ok(self.dev.write(data).inspect_err(|_| metrics::I2C_ERRORS.inc())?)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn read(&mut self, data : &mut [u8]) -> Result<()> {
+        Ok(self.dev.read(data).inspect_err(|_| metrics::I2C_ERRORS.inc())?)
+    }
+
+
     //------------------------------------------------------------------------------------------------------------------------------
     fn crc(data : &[u8]) -> u8 {
         let mut crc : u8 = 0xff;
@@ -80,13 +402,28 @@ impl Sht31 {
 
 
     //------------------------------------------------------------------------------------------------------------------------------
-    fn process_resp(&self, resp : &[u8]) -> Result<Summary> {
+    /// The 16 bit words of a response, each followed by its CRC
+    fn check_words(resp : &[u8]) -> Result<Vec<u16>> {
+        resp.chunks(3).map(|word| {
+            if Self::crc(&word[0..2]) != word[2] {
+                metrics::CRC_FAILURES.inc();
+                return Err(WeatherError::from("Invalid Checksum"));
+            }
+            Ok(((word[0] as u16) << 8) | (word[1] as u16))
+        }).collect()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn process_resp(resp : &[u8]) -> Result<Summary> {
         let calc_crc = Self::crc(&resp[0..2]);
         if calc_crc != resp[2] {
+            metrics::CRC_FAILURES.inc();
             return Err(WeatherError::from("Invalid Checksum"));
         }
         let calc_crc = Self::crc(&resp[3..5]);
         if calc_crc != resp[5] {
+            metrics::CRC_FAILURES.inc();
             return Err(WeatherError::from("Invalid Checksum"));
         }
         let adc = ((resp[0] as u16) << 8) | (resp[1] as u16);
@@ -98,17 +435,165 @@ impl Sht31 {
 
 
     //------------------------------------------------------------------------------------------------------------------------------
+    /// Start a high repeatability measurement without clock stretching
     pub fn one_shot(&mut self) -> Result<()> {
-        self.dev.write(&[0x24, 0x00])?;
+        self.single_shot(Repeatability::High, false)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Start a measurement. With clock stretching the sensor holds the bus until the result is ready, so sample can
+    /// follow straight away. Without it wait for the measurement to finish first.
+    pub fn single_shot(&mut self, repeatability : Repeatability, clock_stretching : bool) -> Result<()> {
+        self.write(&single_shot_command(repeatability, clock_stretching))
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Measure continuously, read the latest with fetch. Stop with stop_periodic before any other command.
+    pub fn start_periodic(&mut self, rate : Rate, repeatability : Repeatability) -> Result<()> {
+        self.write(&periodic_command(rate, repeatability))
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Periodic mode at 4 measurements per second with accelerated response time
+    pub fn start_art(&mut self) -> Result<()> {
+        self.write(&ART)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Break, back to single shot mode
+    pub fn stop_periodic(&mut self) -> Result<()> {
+        self.write(&BREAK)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Read the status register, which also goes to the metrics. Not in periodic mode.
+    pub fn read_status(&mut self) -> Result<Status> {
+        self.write(&READ_STATUS)?;
+        let mut resp : [u8; 3] = [0; 3];
+        self.read(&mut resp)?;
+        if Self::crc(&resp[0..2]) != resp[2] {
+            metrics::CRC_FAILURES.inc();
+            return Err(WeatherError::from("Invalid Checksum"));
+        }
+        let status = Status::new(((resp[0] as u16) << 8) | (resp[1] as u16));
+        status.update_metrics();
+        Ok(status)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Unique serial number. Not in periodic mode.
+    pub fn read_serial_number(&mut self) -> Result<u32> {
+        self.write(&READ_SERIAL_NUMBER)?;
+        let mut resp : [u8; 6] = [0; 6];
+        self.read(&mut resp)?;
+        let words = Self::check_words(&resp)?;
+        Ok(((words[0] as u32) << 16) | (words[1] as u32))
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Set the limits for the ALERT pin. They go back to the defaults when the sensor resets.
+    pub fn set_alert_limits(&mut self, limits : &AlertLimits) -> Result<()> {
+        let words = [limits.high_set, limits.high_clear, limits.low_clear, limits.low_set].map(Limit::to_word);
+        for ((_, command), word) in ALERT_LIMIT_COMMANDS.iter().zip(words) {
+            let data = word.to_be_bytes();
+            self.write(&[command[0], command[1], data[0], data[1], Self::crc(&data)])?;
+        }
         Ok(())
     }
 
+
     //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_alert_limits(&mut self) -> Result<AlertLimits> {
+        let mut limits = [Limit::new(0.0, 0.0); 4];
+        for ((command, _), limit) in ALERT_LIMIT_COMMANDS.iter().zip(limits.iter_mut()) {
+            self.write(command)?;
+            let mut resp : [u8; 3] = [0; 3];
+            self.read(&mut resp)?;
+            *limit = Limit::from_word(Self::check_words(&resp)?[0]);
+        }
+        Ok(AlertLimits { high_set : limits[0], high_clear : limits[1], low_clear : limits[2], low_set : limits[3] })
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Clear the alert and reset detected bits
+    pub fn clear_status(&mut self) -> Result<()> {
+        self.write(&CLEAR_STATUS)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Back to power up defaults, single shot mode with the heater off. Takes up to 1.5ms.
+    pub fn soft_reset(&mut self) -> Result<()> {
+        self.write(&SOFT_RESET)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// The heater warms the sensor by a few degrees to drive off condensation. Not in periodic mode.
+    pub fn set_heater(&mut self, on : bool) -> Result<()> {
+        self.write(if on { &HEATER_ON } else { &HEATER_OFF })
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Result of a single shot measurement
     pub fn sample(&mut self) -> Result<Summary> {
-        self.dev.write(&[0xE0, 0x00])?;
         let mut resp : [u8; 6] = [0; 6];
-        self.dev.read(&mut resp)?;
-        Ok(self.process_resp(&resp)?)
+        self.read(&mut resp)?;
+        Self::process_resp(&resp)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Latest result in periodic mode, an error if there hasn't been a new measurement since the last fetch
+    pub fn fetch(&mut self) -> Result<Summary> {
+        self.write(&FETCH_DATA)?;
+        self.sample()
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Single shot measurements without clock stretching. There is no way to ask whether one has finished, a read before
+/// then is NACKed, so wait for the longest it can take.
+impl Sensor for Sht31 {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_name(&self) -> &str {
+        "sht31"
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_channels(&self) -> &[(&'static str, &'static str)] {
+        &CHANNELS
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn trigger(&mut self) -> Result<()> {
+        self.single_shot(self.repeatability, false)
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_duration(&self) -> Duration {
+        self.repeatability.get_duration()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn read(&mut self, _unix_time : i64) -> Result<Vec<Channel>> {
+        let summary = self.sample()?;
+        Ok(sensor::channels(&CHANNELS, &[Some(summary.get_temperature() as f64), Some(summary.get_humidity() as f64)]))
     }
 }
 
@@ -120,6 +605,82 @@ mod tests {
     use std::time::Duration;
 
     #[test]
+    fn check_commands() {
+        assert_eq!(single_shot_command(Repeatability::High, false), [0x24, 0x00]);
+        assert_eq!(single_shot_command(Repeatability::Low, true), [0x2C, 0x10]);
+        assert_eq!(periodic_command(Rate::Half, Repeatability::Medium), [0x20, 0x24]);
+        assert_eq!(periodic_command(Rate::Ten, Repeatability::High), [0x27, 0x37]);
+        assert_eq!(periodic_command(Rate::Four, Repeatability::Low), [0x23, 0x29]);
+
+        assert_eq!(Rate::from_mps(0.5), Some(Rate::Half));
+        assert_eq!(Rate::from_mps(3.0), None);
+        assert_eq!(Repeatability::from_name("medium"), Some(Repeatability::Medium));
+        assert_eq!(Repeatability::from_name("best"), None);
+    }
+
+    #[test]
+    fn check_status() {
+        let status = Status::new(0x8010);
+        assert!(status.get_alert_pending());
+        assert!(status.get_reset_detected());
+        assert!(!status.get_heater_on());
+        assert_eq!(format!("{:?}", status.check().unwrap_err()), "SHT31 status 8010 reset detected");
+
+        let status = Status::new(0x2003);
+        assert!(status.get_heater_on());
+        assert!(status.get_command_failed());
+        assert!(status.get_write_crc_failed());
+        assert!(format!("{:?}", status.check().unwrap_err()).ends_with("command failed, command checksum failed"));
+
+        assert!(Status::new(0x2C00).check().is_ok());
+    }
+
+    #[test]
+    fn check_limits() {
+        // The datasheet's default high set and low set limits
+        let high_set = Limit::from_word(0xCD33);
+        assert!((high_set.temperature - 60.0).abs() < 0.35);
+        assert!((high_set.humidity - 80.0).abs() < 0.5);
+        let low_set = Limit::from_word(0x3466);
+        assert!((low_set.temperature + 10.0).abs() < 0.35);
+        assert!((low_set.humidity - 20.0).abs() < 0.5);
+
+        assert_eq!(Limit::new(60.0, 80.0).to_word(), 0xCD33);
+        let frost = AlertLimits::frost(2.0);
+        let low_set = Limit::from_word(frost.low_set.to_word());
+        assert!((low_set.temperature - 2.0).abs() < 0.7);
+        assert_eq!(low_set.humidity, 0.0);
+        assert_eq!(frost.high_set.to_word(), 0xFFFF);
+    }
+
+    #[test]
+    fn check_words() {
+        let resp = [0xBE, 0xEF, 0x92, 0x12, 0x34, Sht31::crc(&[0x12, 0x34])];
+        assert_eq!(Sht31::check_words(&resp).unwrap(), [0xBEEF, 0x1234]);
+        assert!(Sht31::check_words(&[0xBE, 0xEF, 0x93]).is_err());
+    }
+
+    #[test]
+    fn check_process_resp() {
+        let sensor_data = |adc : u16| {
+            let bytes = adc.to_be_bytes();
+            [bytes[0], bytes[1], Sht31::crc(&bytes)]
+        };
+        // The datasheet's CRC example
+        assert_eq!(Sht31::crc(&[0xBE, 0xEF]), 0x92);
+
+        let resp = [sensor_data(0x6666), sensor_data(0x8000)].concat();
+        let summary = Sht31::process_resp(&resp).unwrap();
+        assert!((summary.get_temperature() - 25.0).abs() < 0.01);
+        assert!((summary.get_humidity() - 50.0).abs() < 0.01);
+
+        let mut bad = resp.clone();
+        bad[5] ^= 1;
+        assert!(Sht31::process_resp(&bad).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs a SHT31 on /dev/i2c-sht31"]
     fn read_temperature() {
         let mut sensor = Sht31::new("/dev/i2c-sht31");
 
@@ -0,0 +1,74 @@
+//!
+//! station calibrate <channel> <file> fits a gain and offset to pairs of raw and reference readings, one pair a line
+//! separated by a comma or spaces. Raw readings are the uncorrected ones, from the _raw column once a channel is
+//! calibrated.
+//!
+
+use sensor::Correction;
+use weather_err::{Result, WeatherError};
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Pairs of raw and reference readings, skipping blank lines and # comments
+fn parse_pairs(text : &str) -> Result<Vec<(f64, f64)>> {
+    let mut pairs = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields : Vec<_> = line.split(|c : char| c == ',' || c.is_whitespace()).filter(|field| !field.is_empty()).collect();
+        match fields.as_slice() {
+            [raw, reference] => match (raw.parse(), reference.parse()) {
+                (Ok(raw), Ok(reference)) => pairs.push((raw, reference)),
+                _ => return Err(WeatherError::from(format!("Line {} has a reading that isn't a number", number + 1)))
+            },
+            _ => return Err(WeatherError::from(format!("Line {} should be a raw and a reference reading", number + 1)))
+        }
+    }
+    Ok(pairs)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Root mean square difference from the reference readings after correcting
+fn rms_error(pairs : &[(f64, f64)], correction : &Correction) -> f64 {
+    let sum = pairs.iter().map(|(raw, reference)| (correction.apply(*raw) - reference).powi(2)).sum::<f64>();
+    (sum / pairs.len() as f64).sqrt()
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Print the fit and the line for the node's calibration table
+pub fn run(args : &[String]) -> Result<()> {
+    let (channel, file_name) = match args {
+        [channel, file_name] => (channel, file_name),
+        _ => return Err(WeatherError::from("Usage: station calibrate <channel> <file of raw and reference readings>"))
+    };
+    let pairs = parse_pairs(&std::fs::read_to_string(file_name)?)?;
+    let correction = Correction::fit(&pairs)?;
+
+    println!("{} pairs, RMS error {:.3} before and {:.3} after", pairs.len(), rms_error(&pairs, &Correction::new(0.0, 1.0)),
+             rms_error(&pairs, &correction));
+    println!("Add to the node's calibration table in weather.toml");
+    println!("{} = {{ offset = {:.4}, gain = {:.6} }}", channel, correction.get_offset(), correction.get_gain());
+    Ok(())
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_pairs() {
+        let pairs = parse_pairs("# raw, reference\n20.4, 20.0\n\n25.5 25.1  # afternoon\n").unwrap();
+        assert_eq!(pairs, [(20.4, 20.0), (25.5, 25.1)]);
+        assert!(rms_error(&pairs, &Correction::fit(&pairs).unwrap()) < 1e-9);
+        assert!((rms_error(&pairs, &Correction::new(0.0, 1.0)) - 0.4).abs() < 1e-9);
+
+        assert_eq!(format!("{:?}", parse_pairs("20.4\n").unwrap_err()), "Line 1 should be a raw and a reference reading");
+        assert_eq!(format!("{:?}", parse_pairs("1, 2\nwarm, 20\n").unwrap_err()), "Line 2 has a reading that isn't a number");
+    }
+}
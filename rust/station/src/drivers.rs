@@ -0,0 +1,178 @@
+//!
+//! Sensors from the station's sensors list, set up from their options
+//!
+
+use std::thread;
+use std::time::Duration;
+use config::SensorConfig;
+use sensor::{Channel, Sensor};
+use weather_err::{Result, WeatherError};
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// A sensor whose channels go in columns starting with its name, e.g. ground_temperature, so there can be two of a kind.
+/// Channels are named by &'static str like the other columns, so the names made at start up are leaked.
+struct Named {
+    name : String,
+    sensor : Box<dyn Sensor>,
+    channels : Vec<(&'static str, &'static str)>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Named {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn new(name : &str, sensor : Box<dyn Sensor>) -> Result<Self> {
+        if !name.starts_with(|c : char| c.is_ascii_lowercase()) ||
+           !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+            return Err(WeatherError::from(format!("Sensor name {} should be lower case letters, digits and _", name)));
+        }
+        let channels = sensor.get_channels().iter()
+            .map(|(channel, unit)| (&*Box::leak(format!("{}_{}", name, channel).into_boxed_str()), *unit))
+            .collect();
+        Ok(Self { name : name.to_string(), sensor, channels })
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Sensor for Named {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_channels(&self) -> &[(&'static str, &'static str)] {
+        &self.channels
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn trigger(&mut self) -> Result<()> {
+        self.sensor.trigger()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn get_duration(&self) -> Duration {
+        self.sensor.get_duration()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn is_ready(&mut self) -> Result<bool> {
+        self.sensor.is_ready()
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn read(&mut self, unix_time : i64) -> Result<Vec<Channel>> {
+        let channels = self.sensor.read(unix_time)?;
+        Ok(self.channels.iter().zip(channels).map(|(&(name, unit), channel)| Channel::new(name, unit, channel.value)).collect())
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Options are oversampling (1, 2, 4, 8 or 16 for all three readings, default 16), iir_filter and compensation, as for
+/// the indoor node
+fn create_bme688(config : &SensorConfig) -> Result<Box<dyn Sensor>> {
+    let mut sensor = bme688::Bme688::with_address(&config.dev, config.address.unwrap_or(bme688::BME688_ADDR));
+    let variant = sensor.init()?;
+    println!("Found a {} on {}", variant, config.dev);
+
+    let oversampling = config.get_integer("oversampling").unwrap_or(16) as u8;
+    sensor.set_humdity_oversampling(oversampling);
+    sensor.set_pressure_oversampling(oversampling);
+    sensor.set_temperature_oversampling(oversampling);
+    sensor.set_filter(config.get_integer("iir_filter").unwrap_or(0) as u8);
+
+    let compensation = config.get_str("compensation").unwrap_or("float");
+    sensor.set_compensation(bme688::Compensation::from_name(compensation)
+        .ok_or(WeatherError::from(format!("compensation {} should be float or integer", compensation)))?);
+    Ok(Box::new(sensor))
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// The option is repeatability, low, medium or high (the default)
+fn create_sht31(config : &SensorConfig) -> Result<Box<dyn Sensor>> {
+    let mut sensor = sht31::Sht31::with_address(&config.dev, config.address.unwrap_or(sht31::SHT31_ADDR));
+
+    let repeatability = config.get_str("repeatability").unwrap_or("high");
+    sensor.set_repeatability(sht31::Repeatability::from_name(repeatability)
+        .ok_or(WeatherError::from(format!("repeatability {} should be low, medium or high", repeatability)))?);
+
+    // It could still be in periodic mode from before, when it ignores single shots
+    sensor.soft_reset()?;
+    thread::sleep(Duration::from_millis(2));
+    println!("SHT31 at {:#x} on {} has serial number {:08x}", sensor.get_address(), config.dev, sensor.read_serial_number()?);
+    Ok(Box::new(sensor))
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn create_bh1750(config : &SensorConfig) -> Result<Box<dyn Sensor>> {
+    let mut sensor = bh1750::Bh1750::with_address(&config.dev, config.address.unwrap_or(bh1750::BH1750_ADDR));
+    sensor.init()?;
+    Ok(Box::new(sensor))
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// The sensor a sensors list entry describes, ready to trigger. With a name option its columns start with the name.
+pub fn create(config : &SensorConfig) -> Result<Box<dyn Sensor>> {
+    let sensor = match config.kind.as_str() {
+        "bme688" => create_bme688(config)?,
+        "sht31" => create_sht31(config)?,
+        "bh1750" => create_bh1750(config)?,
+        // The Arduino also counts rain, so wind needs the rain gauge, which is still private to the outdoor node
+        "wind" => return Err(WeatherError::from("Wind and rain are read by the outdoor node, station can't read them yet")),
+        kind => return Err(WeatherError::from(format!("Unknown sensor type {}, should be bme688, sht31 or bh1750", kind)))
+    };
+    match config.get_str("name") {
+        Some(name) => Ok(Box::new(Named::new(name, sensor)?)),
+        None => Ok(sensor)
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fake;
+
+    impl Sensor for Fake {
+        fn get_name(&self) -> &str {
+            "sht31"
+        }
+
+        fn get_channels(&self) -> &[(&'static str, &'static str)] {
+            &[("temperature", "°C"), ("humidity", "%")]
+        }
+
+        fn read(&mut self, _unix_time : i64) -> Result<Vec<Channel>> {
+            Ok(sensor::channels(self.get_channels(), &[Some(12.5), None]))
+        }
+    }
+
+    #[test]
+    fn check_named() {
+        let mut named = Named::new("ground", Box::new(Fake)).unwrap();
+        assert_eq!(named.get_name(), "ground");
+        assert_eq!(named.get_channels(), [("ground_temperature", "°C"), ("ground_humidity", "%")]);
+        assert_eq!(sensor::measure(&mut named, 0).unwrap(), [Channel::new("ground_temperature", "°C", Some(12.5)),
+                                                             Channel::new("ground_humidity", "%", None)]);
+
+        for name in ["Ground", "2nd", "ground level", ""] {
+            assert_eq!(format!("{:?}", Named::new(name, Box::new(Fake)).err().unwrap()),
+                       format!("Sensor name {} should be lower case letters, digits and _", name));
+        }
+    }
+}
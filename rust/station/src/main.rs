@@ -0,0 +1,169 @@
+//!
+//! A node that reads whatever sensors its section of weather.toml lists, e.g. station greenhouse.
+//! station calibrate works out calibration coefficients instead.
+//!
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use listener::Listener;
+use sensor::{Calibration, Channel, Sensor};
+use weather_err::Result;
+
+mod calibrate;
+mod drivers;
+mod schema;
+
+type Connection = Arc<Mutex<sqlite::Connection>>;
+
+// Section used when none is given
+const DEFAULT_NAME : &str = "station";
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn wait_tick(ticker : &clock::Clock) {
+    let delay_secs = ticker.secs_to_next_tick();
+    thread::sleep(Duration::from_secs(delay_secs.into()));
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn create_sensors(config : &config::Config, name : &str) -> Result<Vec<Box<dyn Sensor>>> {
+    config.get_sensors(name).iter().map(|sensor_config| {
+        println!("Reading {} on {}", sensor_config.kind, sensor_config.dev);
+        drivers::create(sensor_config)
+    }).collect()
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn create_db_connection(config : &config::Config, name : &str, columns : &[&str]) -> (Connection, String) {
+
+    let (db_file, db_table) = config.get_database(name);
+
+    println!("Opening database {}", db_file);
+    let db_connection = Arc::new(Mutex::new(sqlite::open(db_file).unwrap()));
+
+    println!("Creating/using db table {} with {}", db_table, columns.join(", "));
+    {
+        let conn = db_connection.lock().unwrap();
+        database::create_table(&conn, db_table, columns).unwrap();
+    }
+    (db_connection, String::from(db_table))
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn launch_listener(config : &config::Config, db_connection : Connection) {
+    let mut listener = Listener::new(config.get_port(), db_connection);
+
+    listener.start();
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn launch_metrics(config : &config::Config, name : &str) {
+    if let Some(port) = config.get_metrics_port(name) {
+        metrics::Exporter::new(port).start();
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Latest readings that passed their checks, labelled by sensor, channel and unit, and the checks that failed
+fn update_metrics(unix_time : i64, sensors : &[Box<dyn Sensor>], values : &[(&str, Option<f64>)],
+                  failures : &[(String, sensor::qc::Failure)]) {
+    metrics::LAST_TICK.set(unix_time as f64);
+    for sensor in sensors {
+        for (name, unit) in sensor.get_channels() {
+            let value = values.iter().find(|(column, _)| column == name).and_then(|(_, value)| *value);
+            metrics::READING.set_labelled(&[sensor.get_name(), name, unit], value.unwrap_or(f64::NAN));
+        }
+    }
+    for (name, failure) in failures {
+        metrics::QC_FAILURES.inc_labelled(&[name, &failure.to_string()]);
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Measure with each sensor in turn, correcting the readings and keeping the raw readings of calibrated channels.
+/// A sensor that fails leaves its channels empty rather than losing the row.
+fn read_sensors(sensors : &mut [Box<dyn Sensor>], calibration : &Calibration, unix_time : i64)
+    -> (Vec<Channel>, Vec<(String, Option<f64>)>) {
+    let mut readings = Vec::new();
+    let mut raw = Vec::new();
+    for sensor in sensors.iter_mut() {
+        match sensor::measure(sensor.as_mut(), unix_time) {
+            Ok(mut channels) => {
+                raw.extend(calibration.apply(&mut channels));
+                readings.extend(channels);
+            },
+            Err(error) => {
+                println!("Failed to read {} {:?}", sensor.get_name(), error);
+                let mut channels = sensor::channels(sensor.get_channels(), &vec![None; sensor.get_channels().len()]);
+                raw.extend(calibration.apply(&mut channels));
+                readings.extend(channels);
+            }
+        }
+    }
+    (readings, raw)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+fn main() {
+
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "calibrate") {
+        if let Err(error) = calibrate::run(&args[1..]) {
+            println!("{:?}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let name = args.first().cloned().unwrap_or(String::from(DEFAULT_NAME));
+
+    let config = config::Config::new();
+
+    let mut sensors = create_sensors(&config, &name).unwrap();
+
+    let channels : Vec<_> = sensors.iter().map(|sensor| sensor.get_channels()).collect();
+    let mut columns = schema::get_columns(&channels).unwrap();
+
+    let calibration = Calibration::from_config(&config, &name);
+    calibration.check(&columns).unwrap();
+    let mut checker = sensor::qc::Checker::from_config(&config, &name, &columns).unwrap();
+    let mut extra_columns = calibration.get_raw_columns();
+    extra_columns.extend(checker.get_qc_columns());
+    columns.extend(extra_columns.iter().map(|column| column.as_str()));
+
+    let (db_connection, db_table) = create_db_connection(&config, &name, &columns);
+
+    let ticker = clock::Clock::new(config.get_sample_period() * 60);
+
+    launch_listener(&config, db_connection.clone());
+
+    launch_metrics(&config, &name);
+
+    loop {
+        wait_tick(&ticker);
+        println!("Tick");
+        let unix_time = ticker.get_nearest_tick();
+
+        let (readings, raw) = read_sensors(&mut sensors, &calibration, unix_time);
+        let derived = schema::derive(&readings, config.get_altitude(), config.get_latitude());
+        println!("{}", readings.iter().map(|channel| channel.to_string()).collect::<Vec<_>>().join(" "));
+
+        let mut values : Vec<_> = readings.iter().map(|channel| (channel.name, channel.value)).collect();
+        values.extend(derived);
+        let checked = checker.check(&mut values);
+        update_metrics(unix_time, &sensors, &values, &checked.failures);
+        values.extend(raw.iter().chain(&checked.columns).map(|(column, value)| (column.as_str(), *value)));
+        {
+            let conn = db_connection.lock().unwrap();
+            database::insert(&conn, &db_table, unix_time, &values).unwrap();
+        }
+    }
+}
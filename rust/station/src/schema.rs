@@ -0,0 +1,112 @@
+//!
+//! The station's table follows from its sensors' channels, with the derived quantities they make possible
+//!
+
+use sensor::Channel;
+use weather_err::{Result, WeatherError};
+
+// Derived from temperature and humidity, and those that need a wind speed too
+const HUMIDITY_DERIVED : [&str; 5] = ["dew_point", "frost_point", "absolute_humidity", "heat_index", "humidex"];
+const WIND_DERIVED : [&str; 2] = ["wind_chill", "apparent_temperature"];
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Derived columns that the measured columns allow
+fn derived_columns(measured : &[&'static str]) -> Vec<&'static str> {
+    let mut columns = Vec::new();
+    if measured.contains(&"pressure") {
+        columns.push("msl_pressure");
+    }
+    if measured.contains(&"temperature") && measured.contains(&"humidity") {
+        columns.extend(HUMIDITY_DERIVED);
+        if measured.contains(&"ave_speed") {
+            columns.extend(WIND_DERIVED);
+        }
+    }
+    columns
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Every sensor's channels in the order listed, then the derived columns. Two sensors can't measure the same thing
+/// unless one is named, as their readings would go in the same column. Only unnamed sensors' readings are derived from.
+pub fn get_columns(channels : &[&[(&'static str, &'static str)]]) -> Result<Vec<&'static str>> {
+    let mut columns : Vec<&'static str> = Vec::new();
+    for (name, _) in channels.iter().flat_map(|channels| channels.iter()) {
+        if columns.contains(name) {
+            return Err(WeatherError::from(format!("More than one sensor measures {}, name all but one", name)));
+        }
+        columns.push(name);
+    }
+    columns.extend(derived_columns(&columns));
+    Ok(columns)
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Values of the derived columns from a tick's readings. Sea level pressure is reduced with the station's own
+/// temperature, if it has one.
+pub fn derive(readings : &[Channel], altitude : f32, latitude : Option<f32>) -> Vec<(&'static str, Option<f64>)> {
+    let get = |name| readings.iter().find(|channel| channel.name == name).and_then(|channel| channel.value)
+        .map(|value| value as f32);
+    let measured : Vec<_> = readings.iter().map(|channel| channel.name).collect();
+    let columns = derived_columns(&measured);
+    let (temperature, humidity) = (get("temperature"), get("humidity"));
+
+    let mut values = Vec::new();
+    if columns.contains(&"msl_pressure") {
+        let msl_pressure = get("pressure").map(|pressure| meteo::msl_pressure(pressure, altitude, temperature, latitude));
+        values.push(("msl_pressure", msl_pressure.map(|value| value as f64)));
+    }
+    let derived = match (temperature, humidity) {
+        (Some(temperature), Some(humidity)) => Some(meteo::Derived::new(temperature, humidity, get("ave_speed"))),
+        _ => None
+    };
+    for column in columns.iter().filter(|column| **column != "msl_pressure") {
+        let value = derived.as_ref()
+            .and_then(|derived| derived.values().iter().find(|(name, _)| name == column).and_then(|(_, value)| *value));
+        values.push((*column, value.map(|value| value as f64)));
+    }
+    values
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    const SHT31 : [(&str, &str); 2] = [("temperature", "°C"), ("humidity", "%")];
+    const BME688 : [(&str, &str); 3] = [("temperature", "°C"), ("humidity", "%"), ("pressure", "hPa")];
+    const BH1750 : [(&str, &str); 2] = [("illuminance", "lx"), ("solar", "W/m²")];
+
+    #[test]
+    fn check_columns() {
+        assert_eq!(get_columns(&[&BH1750]).unwrap(), ["illuminance", "solar"]);
+        assert_eq!(get_columns(&[&SHT31, &BH1750]).unwrap(),
+                   ["temperature", "humidity", "illuminance", "solar", "dew_point", "frost_point", "absolute_humidity",
+                    "heat_index", "humidex"]);
+        assert_eq!(get_columns(&[&BME688]).unwrap()[..4], ["temperature", "humidity", "pressure", "msl_pressure"]);
+        assert_eq!(format!("{:?}", get_columns(&[&SHT31, &BME688]).unwrap_err()),
+                   "More than one sensor measures temperature, name all but one");
+        assert_eq!(get_columns(&[&SHT31, &[("ground_temperature", "°C"), ("ground_humidity", "%")]]).unwrap()[..4],
+                   ["temperature", "humidity", "ground_temperature", "ground_humidity"]);
+    }
+
+    #[test]
+    fn check_derive() {
+        let readings = sensor::channels(&BME688, &[Some(20.0), Some(50.0), Some(1000.0)]);
+        let values = derive(&readings, 0.0, None);
+        assert_eq!(values.len(), 6);
+        assert_eq!(values[0], ("msl_pressure", Some(1000.0)));
+        assert_eq!(values[1].0, "dew_point");
+        assert_relative_eq!(values[1].1.unwrap(), 9.3, epsilon = 0.1);
+
+        // A failed read still fills the row
+        let readings = sensor::channels(&SHT31, &[None, None]);
+        let values = derive(&readings, 120.0, None);
+        assert_eq!(values.len(), 5);
+        assert!(values.iter().all(|(_, value)| value.is_none()));
+    }
+}
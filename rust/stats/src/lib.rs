@@ -0,0 +1,231 @@
+//!
+//! Running statistics for a sensor channel, without keeping the readings
+//!
+
+pub mod quantile;
+
+use std::fmt;
+use quantile::Quantile;
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+/// Count, mean and variance (Welford's method), the extremes with when they happened, and optionally streaming
+/// percentiles. T is whatever the channel uses for a timestamp, e.g. unix time.
+#[derive(Clone)]
+pub struct Accumulator<T : Copy> {
+    count : u64,
+    mean : f64,
+    sum_squares : f64,
+    min : Option<(T, f64)>,
+    max : Option<(T, f64)>,
+    quantiles : Vec<Quantile>
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl<T : Copy> Default for Accumulator<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl<T : Copy> Accumulator<T> {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub const fn new() -> Self {
+        Self {
+            count : 0,
+            mean : 0.0,
+            sum_squares : 0.0,
+            min : None,
+            max : None,
+            quantiles : Vec::new()
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Also estimate the given percentiles, e.g. &[50.0, 95.0]
+    pub fn with_percentiles(percentiles : &[f64]) -> Self {
+        Self {
+            quantiles : percentiles.iter().map(|percentile| Quantile::new(percentile / 100.0)).collect(),
+            ..Self::new()
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Values that aren't finite are ignored
+    pub fn add(&mut self, time : T, value : f64) {
+        if !value.is_finite() {
+            return;
+        }
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.sum_squares += delta * (value - self.mean);
+
+        if self.min.is_none_or(|(_, min)| value < min) {
+            self.min = Some((time, value));
+        }
+        if self.max.is_none_or(|(_, max)| value > max) {
+            self.max = Some((time, value));
+        }
+        for quantile in &mut self.quantiles {
+            quantile.add(value);
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Start again, keeping the same percentiles
+    pub fn reset(&mut self) {
+        *self = Self {
+            quantiles : self.quantiles.iter().map(|quantile| Quantile::new(quantile.get_p())).collect(),
+            ..Self::new()
+        };
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_count(&self) -> u64 {
+        self.count
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_mean(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.mean)
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Sample variance, None with fewer than two values
+    pub fn get_variance(&self) -> Option<f64> {
+        (self.count > 1).then(|| self.sum_squares / (self.count - 1) as f64)
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_std_dev(&self) -> Option<f64> {
+        self.get_variance().map(f64::sqrt)
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Lowest value and when it was first seen
+    pub fn get_min(&self) -> Option<(T, f64)> {
+        self.min
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Highest value and when it was first seen
+    pub fn get_max(&self) -> Option<(T, f64)> {
+        self.max
+    }
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Estimate of one of the percentiles given to with_percentiles
+    pub fn get_percentile(&self, percentile : f64) -> Option<f64> {
+        self.quantiles.iter()
+            .find(|quantile| (quantile.get_p() * 100.0 - percentile).abs() < 1e-9)
+            .and_then(|quantile| quantile.get_estimate())
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl<T : Copy> fmt::Display for Accumulator<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.min, self.get_mean(), self.max) {
+            (Some((_, min)), Some(mean), Some((_, max))) =>
+                write!(f, "({:.1} - {:.1} - {:.1}) sd {:.2} n {}", min, mean, max, self.get_std_dev().unwrap_or(0.0), self.count),
+            _ => write!(f, "no values")
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use proptest::prelude::*;
+
+    #[test]
+    fn check_accumulator() {
+        let mut acc = Accumulator::new();
+        assert!(acc.get_mean().is_none());
+        assert_eq!(format!("{}", acc), "no values");
+
+        // Falling then rising, each of which the old else if got wrong
+        for (time, value) in [(10, 5.0), (11, 2.0), (12, 9.0), (13, 4.0), (14, f64::NAN)] {
+            acc.add(time, value);
+        }
+        assert_eq!(acc.get_count(), 4);
+        assert_eq!(acc.get_min(), Some((11, 2.0)));
+        assert_eq!(acc.get_max(), Some((12, 9.0)));
+        assert_relative_eq!(acc.get_mean().unwrap(), 5.0);
+        assert_relative_eq!(acc.get_variance().unwrap(), 26.0 / 3.0);
+        assert_eq!(format!("{}", acc), "(2.0 - 5.0 - 9.0) sd 2.94 n 4");
+
+        acc.reset();
+        assert_eq!(acc.get_count(), 0);
+        assert!(acc.get_max().is_none());
+    }
+
+    #[test]
+    fn check_percentiles() {
+        let mut acc = Accumulator::with_percentiles(&[50.0, 90.0]);
+        for value in 1..=9 {
+            acc.add((), value as f64);
+        }
+        assert!(acc.get_percentile(95.0).is_none());
+        assert_relative_eq!(acc.get_percentile(50.0).unwrap(), 5.0);
+
+        acc.reset();
+        assert!(acc.get_percentile(50.0).is_none());
+        acc.add((), 1.0);
+        assert_eq!(acc.get_percentile(90.0), Some(1.0));
+    }
+
+    proptest! {
+        #[test]
+        fn check_matches_two_pass(values in prop::collection::vec(-1000.0..1000.0f64, 1..200)) {
+            let mut acc = Accumulator::with_percentiles(&[50.0]);
+            for (time, value) in values.iter().enumerate() {
+                acc.add(time, *value);
+            }
+            let count = values.len() as f64;
+            let mean = values.iter().sum::<f64>() / count;
+
+            prop_assert_eq!(acc.get_count(), values.len() as u64);
+            prop_assert!((acc.get_mean().unwrap() - mean).abs() < 1e-9);
+            if values.len() > 1 {
+                let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (count - 1.0);
+                prop_assert!((acc.get_variance().unwrap() - variance).abs() <= 1e-6 * variance.max(1.0));
+            }
+        }
+
+        #[test]
+        fn check_extremes(values in prop::collection::vec(-1000.0..1000.0f64, 1..200)) {
+            let mut acc = Accumulator::with_percentiles(&[5.0, 50.0, 95.0]);
+            for (time, value) in values.iter().enumerate() {
+                acc.add(time, *value);
+            }
+            let (min_time, min) = acc.get_min().unwrap();
+            let (max_time, max) = acc.get_max().unwrap();
+
+            // Times are those of the first occurrence
+            prop_assert_eq!(values.iter().position(|value| *value == min), Some(min_time));
+            prop_assert_eq!(values.iter().position(|value| *value == max), Some(max_time));
+            prop_assert!(values.iter().all(|value| min <= *value && *value <= max));
+
+            prop_assert!(min <= acc.get_mean().unwrap() && acc.get_mean().unwrap() <= max);
+            for percentile in [5.0, 50.0, 95.0] {
+                let estimate = acc.get_percentile(percentile).unwrap();
+                prop_assert!(min <= estimate && estimate <= max);
+            }
+            prop_assert!(acc.get_percentile(5.0) <= acc.get_percentile(95.0));
+        }
+    }
+}
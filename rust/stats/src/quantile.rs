@@ -0,0 +1,152 @@
+//!
+//! Streaming quantile estimate using the P² algorithm (Jain and Chlamtac, 1985), which keeps five markers
+//! rather than every value
+//!
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[derive(Clone, Debug)]
+pub struct Quantile {
+    p : f64,
+    count : u64,
+    heights : [f64; 5],
+    positions : [f64; 5],
+    desired : [f64; 5],
+    increments : [f64; 5]
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+impl Quantile {
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// p is the quantile wanted, from 0 to 1, e.g. 0.95 for the 95th percentile
+    pub fn new(p : f64) -> Self {
+        assert!((0.0..=1.0).contains(&p), "Quantile {} must be 0 to 1", p);
+        Self {
+            p,
+            count : 0,
+            heights : [0.0; 5],
+            positions : [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired : [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments : [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0]
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn get_p(&self) -> f64 {
+        self.p
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    pub fn add(&mut self, value : f64) {
+        // The first five values are the markers
+        if self.count < 5 {
+            self.heights[self.count as usize] = value;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+
+        let cell = if value < self.heights[0] {
+            self.heights[0] = value;
+            0
+        } else if value >= self.heights[4] {
+            self.heights[4] = value;
+            3
+        } else {
+            (0..4).rev().find(|&i| value >= self.heights[i]).unwrap_or(0)
+        };
+
+        for position in &mut self.positions[cell + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments) {
+            *desired += increment;
+        }
+
+        for i in 1..4 {
+            let offset = self.desired[i] - self.positions[i];
+            if (offset >= 1.0 && self.positions[i + 1] - self.positions[i] > 1.0) ||
+               (offset <= -1.0 && self.positions[i - 1] - self.positions[i] < -1.0) {
+                let step = offset.signum();
+                let height = self.parabolic(i, step);
+                self.heights[i] = if self.heights[i - 1] < height && height < self.heights[i + 1] {
+                    height
+                } else {
+                    self.linear(i, step)
+                };
+                self.positions[i] += step;
+            }
+        }
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn parabolic(&self, i : usize, step : f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        q[i] + step / (n[i + 1] - n[i - 1]) *
+            ((n[i] - n[i - 1] + step) * (q[i + 1] - q[i]) / (n[i + 1] - n[i]) +
+             (n[i + 1] - n[i] - step) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    fn linear(&self, i : usize, step : f64) -> f64 {
+        let j = if step > 0.0 { i + 1 } else { i - 1 };
+        self.heights[i] + step * (self.heights[j] - self.heights[i]) / (self.positions[j] - self.positions[i])
+    }
+
+
+    //------------------------------------------------------------------------------------------------------------------------------
+    /// Estimate of the quantile, exact while there are five values or fewer. None until there is a value.
+    pub fn get_estimate(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            count if count < 5 => {
+                let mut values = self.heights[..count as usize].to_vec();
+                values.sort_by(f64::total_cmp);
+                Some(values[(self.p * (count - 1) as f64).round() as usize])
+            },
+            _ => Some(self.heights[2])
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn check_few_values() {
+        let mut median = Quantile::new(0.5);
+        assert!(median.get_estimate().is_none());
+        median.add(3.0);
+        assert_eq!(median.get_estimate(), Some(3.0));
+        median.add(1.0);
+        median.add(2.0);
+        assert_eq!(median.get_estimate(), Some(2.0));
+    }
+
+    #[test]
+    fn check_estimate() {
+        // Evenly spread values in a scrambled order, 0 to 999
+        let mut median = Quantile::new(0.5);
+        let mut p95 = Quantile::new(0.95);
+        for i in 0..1000u64 {
+            let value = ((i * 617) % 1000) as f64;
+            median.add(value);
+            p95.add(value);
+        }
+        assert_abs_diff_eq!(median.get_estimate().unwrap(), 499.5, epsilon = 10.0);
+        assert_abs_diff_eq!(p95.get_estimate().unwrap(), 949.5, epsilon = 10.0);
+    }
+}
@@ -1,7 +1,6 @@
 use std::fmt;
 use tokio::io;
 use i2cdev::linux::LinuxI2CError;
-use sqlite;
 use std::num::{ParseIntError, ParseFloatError};
 use std::ffi::NulError;
 
@@ -33,6 +32,16 @@ impl From<&str> for WeatherError {
 }
 
 
+//----------------------------------------------------------------------------------------------------------------------------------
+impl From<String> for WeatherError {
+    fn from(error : String) -> Self {
+        Self {
+            error
+        }
+    }
+}
+
+
 //----------------------------------------------------------------------------------------------------------------------------------
 impl fmt::Debug for WeatherError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -4,25 +4,118 @@ import socket
 import os
 import pwd
 import grp
+import json
+import time
+import tomllib
 
 
 SOCK_NAME = "/run/lighttpd/scgi_app"
 SOCK_UID = 33
 SOCK_GID = 33
 
+CONFIG_FILE = os.environ.get("WEATHER_CONFIG", "weather.toml")
+LISTENER_TIMEOUT = 5
+
+# Codes in the _qc columns, 0 means the value passed its checks
+QC_CHECKS = {1: "range", 2: "step", 3: "flat", 4: "consistency"}
+
+def read_config():
+    try:
+        with open(CONFIG_FILE, "rb") as config_file:
+            return tomllib.load(config_file)
+    except OSError:
+        return {}
+
+def query_listener(host, port, command):
+    """Send a command to a node's listener, returns the response as a list of (name, value) pairs"""
+    with socket.create_connection((host, port), timeout=LISTENER_TIMEOUT) as sock:
+        sock.sendall(command.encode("ascii") + b"\n")
+        stream = sock.makefile("r", encoding="utf-8")
+        pairs = []
+        for line in stream:
+            line = line.strip()
+            if not line:
+                break
+            if line.startswith("Error"):
+                raise RuntimeError(line)
+            name, _, value = line.partition("=")
+            pairs.append((name.strip(), value.strip()))
+        return pairs
+
+def get_forecast(config):
+    """Pressure tendency and Zambretti forecast from the indoor node"""
+    host = config["indoor"]["host"]
+    port = config["common"]["port"]
+    forecast = {}
+    for name, value in query_listener(host, port, "forecast"):
+        if name in ("unix_time", "characteristic"):
+            forecast[name] = int(value)
+        elif name == "pressure_change":
+            forecast[name] = float(value)
+        else:
+            forecast[name] = value
+    return forecast
+
+def get_latest(config):
+    """Latest row from each node, with the check each failed value failed by column. Failed values are stored as
+    NULL, so only show up in failed."""
+    port = config["common"]["port"]
+    since = int(time.time()) - 2 * 60 * config["common"]["sample_period_in_mins"]
+    latest = {}
+    for node in config.get("collector", {}).get("nodes", ["indoor", "outdoor"]):
+        host = config.get(node, {}).get("host")
+        if host is None:
+            continue
+        try:
+            pairs = query_listener(host, port, str(since))
+        except (OSError, RuntimeError) as error:
+            latest[node] = {"error": str(error)}
+            continue
+        row = {"error": "No readings"}
+        for name, value in pairs:
+            if name == "unix_time":
+                row = {"unix_time": int(value), "values": {}, "failed": {}}
+            elif name.endswith("_qc"):
+                code = int(float(value))
+                if code:
+                    row["failed"][name[:-3]] = QC_CHECKS.get(code, "unknown")
+            elif not name.endswith("_raw"):
+                row["values"][name] = float(value)
+        latest[node] = row
+    return latest
+
+def response(status, content_type, body):
+    header = "Status: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n"
+    body = body.encode("utf-8")
+    return header.format(status, content_type, len(body)).encode("ascii") + body
+
+def handle_request(config, request):
+    path = request.split("?")[0]
+    if path == "/forecast":
+        try:
+            return response("200 OK", "application/json", json.dumps(get_forecast(config)))
+        except (OSError, KeyError, RuntimeError, ValueError) as error:
+            return response("503 Service Unavailable", "application/json", json.dumps({"error": str(error)}))
+    if path == "/latest":
+        try:
+            return response("200 OK", "application/json", json.dumps(get_latest(config)))
+        except (KeyError, ValueError) as error:
+            return response("503 Service Unavailable", "application/json", json.dumps({"error": str(error)}))
+    return response("404 Not Found", "text/plain", "Not found\n")
+
 def get_uid_and_gid(uid_name='http', gid_name='http'):
     uid = pwd.getpwnam(uid_name).pw_uid
     gid = grp.getgrnam(gid_name).gr_gid
     return uid, gid
 
-def create_socket():
-    if os.path.exists(SOCK_NAME):
-        os.remove(SOCK_NAME)
+def create_socket(sock_name):
+    if os.path.exists(sock_name):
+        os.remove(sock_name)
 
     server = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
-    server.bind(SOCK_NAME)
+    server.bind(sock_name)
     uid, gid = get_uid_and_gid()
-    os.chown(SOCK_NAME, uid, gid)
+    os.chown(sock_name, uid, gid)
 
     if os.getuid() == 0:
         # Remove group privileges
@@ -37,7 +130,8 @@ def create_socket():
     return server
 
 def run():
-    server = create_socket()
+    config = read_config()
+    server = create_socket(config.get("scgi", {}).get("sock_name", SOCK_NAME))
     server.listen()
 
     while True:
@@ -73,5 +167,8 @@ def run():
                 idx += 2
             print(hdr_dict)
             request = hdr_dict[b'REQUEST_URI'].decode("ascii")
+            conn.sendall(handle_request(config, request))
+            break
+        conn.close()
 
 